@@ -1,5 +1,4 @@
 extern crate lua_patterns;
-use lua_patterns::errors::PatternError;
 
 fn main() {
    let bad = [
@@ -11,14 +10,11 @@ fn main() {
     ("frodo (1) (2(3)%2)%1","invalid capture index %2"),
     ];
 
-    fn error(s: &str) -> PatternError {
-            PatternError(s.into())
-    }
-
     for p in bad.iter() {
         let res = lua_patterns::LuaPattern::new_try(p.0);
         if let Err(e) = res {
-            assert_eq!(e, error(p.1));
+            assert_eq!(e.message(), p.1);
+            println!("{}\n{}\n{}", p.0, e.caret().unwrap(), e);
         } else {
             println!("'{}' was fine",p.0);
         }
@@ -9,7 +9,7 @@ use std::fs::File;
 use std::io::prelude::*;
 
 fn main() {
-    let file = env::args().skip(1).next().expect("provide a binary file");
+    let file = env::args().nth(1).expect("provide a binary file");
     let mut f = File::open(&file).expect("can't open file");
     let mut buf = Vec::new();
     f.read_to_end(&mut buf).expect("can't read file");
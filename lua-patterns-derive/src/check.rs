@@ -0,0 +1,121 @@
+// Pattern validation for `lua_pat!`, following `str_check` in lua-patterns,
+// which cannot be used directly since it depends on this crate.
+// `same_checks_as_new_try` in lua-patterns' tests/macros.rs checks that
+// the two agree.
+
+const L_ESC: u8 = b'%';
+const LUA_MAXCAPTURES: usize = 32;
+
+type Result<T> = std::result::Result<T,String>;
+
+fn error<T>(msg: &str) -> Result<T> {
+    Err(msg.into())
+}
+
+// where a set starting at `i` ends
+fn set_end(p: &[u8], i: usize) -> Result<usize> {
+    let mut j = i + 1;
+    if p.get(j) == Some(&b'^') {
+        j += 1;
+    }
+    loop { /* look for a ']' */
+        if j >= p.len() {
+            return error("malformed pattern (missing ']')");
+        }
+        let c = p[j];
+        j += 1;
+        if c == L_ESC && j < p.len() {
+            j += 1; /* skip escapes (e.g. '%]') */
+        }
+        if p.get(j) == Some(&b']') {
+            return Ok(j + 1);
+        }
+    }
+}
+
+/// Check a pattern, giving the same messages as `LuaPattern::new_try`
+pub fn str_check(p: &[u8]) -> Result<()> {
+    let p = if p.first() == Some(&b'^') {&p[1..]} else {p};
+    // whether each capture has been closed
+    let mut closed: Vec<bool> = Vec::new();
+    let mut open = Vec::new();
+    let mut i = 0;
+    while i < p.len() {
+        match p[i] {
+            L_ESC => {
+                let c = match p.get(i + 1) {
+                    Some(&c) => c,
+                    None => return error("malformed pattern (ends with '%')")
+                };
+                i += 2;
+                match c {
+                    b'b' => {
+                        if i + 2 > p.len() {
+                            return error("malformed pattern (missing arguments to '%b')");
+                        }
+                        i += 2;
+                    },
+                    b'f' if p.get(i) != Some(&b'[') => {
+                        return error("missing '[' after '%f' in pattern");
+                    },
+                    b'0' ..= b'9' => {
+                        let l = (c - b'0') as usize;
+                        if l == 0 || l > closed.len() || ! closed[l - 1] {
+                            return error(&format!("invalid capture index %{}", l));
+                        }
+                    },
+                    _ => {}
+                }
+            },
+            b'[' => i = set_end(p, i)?,
+            b'(' => {
+                if closed.len() >= LUA_MAXCAPTURES {
+                    return error("too many captures");
+                }
+                if p.get(i + 1) == Some(&b')') { // position capture
+                    closed.push(true);
+                    i += 2;
+                } else {
+                    open.push(closed.len());
+                    closed.push(false);
+                    i += 1;
+                }
+            },
+            b')' => {
+                match open.pop() {
+                    Some(level) => closed[level] = true,
+                    None => return error("no open capture")
+                }
+                i += 1;
+            },
+            _ => i += 1
+        }
+    }
+    if ! open.is_empty() {
+        return error("unfinished capture");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_patterns() {
+        let bad = [
+            ("bonzo %","malformed pattern (ends with '%')"),
+            ("bonzo (dog%(","unfinished capture"),
+            ("alles [%a%[","malformed pattern (missing ']')"),
+            ("(1) (2(3)%2)%1","invalid capture index %2"),
+            ("x)","no open capture"),
+            ("%b(","malformed pattern (missing arguments to '%b')"),
+            ("%fx","missing '[' after '%f' in pattern"),
+        ];
+        for p in bad.iter() {
+            assert_eq!(str_check(p.0.as_bytes()), Err(p.1.to_string()));
+        }
+        assert_eq!(str_check(b"^(%d+)-(%d+)%1[]%]]"), Ok(()));
+        assert_eq!(str_check("(%a)".repeat(33).as_bytes()), Err("too many captures".to_string()));
+    }
+}
@@ -0,0 +1,114 @@
+//! `#[derive(FromCaptures)]` for [lua-patterns](https://docs.rs/lua-patterns).
+//!
+//! Use it through the `derive` feature of `lua-patterns`, which re-exports it.
+//! Each field is converted from a capture using `FromCapture`; the first
+//! field comes from capture 1 and so on, unless it has a `#[capture(n)]`
+//! attribute. Capture 0 is the whole match.
+//!
+//! `lua_pat!` is re-exported through the `macros` feature.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use std::collections::HashSet;
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use syn::{Data, DeriveInput, Error, Fields, Lifetime, LitInt, LitStr};
+
+mod check;
+
+/// Implement `FromCaptures` for a struct, converting each field from a capture
+#[proc_macro_derive(FromCaptures, attributes(capture))]
+pub fn derive_from_captures(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into()
+    }
+}
+
+/// A pattern checked at compile time, as a lazily created `&'static LuaPatternOwned`
+#[proc_macro]
+pub fn lua_pat(input: TokenStream) -> TokenStream {
+    let lit = syn::parse_macro_input!(input as LitStr);
+    if let Err(msg) = check::str_check(lit.value().as_bytes()) {
+        // not `to_compile_error`, which refers to `::core` and so fails in 2015 edition crates
+        let msg = format!("bad pattern: {}", msg);
+        return quote_spanned!(lit.span()=> compile_error!(#msg)).into();
+    }
+    let tokens = quote! {
+        {
+            static PATTERN: ::std::sync::OnceLock<::lua_patterns::LuaPatternOwned> = ::std::sync::OnceLock::new();
+            PATTERN.get_or_init(|| ::lua_patterns::LuaPatternOwned::new(#lit).unwrap())
+        }
+    };
+    tokens.into()
+}
+
+// the capture index for each field
+fn capture_indices(fields: &Fields) -> syn::Result<Vec<usize>> {
+    let mut indices = Vec::new();
+    let mut used = HashSet::new();
+    for (i, field) in fields.iter().enumerate() {
+        let mut index = i + 1;
+        for attr in &field.attrs {
+            if attr.path().is_ident("capture") {
+                index = attr.parse_args::<LitInt>()?.base10_parse()?;
+            }
+        }
+        if ! used.insert(index) {
+            return Err(Error::new_spanned(field, format!("capture {} is used twice", index)));
+        }
+        indices.push(index);
+    }
+    Ok(indices)
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let fields = match input.data {
+        Data::Struct(ref data) => &data.fields,
+        _ => return Err(Error::new_spanned(name, "FromCaptures can only be derived for structs"))
+    };
+    let generics = &input.generics;
+    let mut lifetimes = generics.lifetimes();
+    let lt = match lifetimes.next() {
+        Some(param) => param.lifetime.clone(),
+        None => Lifetime::new("'__text", Span::call_site())
+    };
+    if lifetimes.next().is_some() || generics.type_params().next().is_some() || generics.const_params().next().is_some() {
+        return Err(Error::new_spanned(generics, "FromCaptures structs can only have a single lifetime parameter"));
+    }
+
+    let indices = capture_indices(fields)?;
+    let max_index = indices.iter().cloned().max().unwrap_or(0);
+    let values: Vec<_> = fields.iter().zip(&indices).map(|(field, &i)| {
+        let ty = &field.ty;
+        quote! {
+            <#ty as ::lua_patterns::FromCapture<#lt>>::from_capture(cc.get(#i))?
+        }
+    }).collect();
+    let value = match *fields {
+        Fields::Named(_) => {
+            let names = fields.iter().map(|field| field.ident.as_ref().unwrap());
+            quote! { #name { #(#names: #values),* } }
+        },
+        Fields::Unnamed(_) => quote! { #name ( #(#values),* ) },
+        Fields::Unit => quote! { #name }
+    };
+    let (_, ty_generics, where_clause) = generics.split_for_impl();
+
+    Ok(quote! {
+        impl <#lt> ::lua_patterns::FromCaptures<#lt> for #name #ty_generics #where_clause {
+            fn from_captures(cc: &::lua_patterns::Captures<'_,#lt,'_>) -> ::std::option::Option<Self> {
+                if cc.num_matches() <= #max_index {
+                    return ::std::option::Option::None;
+                }
+                ::std::option::Option::Some(#value)
+            }
+        }
+    })
+}
@@ -0,0 +1,266 @@
+//! An opt-in extension allowing alternatives separated by `|`.
+//!
+//! Lua patterns have no alternation, so `LuaAlternation` splits the pattern
+//! at each top-level `|` and tries each alternative as an ordinary pattern.
+//! The match starting furthest to the left wins, and if several start at the
+//! same place the first alternative wins. Captures are numbered across all
+//! the alternatives, so captures from alternatives that did not match are empty.
+//!
+//! `|` inside a capture or a set, or escaped as `%|`, is not special.
+//! With `set_longest`, the longest match at the leftmost place wins instead,
+//! which is what lexers usually want.
+//!
+//! ```
+//! use lua_patterns::LuaAlternation;
+//! let mut m = LuaAlternation::new("(%d+) apples|(%a+) pears");
+//! assert_eq!(m.captures("many pears"), &["many pears", "", "many"]);
+//! assert_eq!(m.which(), 1);
+//! assert!(m.matches("I have 2 apples and 3 pears"));
+//! assert_eq!(m.range(), 7..15);
+//! ```
+
+use std::ops;
+use errors::*;
+use parse;
+use LuaPattern;
+
+/// A pattern with alternatives separated by `|`
+pub struct LuaAlternation<'a> {
+    alts: Vec<LuaPattern<'a>>,
+    // number of captures in each alternative
+    num_captures: Vec<usize>,
+    which: Option<usize>,
+    longest: bool,
+}
+
+// split a pattern at top-level '|', following the rules for escapes and sets
+fn split_alternatives(patt: &[u8]) -> Vec<&[u8]> {
+    let mut res = Vec::new();
+    let mut start = 0;
+    let mut depth = 0;
+    let mut i = 0;
+    while i < patt.len() {
+        match patt[i] {
+            b'%' => {
+                i += if patt.get(i + 1) == Some(&b'b') {4} else {2};
+                continue;
+            },
+            b'[' => {
+                // the first character of a set (after any '^') is always a member
+                i += 1;
+                if patt.get(i) == Some(&b'^') {
+                    i += 1;
+                }
+                i += 1;
+                while i < patt.len() && patt[i] != b']' {
+                    i += if patt[i] == b'%' {2} else {1};
+                }
+            },
+            b'(' => depth += 1,
+            b')' => depth -= 1,
+            b'|' if depth == 0 => {
+                res.push(&patt[start..i]);
+                start = i + 1;
+            },
+            _ => {}
+        }
+        i += 1;
+    }
+    res.push(&patt[start.min(patt.len())..]);
+    res
+}
+
+impl <'a> LuaAlternation<'a> {
+    /// Maybe create a new alternation from a slice of bytes.
+    /// Each alternative must be a valid Lua pattern.
+    pub fn from_bytes_try(bytes: &'a [u8]) -> Result<LuaAlternation<'a>,PatternError> {
+        let mut alts = Vec::new();
+        let mut num_captures = Vec::new();
+        for alt in split_alternatives(bytes) {
+            alts.push(LuaPattern::from_bytes_try(alt)?);
+            num_captures.push(parse::parse(alt)?.num_captures);
+        }
+        Ok(LuaAlternation{alts, num_captures, which: None, longest: false})
+    }
+
+    /// Maybe create a new alternation from a string
+    pub fn new_try(patt: &'a str) -> Result<LuaAlternation<'a>,PatternError> {
+        LuaAlternation::from_bytes_try(patt.as_bytes())
+    }
+
+    /// Create a new alternation from a string, panicking if bad
+    pub fn new(patt: &'a str) -> LuaAlternation<'a> {
+        LuaAlternation::new_try(patt).expect("bad pattern")
+    }
+
+    /// Create a new alternation from a slice of bytes, panicking if bad
+    pub fn from_bytes(bytes: &'a [u8]) -> LuaAlternation<'a> {
+        LuaAlternation::from_bytes_try(bytes).expect("bad pattern")
+    }
+
+    /// Choose the longest match rather than the first alternative
+    /// when several alternatives match at the same place.
+    ///
+    /// Ties between matches of the same length still go to the
+    /// alternative which comes first.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaAlternation::new("=|==|%a+|if");
+    /// assert_eq!(m.match_maybe("== x"), Some("="));
+    /// m.set_longest(true);
+    /// assert_eq!(m.match_maybe("== x"), Some("=="));
+    /// assert!(m.matches("if"));
+    /// assert_eq!(m.which(), 2);
+    /// ```
+    pub fn set_longest(&mut self, longest: bool) {
+        self.longest = longest;
+    }
+
+    /// Match a slice of bytes with the alternatives
+    pub fn matches_bytes(&mut self, s: &[u8]) -> bool {
+        self.which = None;
+        let (mut start, mut end) = (usize::MAX, 0);
+        for (i, alt) in self.alts.iter_mut().enumerate() {
+            if ! alt.matches_bytes(s) {
+                continue;
+            }
+            let r = alt.range();
+            if r.start < start || (self.longest && r.start == start && r.end > end) {
+                start = r.start;
+                end = r.end;
+                self.which = Some(i);
+            }
+        }
+        self.which.is_some()
+    }
+
+    /// Match a string with the alternatives
+    pub fn matches(&mut self, text: &str) -> bool {
+        self.matches_bytes(text.as_bytes())
+    }
+
+    /// Match a string, returning first capture if successful
+    ///
+    /// As with `LuaPattern::match_maybe`, this is the whole match if
+    /// the alternative which matched has no captures.
+    ///
+    /// Panics if a capture splits a UTF-8 character, as `.` can; see the crate docs.
+    pub fn match_maybe<'t>(&mut self, text: &'t str) -> Option<&'t str> {
+        if self.matches(text) {
+            let alt = &self.alts[self.which()];
+            Some(&text[alt.first_capture()])
+        } else {
+            None
+        }
+    }
+
+    /// Index of the alternative which matched last time
+    ///
+    /// Panics if there was no match.
+    pub fn which(&self) -> usize {
+        self.which.expect("no match")
+    }
+
+    /// Total number of captures in all the alternatives
+    pub fn num_captures(&self) -> usize {
+        self.num_captures.iter().sum()
+    }
+
+    /// The full match (same as `capture(0)`)
+    pub fn range(&self) -> ops::Range<usize> {
+        self.capture(0)
+    }
+
+    /// Get the nth capture of the match, numbering across all alternatives.
+    ///
+    /// Captures belonging to alternatives which did not match are `0..0`.
+    pub fn capture(&self, i: usize) -> ops::Range<usize> {
+        let which = self.which();
+        if i == 0 {
+            return self.alts[which].range();
+        }
+        let offset: usize = self.num_captures[..which].iter().sum();
+        if i > offset && i <= offset + self.num_captures[which] {
+            self.alts[which].capture(i - offset)
+        } else {
+            0..0
+        }
+    }
+
+    /// Match and collect all captures as a vector of string slices
+    ///
+    /// Captures from alternatives which did not match are empty strings.
+    ///
+    /// Panics if a capture splits a UTF-8 character, as `.` can; see the crate docs.
+    pub fn captures<'b>(&mut self, text: &'b str) -> Vec<&'b str> {
+        if ! self.matches(text) {
+            return Vec::new();
+        }
+        (0..=self.num_captures()).map(|i| &text[self.capture(i)]).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splitting() {
+        let split = |p: &'static str| -> Vec<&'static str> {
+            split_alternatives(p.as_bytes()).into_iter()
+                .map(|b| ::std::str::from_utf8(b).unwrap()).collect()
+        };
+        assert_eq!(split("a|b|c"), &["a","b","c"]);
+        assert_eq!(split("(a|b)|[|]|[^|]|%||%b||x"), &["(a|b)","[|]","[^|]","%|","%b||x"]);
+        assert_eq!(split("[]|]|x"), &["[]|]","x"]);
+        assert_eq!(split("a|"), &["a",""]);
+        assert_eq!(split("abc"), &["abc"]);
+    }
+
+    #[test]
+    fn leftmost_first() {
+        let mut m = LuaAlternation::new("cat|dog");
+        assert!(m.matches("hotdog and cat"));
+        assert_eq!(m.range(), 3..6);
+        assert_eq!(m.which(), 1);
+        // at the same position, the first alternative wins
+        let mut m = LuaAlternation::new("%a+|%a");
+        assert_eq!(m.match_maybe("  word"), Some("word"));
+        let mut m = LuaAlternation::new("%a|%a+");
+        assert_eq!(m.match_maybe("  word"), Some("w"));
+        assert!(! m.matches("123"));
+        assert_eq!(m.captures("123"), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn leftmost_longest() {
+        let mut m = LuaAlternation::new("%a|%a+|%a%a+");
+        m.set_longest(true);
+        assert_eq!(m.match_maybe("  word"), Some("word"));
+        assert_eq!(m.which(), 1);
+        // the leftmost match still wins over a longer one further on
+        let mut m = LuaAlternation::new("a|%d+");
+        m.set_longest(true);
+        assert_eq!(m.match_maybe("1a 2345"), Some("1"));
+        assert_eq!(m.match_maybe("a 2345"), Some("a"));
+    }
+
+    #[test]
+    fn capture_numbering() {
+        let mut m = LuaAlternation::new("(%a+)=(%d+)|(%d+)()");
+        assert_eq!(m.num_captures(), 4);
+        assert!(m.matches("x 42"));
+        assert_eq!(m.capture(1), 0..0);
+        assert_eq!(m.capture(3), 2..4);
+        assert_eq!(m.capture(4), 4..4);
+        assert_eq!(m.captures("a=1"), &["a=1","a","1","",""]);
+        assert_eq!(m.match_maybe("a=1"), Some("a"));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(LuaAlternation::new_try("(a|b").is_err());
+        assert!(LuaAlternation::new_try("a|(b").is_err());
+        assert!(LuaAlternation::new_try("a|[b").is_err());
+    }
+}
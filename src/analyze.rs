@@ -0,0 +1,279 @@
+//! Looking for common mistakes in patterns.
+//!
+//! Lua patterns are terse, and some mistakes are easy to make: forgetting
+//! to escape a `.` or `-`, putting `$` in the middle, or writing a set which
+//! matches nothing. These are all valid patterns, so `LuaPattern::new_try`
+//! will not complain.
+//!
+//! ```
+//! use lua_patterns::analyze::*;
+//! let warnings = analyze("www.lua.org").unwrap();
+//! assert_eq!(warnings.len(), 2);
+//! assert_eq!(warnings[0].kind, WarningKind::LooksLiteral(b'.'));
+//! assert_eq!(warnings[0].span, 3..4);
+//! ```
+
+use std::fmt;
+use std::ops;
+use errors::*;
+use parse::*;
+
+/// A possible problem with a pattern
+#[derive(Debug,Clone,PartialEq)]
+pub struct Warning {
+    pub kind: WarningKind,
+    /// the bytes of the pattern concerned
+    pub span: ops::Range<usize>,
+}
+
+/// The kinds of warning
+#[derive(Debug,Clone,PartialEq)]
+pub enum WarningKind {
+    /// a magic character (`.` or `-`) between letters or digits,
+    /// which was probably meant literally
+    LooksLiteral(u8),
+    /// `^` not at the start, or `$` not at the end, which are matched literally
+    MisplacedAnchor(u8),
+    /// a range in a set like `[z-a]` which contains nothing
+    EmptyRange(u8,u8),
+    /// a set or frontier which cannot match any byte
+    NeverMatches,
+    /// adjacent repetitions which can match the same bytes, or a leading
+    /// `.*` in an unanchored pattern, which can take quadratic time
+    Backtracking,
+    /// a capture which is not used by the replacement string
+    UnusedCapture(usize),
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            WarningKind::LooksLiteral(c) =>
+                write!(f, "'{}' is magic here; use '%{}' to match it literally", c as char, c as char),
+            WarningKind::MisplacedAnchor(c) =>
+                write!(f, "'{}' is only an anchor at the {} of the pattern", c as char,
+                    if c == b'^' {"start"} else {"end"}),
+            WarningKind::EmptyRange(lo, hi) =>
+                write!(f, "range '{}-{}' is empty", lo as char, hi as char),
+            WarningKind::NeverMatches =>
+                write!(f, "set can never match"),
+            WarningKind::Backtracking =>
+                write!(f, "repetition may backtrack excessively"),
+            WarningKind::UnusedCapture(index) =>
+                write!(f, "capture {} is not used by the replacement", index),
+        }?;
+        write!(f, " (at {}..{})", self.span.start, self.span.end)
+    }
+}
+
+/// Look for likely mistakes in a pattern.
+///
+/// Returns an error if the pattern is invalid.
+pub fn analyze<P: AsRef<[u8]>>(patt: P) -> Result<Vec<Warning>,PatternError> {
+    let patt = parse(patt)?;
+    let mut res = Vec::new();
+    let mut leaves = Vec::new();
+    flatten(&patt.items, &mut leaves);
+    check_literals(&leaves, &mut res);
+    for item in &leaves {
+        match item.kind {
+            ItemKind::Single(Single::Set(ref set), _) => check_set(set, item, &mut res),
+            ItemKind::Frontier(ref set) => check_set(set, item, &mut res),
+            _ => {}
+        }
+    }
+    check_backtracking(&patt, &leaves, &mut res);
+    res.sort_by_key(|w| w.span.start);
+    Ok(res)
+}
+
+/// Like `analyze`, but also check that the captures are used
+/// by a `gsub` replacement string.
+///
+/// ```
+/// use lua_patterns::analyze::*;
+/// let warnings = analyze_gsub("(%a+)=(%a+)", "%2").unwrap();
+/// assert_eq!(warnings[0].kind, WarningKind::UnusedCapture(1));
+/// ```
+pub fn analyze_gsub<P: AsRef<[u8]>>(patt: P, repl: &str) -> Result<Vec<Warning>,PatternError> {
+    let patt = patt.as_ref();
+    let mut res = analyze(patt)?;
+    let mut used = [false; 10];
+    let mut iter = repl.bytes();
+    while let Some(c) = iter.next() {
+        if c == b'%' {
+            if let Some(d @ b'0' ..= b'9') = iter.next() {
+                used[(d - b'0') as usize] = true;
+            }
+        }
+    }
+    let mut captures = Vec::new();
+    captures_of(&parse(patt)?.items, &mut captures);
+    for (index, span) in captures {
+        if index < used.len() && ! used[index] {
+            res.push(Warning{kind: WarningKind::UnusedCapture(index), span});
+        }
+    }
+    res.sort_by_key(|w| w.span.start);
+    Ok(res)
+}
+
+// the items in order, looking inside captures.
+// Position captures match nothing, so they are left out
+fn flatten<'a>(items: &'a [Item], res: &mut Vec<&'a Item>) {
+    for item in items {
+        match item.kind {
+            ItemKind::Capture(ref cap) => flatten(&cap.items, res),
+            ItemKind::Position(_) => {},
+            _ => res.push(item)
+        }
+    }
+}
+
+fn captures_of(items: &[Item], res: &mut Vec<(usize,ops::Range<usize>)>) {
+    for item in items {
+        match item.kind {
+            ItemKind::Capture(ref cap) => {
+                res.push((cap.index, item.span.clone()));
+                captures_of(&cap.items, res);
+            },
+            ItemKind::Position(index) => res.push((index, item.span.clone())),
+            _ => {}
+        }
+    }
+}
+
+// a literal letter or digit, written without escape
+fn alnum(item: Option<&&Item>) -> bool {
+    match item.map(|item| &item.kind) {
+        Some(&ItemKind::Single(Single::Char(c), _)) => c.is_ascii_alphanumeric(),
+        _ => false
+    }
+}
+
+fn check_literals(leaves: &[&Item], res: &mut Vec<Warning>) {
+    for (i, item) in leaves.iter().enumerate() {
+        let (single, q) = match item.kind {
+            ItemKind::Single(ref single, q) => (single, q),
+            _ => continue
+        };
+        let unescaped = item.span.len() == 1;
+        match *single {
+            Single::Any if q == Quantifier::One
+                && i > 0 && alnum(leaves.get(i - 1)) && alnum(leaves.get(i + 1)) => {
+                res.push(Warning{kind: WarningKind::LooksLiteral(b'.'), span: item.span.clone()});
+            },
+            Single::Char(c) if c.is_ascii_alphanumeric() && q == Quantifier::ZeroOrMoreLazy
+                && alnum(leaves.get(i + 1)) => {
+                let end = item.span.end;
+                res.push(Warning{kind: WarningKind::LooksLiteral(b'-'), span: end - 1..end});
+            },
+            Single::Char(c) if unescaped && (c == b'^' || c == b'$') => {
+                res.push(Warning{kind: WarningKind::MisplacedAnchor(c), span: item.span.clone()});
+            },
+            _ => {}
+        }
+    }
+}
+
+fn check_set(set: &Set, item: &Item, res: &mut Vec<Warning>) {
+    for member in &set.items {
+        if let SetItem::Range(lo, hi) = *member {
+            if lo > hi {
+                res.push(Warning{kind: WarningKind::EmptyRange(lo, hi), span: item.span.clone()});
+            }
+        }
+    }
+    if ! (0..=255).any(|c| set.matches(c)) {
+        res.push(Warning{kind: WarningKind::NeverMatches, span: item.span.clone()});
+    }
+}
+
+fn repeated(item: &Item) -> Option<&Single> {
+    match item.kind {
+        ItemKind::Single(ref single, Quantifier::ZeroOrMore)
+        | ItemKind::Single(ref single, Quantifier::OneOrMore)
+        | ItemKind::Single(ref single, Quantifier::ZeroOrMoreLazy) => Some(single),
+        _ => None
+    }
+}
+
+fn check_backtracking(patt: &Pattern, leaves: &[&Item], res: &mut Vec<Warning>) {
+    if ! patt.anchored {
+        if let Some(item) = leaves.first() {
+            if repeated(item) == Some(&Single::Any) {
+                res.push(Warning{kind: WarningKind::Backtracking, span: item.span.clone()});
+            }
+        }
+    }
+    for pair in leaves.windows(2) {
+        if let (Some(a), Some(b)) = (repeated(pair[0]), repeated(pair[1])) {
+            if (0..=255).any(|c| a.matches(c) && b.matches(c)) {
+                res.push(Warning{kind: WarningKind::Backtracking, span: pair[0].span.start..pair[1].span.end});
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(patt: &str) -> Vec<WarningKind> {
+        analyze(patt).unwrap().into_iter().map(|w| w.kind).collect()
+    }
+
+    #[test]
+    fn clean_patterns() {
+        for patt in &["^(%a+)%s*=%s*(%d+)$", "%S+", "[%w_]+", "a%.b", "colou?r", "%b()", "%f[%w]%w+"] {
+            assert_eq!(kinds(patt), vec![], "{}", patt);
+        }
+    }
+
+    #[test]
+    fn looks_literal() {
+        assert_eq!(kinds("www.lua.org"), vec![WarningKind::LooksLiteral(b'.'), WarningKind::LooksLiteral(b'.')]);
+        let w = analyze("x-ray").unwrap();
+        assert_eq!(w[0].kind, WarningKind::LooksLiteral(b'-'));
+        assert_eq!(w[0].span, 1..2);
+    }
+
+    #[test]
+    fn anchors() {
+        assert_eq!(kinds("a$b"), vec![WarningKind::MisplacedAnchor(b'$')]);
+        assert_eq!(kinds("a^b"), vec![WarningKind::MisplacedAnchor(b'^')]);
+        assert_eq!(kinds("a%$b"), vec![]);
+    }
+
+    #[test]
+    fn sets() {
+        assert_eq!(kinds("[z-a]"), vec![WarningKind::EmptyRange(b'z', b'a'), WarningKind::NeverMatches]);
+        assert_eq!(kinds("[^%s%S]"), vec![WarningKind::NeverMatches]);
+        assert_eq!(kinds("%f[^%c%g ]"), vec![]);
+    }
+
+    #[test]
+    fn backtracking() {
+        assert_eq!(kinds(".*foo"), vec![WarningKind::Backtracking]);
+        assert_eq!(kinds("^.*foo"), vec![]);
+        assert_eq!(kinds("^(%w+)%a*"), vec![WarningKind::Backtracking]);
+        assert_eq!(kinds("^%a+%d+"), vec![]);
+        let w = analyze("^(.-)%s*$").unwrap();
+        assert_eq!(w[0].span, 2..8);
+    }
+
+    #[test]
+    fn gsub_captures() {
+        assert_eq!(analyze_gsub("(%a+)=(%a+)", "%2=%1").unwrap(), vec![]);
+        let w = analyze_gsub("(%a+)=()", "%%1%1").unwrap();
+        assert_eq!(w, vec![Warning{kind: WarningKind::UnusedCapture(2), span: 6..8}]);
+        assert_eq!(analyze_gsub("%a+", "<%0>").unwrap(), vec![]);
+        assert!(analyze_gsub("(%a+", "").is_err());
+    }
+
+    #[test]
+    fn messages() {
+        let w = analyze("a$b").unwrap();
+        assert_eq!(w[0].to_string(), "'$' is only an anchor at the end of the pattern (at 1..2)");
+    }
+}
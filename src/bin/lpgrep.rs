@@ -0,0 +1,148 @@
+// lpgrep: search files (or stdin) for lines matching a Lua pattern.
+// Lines are treated as bytes, so files need not be UTF-8.
+extern crate lua_patterns;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process;
+use lua_patterns::LuaPattern;
+
+const USAGE: &str = "usage: lpgrep [-o] [-v] [-n] [--replace REPL] PATTERN [FILE...]
+  -o              print only the matches (the first capture, if any)
+  -v              print lines which do not match
+  -n              prefix lines with their line number
+  --replace REPL  print matching lines with matches replaced by REPL,
+                  which may contain %0, %1, .. and %%";
+
+#[derive(Default)]
+struct Options {
+    only_matching: bool,
+    invert: bool,
+    line_numbers: bool,
+    replace: Option<String>,
+    pattern: String,
+    files: Vec<String>,
+}
+
+fn parse_args(args: &[String]) -> Result<Options,String> {
+    let mut opts = Options::default();
+    let mut rest = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        let arg = &args[i];
+        i += 1;
+        if ! rest.is_empty() || ! arg.starts_with('-') || arg == "-" {
+            rest.push(arg.clone());
+            continue;
+        }
+        match arg.as_str() {
+            "--" => {
+                rest.extend(args[i..].iter().cloned());
+                break;
+            },
+            "--replace" => {
+                let repl = args.get(i).ok_or("--replace needs a replacement")?;
+                opts.replace = Some(repl.clone());
+                i += 1;
+            },
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            flags if ! flags.starts_with("--") => {
+                for c in flags[1..].chars() {
+                    match c {
+                        'o' => opts.only_matching = true,
+                        'v' => opts.invert = true,
+                        'n' => opts.line_numbers = true,
+                        _ => return Err(format!("unknown flag -{}\n{}", c, USAGE))
+                    }
+                }
+            },
+            _ => return Err(format!("unknown option {}\n{}", arg, USAGE))
+        }
+    }
+    if opts.only_matching && opts.replace.is_some() {
+        return Err("-o and --replace cannot be used together".to_string());
+    }
+    let mut rest = rest.into_iter();
+    opts.pattern = rest.next().ok_or(USAGE)?;
+    opts.files = rest.collect();
+    Ok(opts)
+}
+
+// search one input, returning true if any line was selected
+fn grep<R: BufRead, W: Write>(m: &mut LuaPattern, opts: &Options, name: Option<&str>, input: R, out: &mut W) -> io::Result<bool> {
+    let mut selected = false;
+    for (i, line) in input.split(b'\n').enumerate() {
+        let line = line?;
+        if m.matches_bytes(&line) == opts.invert {
+            continue;
+        }
+        selected = true;
+        let prefix = |out: &mut W| -> io::Result<()> {
+            if let Some(name) = name {
+                write!(out, "{}:", name)?;
+            }
+            if opts.line_numbers {
+                write!(out, "{}:", i + 1)?;
+            }
+            Ok(())
+        };
+        if opts.only_matching && ! opts.invert {
+            for found in m.gmatch_bytes(&line) {
+                prefix(out)?;
+                out.write_all(found)?;
+                out.write_all(b"\n")?;
+            }
+        } else {
+            prefix(out)?;
+            match opts.replace {
+                Some(ref repl) if ! opts.invert => {
+                    m.gsub_bytes_to(&line, repl.as_bytes(), out)?;
+                },
+                _ => out.write_all(&line)?
+            }
+            out.write_all(b"\n")?;
+        }
+    }
+    Ok(selected)
+}
+
+fn run(opts: &Options) -> Result<bool,String> {
+    let mut m = LuaPattern::new_try(&opts.pattern).map_err(|e| format!("bad pattern: {}", e))?;
+    if let Some(ref repl) = opts.replace {
+        m.check_replacement(repl).map_err(|e| format!("bad replacement: {}", e))?;
+    }
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut selected = false;
+    if opts.files.is_empty() {
+        let stdin = io::stdin();
+        selected = grep(&mut m, opts, None, stdin.lock(), &mut out).map_err(|e| e.to_string())?;
+    }
+    let show_names = opts.files.len() > 1;
+    for file in &opts.files {
+        let name = if show_names {Some(file.as_str())} else {None};
+        let res = if file == "-" {
+            let stdin = io::stdin();
+            grep(&mut m, opts, name, stdin.lock(), &mut out)
+        } else {
+            let f = File::open(file).map_err(|e| format!("{}: {}", file, e))?;
+            grep(&mut m, opts, name, BufReader::new(f), &mut out)
+        };
+        selected |= res.map_err(|e| format!("{}: {}", file, e))?;
+    }
+    Ok(selected)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let code = match parse_args(&args).and_then(|opts| run(&opts)) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            eprintln!("lpgrep: {}", e);
+            2
+        }
+    };
+    process::exit(code);
+}
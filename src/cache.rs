@@ -0,0 +1,128 @@
+//! A cache of checked patterns, for patterns which arrive at run time.
+//!
+//! Lua keeps recently used patterns around so that `string.find` and friends
+//! do not have to start from scratch each time; `PatternCache` does the same
+//! for patterns read from rules files and the like. When the cache is full,
+//! the pattern used least recently is dropped.
+//!
+//! ```
+//! use lua_patterns::PatternCache;
+//! let cache = PatternCache::new(16);
+//! let rules = ["(%a+)=(%d+)", "^#", "(%a+)=(%d+)"];
+//! let hits: Vec<bool> = rules.iter()
+//!     .map(|r| cache.get(r).unwrap().pattern().matches("k=10"))
+//!     .collect();
+//! assert_eq!(hits, &[true, false, true]);
+//! assert_eq!(cache.len(), 2);
+//! assert!(cache.get("(%a+").is_err());
+//! ```
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use errors::*;
+use LuaPatternOwned;
+
+/// A thread-safe cache of checked patterns with least-recently-used eviction
+pub struct PatternCache {
+    capacity: usize,
+    inner: Mutex<Entries>,
+}
+
+#[derive(Default)]
+struct Entries {
+    // each pattern, with the tick when it was last used
+    map: HashMap<String,(Arc<LuaPatternOwned>,u64)>,
+    tick: u64,
+}
+
+impl PatternCache {
+    /// A cache holding at most `capacity` patterns
+    pub fn new(capacity: usize) -> PatternCache {
+        PatternCache{capacity: capacity.max(1), inner: Mutex::new(Entries::default())}
+    }
+
+    /// Get a checked pattern, checking and caching it if it is not already known
+    ///
+    /// Bad patterns are not cached.
+    pub fn get(&self, patt: &str) -> Result<Arc<LuaPatternOwned>,PatternError> {
+        let mut entries = self.lock();
+        entries.tick += 1;
+        let tick = entries.tick;
+        if let Some(entry) = entries.map.get_mut(patt) {
+            entry.1 = tick;
+            return Ok(entry.0.clone());
+        }
+        let owned = Arc::new(LuaPatternOwned::new(patt)?);
+        if entries.map.len() >= self.capacity {
+            let oldest = entries.map.iter()
+                .min_by_key(|(_, entry)| entry.1)
+                .map(|(key, _)| key.clone());
+            if let Some(key) = oldest {
+                entries.map.remove(&key);
+            }
+        }
+        entries.map.insert(patt.to_string(), (owned.clone(), tick));
+        Ok(owned)
+    }
+
+    /// Is this pattern in the cache?
+    pub fn contains(&self, patt: &str) -> bool {
+        self.lock().map.contains_key(patt)
+    }
+
+    /// Number of patterns in the cache
+    pub fn len(&self) -> usize {
+        self.lock().map.len()
+    }
+
+    /// Is the cache empty?
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove all patterns from the cache
+    pub fn clear(&self) {
+        self.lock().map.clear();
+    }
+
+    // the entries stay consistent even if another thread panicked
+    fn lock(&self) -> std::sync::MutexGuard<'_,Entries> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn least_recently_used() {
+        let cache = PatternCache::new(2);
+        cache.get("a").unwrap();
+        cache.get("b").unwrap();
+        cache.get("a").unwrap();
+        cache.get("c").unwrap();
+        assert!(cache.contains("a"));
+        assert!(! cache.contains("b"));
+        assert!(cache.contains("c"));
+        assert!(cache.get("%").is_err());
+        assert_eq!(cache.len(), 2);
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let cache = Arc::new(PatternCache::new(4));
+        let handles: Vec<_> = (0..8).map(|i| {
+            let cache = cache.clone();
+            std::thread::spawn(move || {
+                let patt = format!("%d{}", i % 3);
+                cache.get(&patt).unwrap().pattern().matches("x41")
+            })
+        }).collect();
+        let hits: Vec<bool> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(hits, &[false, true, false, false, true, false, false, true]);
+        assert_eq!(cache.len(), 3);
+    }
+}
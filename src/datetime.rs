@@ -0,0 +1,245 @@
+//! Finding timestamps in text.
+//!
+//! `find_iso8601` finds times like "2024-02-29T13:45:00.5+02:00", which
+//! includes the common "2024-02-29 13:45:00", and `find_rfc2822` finds times
+//! like "Thu, 29 Feb 2024 13:45:00 +0200" as used in email headers. Both
+//! return a `Timestamp`, whose fields are numbers; impossible dates like
+//! "2023-02-29" are skipped. With the `chrono` feature, a `Timestamp` can be
+//! converted into a `chrono` date and time.
+//!
+//! ```
+//! use lua_patterns::datetime::{find_iso8601, find_rfc2822};
+//!
+//! let t = find_iso8601("[2024-02-29 13:45:07] started").unwrap();
+//! assert_eq!(t.date(), (2024, 2, 29));
+//! assert_eq!(t.time(), (13, 45, 7));
+//! assert_eq!(t.offset, None);
+//! let t = find_rfc2822("Date: Thu, 29 Feb 2024 13:45:00 -0500").unwrap();
+//! assert_eq!((t.date(), t.offset), ((2024, 2, 29), Some(-5 * 3600)));
+//! ```
+
+use LuaPattern;
+
+/// An ISO 8601 date and time, capturing the year, month, day, hour, minute,
+/// second, any fraction of a second (with its '.' or ','), and anything which
+/// looks like a time zone
+pub const ISO_8601: &str = "%f[%d](%d%d%d%d)%-(%d%d)%-(%d%d)[T ](%d%d):(%d%d):(%d%d)([%.,]?%d*)([Zz%+%-]?[%d:]*)";
+
+/// An RFC 2822 date and time without the day of the week, capturing the
+/// day, month name, year, hour, minute, seconds (with their ':') and zone
+pub const RFC_2822: &str = "%f[%w](%d%d?) (%a%a%a) (%d%d%d%d) (%d%d):(%d%d)(:?%d?%d?)%s*([%+%-]?%w*)";
+
+/// A date and time found in text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Timestamp {
+    pub year: i32,
+    /// the month, from 1 to 12
+    pub month: u32,
+    /// the day of the month, from 1
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    /// the second, which is 60 for a leap second
+    pub second: u32,
+    pub nanosecond: u32,
+    /// the offset from UTC in seconds, if a time zone was given
+    pub offset: Option<i32>,
+}
+
+impl Timestamp {
+    /// The year, month and day
+    pub fn date(&self) -> (i32, u32, u32) {
+        (self.year, self.month, self.day)
+    }
+
+    /// The hour, minute and second
+    pub fn time(&self) -> (u32, u32, u32) {
+        (self.hour, self.minute, self.second)
+    }
+
+    // None unless the fields make sense
+    fn checked(self) -> Option<Timestamp> {
+        let leap = self.year % 4 == 0 && (self.year % 100 != 0 || self.year % 400 == 0);
+        let days = match self.month {
+            1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+            4 | 6 | 9 | 11 => 30,
+            2 => if leap {29} else {28},
+            _ => return None
+        };
+        if self.day == 0 || self.day > days || self.hour > 23 || self.minute > 59 || self.second > 60 {
+            return None;
+        }
+        Some(self)
+    }
+
+    /// The date and time, ignoring any offset
+    #[cfg(feature = "chrono")]
+    pub fn to_naive(&self) -> Option<::chrono::NaiveDateTime> {
+        // chrono represents a leap second as a large nanosecond count
+        let (second, nano) = if self.second == 60 {
+            (59, self.nanosecond + 1_000_000_000)
+        } else {
+            (self.second, self.nanosecond)
+        };
+        ::chrono::NaiveDate::from_ymd_opt(self.year, self.month, self.day)?
+            .and_hms_nano_opt(self.hour, self.minute, second, nano)
+    }
+
+    /// The date and time with its offset, or `None` if there was no time zone
+    #[cfg(feature = "chrono")]
+    pub fn to_chrono(&self) -> Option<::chrono::DateTime<::chrono::FixedOffset>> {
+        let offset = ::chrono::FixedOffset::east_opt(self.offset?)?;
+        self.to_naive()?.and_local_timezone(offset).single()
+    }
+}
+
+// the offset of "Z", "+02", "+0200" or "+02:00"
+fn numeric_offset(zone: &str) -> Option<i32> {
+    if zone == "Z" || zone == "z" {
+        return Some(0);
+    }
+    let (sign, hours, minutes) = LuaPattern::new("^([%+%-])(%d%d):?(%d?%d?)$").match_maybe_3(zone)?;
+    let minutes = match minutes.len() {
+        0 => 0,
+        2 => minutes.parse::<i32>().ok()?,
+        _ => return None
+    };
+    let offset = hours.parse::<i32>().ok()? * 3600 + minutes * 60;
+    Some(if sign == "-" {-offset} else {offset})
+}
+
+fn number<T: ::std::str::FromStr>(s: &str) -> Option<T> {
+    s.parse().ok()
+}
+
+fn iso8601_at(text: &str, m: &mut LuaPattern) -> Option<Timestamp> {
+    let cc = m.captures(text);
+    let fraction = cc[7];
+    let nanosecond = if fraction.len() > 1 {
+        // only the first nine digits matter
+        let digits = &fraction[1..fraction.len().min(10)];
+        number::<u32>(digits)? * 10u32.pow(9 - digits.len() as u32)
+    } else {
+        0
+    };
+    Timestamp {
+        year: number(cc[1])?, month: number(cc[2])?, day: number(cc[3])?,
+        hour: number(cc[4])?, minute: number(cc[5])?, second: number(cc[6])?,
+        nanosecond,
+        // anything else after the time is not a zone
+        offset: numeric_offset(cc[8]),
+    }.checked()
+}
+
+/// Find the first ISO 8601 date and time in the text.
+///
+/// The date and time may be separated by 'T' or a space, and may be
+/// followed by a fraction of a second and by 'Z' or a numeric offset.
+///
+/// ```
+/// let t = lua_patterns::datetime::find_iso8601("at 2024-06-01T09:30:15.25Z").unwrap();
+/// assert_eq!((t.time(), t.nanosecond, t.offset), ((9, 30, 15), 250_000_000, Some(0)));
+/// ```
+pub fn find_iso8601(text: &str) -> Option<Timestamp> {
+    find_first(text, ISO_8601, iso8601_at)
+}
+
+fn rfc2822_at(text: &str, m: &mut LuaPattern) -> Option<Timestamp> {
+    let cc = m.captures(text);
+    let month = ["Jan","Feb","Mar","Apr","May","Jun","Jul","Aug","Sep","Oct","Nov","Dec"]
+        .iter().position(|&name| name == cc[2])? as u32 + 1;
+    let second = match cc[6].len() {
+        0 => 0,
+        3 => number(&cc[6][1..])?,
+        _ => return None
+    };
+    let offset = match cc[7] {
+        "UT" | "GMT" | "Z" => Some(0),
+        "EDT" => Some(-4 * 3600),
+        "EST" | "CDT" => Some(-5 * 3600),
+        "CST" | "MDT" => Some(-6 * 3600),
+        "MST" | "PDT" => Some(-7 * 3600),
+        "PST" => Some(-8 * 3600),
+        zone => numeric_offset(zone)
+    };
+    Timestamp {
+        year: number(cc[3])?, month, day: number(cc[1])?,
+        hour: number(cc[4])?, minute: number(cc[5])?, second,
+        nanosecond: 0,
+        offset,
+    }.checked()
+}
+
+/// Find the first RFC 2822 date and time in the text, like
+/// "1 Jul 2003 10:52:37 +0200".
+///
+/// Any day of the week before the date is ignored, and the seconds may be
+/// left out. The zone may be numeric or one of UT, GMT and the US zones.
+///
+/// ```
+/// let t = lua_patterns::datetime::find_rfc2822("sent 1 Jul 2003 10:52 PDT").unwrap();
+/// assert_eq!((t.date(), t.time(), t.offset), ((2003, 7, 1), (10, 52, 0), Some(-7 * 3600)));
+/// ```
+pub fn find_rfc2822(text: &str) -> Option<Timestamp> {
+    find_first(text, RFC_2822, rfc2822_at)
+}
+
+// the first match which converts to a timestamp
+fn find_first<F>(text: &str, patt: &str, mut convert: F) -> Option<Timestamp>
+where F: FnMut(&str, &mut LuaPattern) -> Option<Timestamp> {
+    let mut m = LuaPattern::new(patt);
+    let mut pos = 0;
+    while m.matches_bytes_at(text.as_bytes(), pos, false) {
+        let range = m.range();
+        if let Some(t) = convert(&text[range.start..], &mut m) {
+            return Some(t);
+        }
+        pos = range.start + 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iso8601() {
+        let t = find_iso8601("2024-02-30 00:00:00, then 1999-12-31T23:59:60,123456789999-05").unwrap();
+        assert_eq!(t, Timestamp {
+            year: 1999, month: 12, day: 31, hour: 23, minute: 59, second: 60,
+            nanosecond: 123_456_789, offset: Some(-5 * 3600),
+        });
+        assert_eq!(find_iso8601("2020-01-02T03:04:05+05:30").unwrap().offset, Some(19800));
+        assert_eq!(find_iso8601("2020-01-02T03:04:05-0100").unwrap().offset, Some(-3600));
+        let t = find_iso8601("2020-01-02 03:04:05. Done").unwrap();
+        assert_eq!((t.second, t.nanosecond, t.offset), (5, 0, None));
+        assert_eq!(find_iso8601("2023-02-29 12:00:00"), None);
+        assert_eq!(find_iso8601("2024-01-01 24:00:00"), None);
+        assert_eq!(find_iso8601("2024-01-01"), None);
+    }
+
+    #[test]
+    fn rfc2822() {
+        let t = find_rfc2822("Date: Fri, 21 Nov 1997 09:55:06 -0600").unwrap();
+        assert_eq!(t, Timestamp {
+            year: 1997, month: 11, day: 21, hour: 9, minute: 55, second: 6,
+            nanosecond: 0, offset: Some(-6 * 3600),
+        });
+        assert_eq!(find_rfc2822("1 Jan 2000 00:00:00 GMT").unwrap().offset, Some(0));
+        assert_eq!(find_rfc2822("1 Jan 2000 00:00:00 +0530").unwrap().offset, Some(19800));
+        assert_eq!(find_rfc2822("1 Jan 2000 00:00 and more").unwrap().offset, None);
+        assert_eq!(find_rfc2822("31 Foo 2000 00:00, 30 Apr 2000 01:02").unwrap().date(), (2000, 4, 30));
+        assert_eq!(find_rfc2822("31 Apr 2000 00:00:00"), None);
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn chrono() {
+        let t = find_iso8601("2024-02-29T13:45:00.5+02:00").unwrap();
+        assert_eq!(t.to_chrono().unwrap().to_rfc3339(), "2024-02-29T13:45:00.500+02:00");
+        let t = find_iso8601("2016-12-31 23:59:60").unwrap();
+        assert_eq!(t.to_chrono(), None);
+        assert_eq!(t.to_naive().unwrap().to_string(), "2016-12-31 23:59:60");
+    }
+}
@@ -0,0 +1,352 @@
+//! Deserializing the captures of a match with serde.
+//!
+//! Lua patterns have no named captures, so captures are matched with the
+//! fields of a struct in order, or with names given to `from_str_named`.
+//! Each capture is parsed as whatever type its field needs; an empty capture
+//! for an `Option` field gives `None`. Tuples and sequences get the captures
+//! in order, and a pattern with a single capture (or none) can be
+//! deserialized directly as a number, string and so on.
+//!
+//! ```
+//! #[macro_use]
+//! extern crate serde_derive;
+//! extern crate lua_patterns;
+//! use lua_patterns::LuaPattern;
+//!
+//! #[derive(Deserialize)]
+//! struct Request<'a> {
+//!     method: &'a str,
+//!     path: &'a str,
+//!     status: u16,
+//!     size: Option<u32>,
+//! }
+//!
+//! # fn main() {
+//! let mut m = LuaPattern::new("(%u+) (%S+) (%d+) (%d*)");
+//! let line = "GET /index.html 200 ";
+//! let req: Request = lua_patterns::de::from_str(&mut m, line).unwrap();
+//! assert_eq!(req.method, "GET");
+//! assert_eq!(req.path, "/index.html");
+//! assert_eq!(req.status, 200);
+//! assert_eq!(req.size, None);
+//!
+//! let (key, value): (String, f64) = lua_patterns::de::from_str(&mut LuaPattern::new("(%a+)=(%S+)"), "x=1.5").unwrap();
+//! assert_eq!((key.as_str(), value), ("x", 1.5));
+//! # }
+//! ```
+
+use std::error;
+use std::fmt;
+use std::ops;
+use std::str::FromStr;
+use serde::de::{self, Deserialize, IntoDeserializer, Visitor};
+use LuaPattern;
+
+/// Error from deserializing captures
+#[derive(Debug,Clone,PartialEq)]
+pub struct Error(pub String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,"{}",self.0)
+    }
+}
+
+impl error::Error for Error {}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error(msg.to_string())
+    }
+}
+
+/// Match `text` and deserialize a value from the captures,
+/// which are matched with struct fields in order.
+pub fn from_str<'de, T: Deserialize<'de>>(m: &mut LuaPattern, text: &'de str) -> Result<T,Error> {
+    T::deserialize(Deserializer::new(m, text)?)
+}
+
+/// Match `text` and deserialize a value from the captures, which are
+/// given names in order. Captures without names are ignored.
+///
+/// ```
+/// use std::collections::HashMap;
+/// use lua_patterns::LuaPattern;
+/// let mut m = LuaPattern::new("(%d+):(%d+)");
+/// let map: HashMap<String,u32> = lua_patterns::de::from_str_named(&mut m, &["hours","mins"], "10:30").unwrap();
+/// assert_eq!(map["hours"], 10);
+/// assert_eq!(map["mins"], 30);
+/// ```
+pub fn from_str_named<'de, T: Deserialize<'de>>(m: &mut LuaPattern, names: &[&str], text: &'de str) -> Result<T,Error> {
+    T::deserialize(Deserializer::new(m, text)?.with_names(names))
+}
+
+/// A serde `Deserializer` for the captures of a match
+pub struct Deserializer<'n,'de> {
+    text: &'de str,
+    captures: Vec<ops::Range<usize>>,
+    names: Option<&'n [&'n str]>,
+}
+
+impl <'n,'de> Deserializer<'n,'de> {
+    /// Match `text`, failing if there is no match. If the pattern has no
+    /// captures, the whole match is used.
+    pub fn new(m: &mut LuaPattern, text: &'de str) -> Result<Deserializer<'n,'de>,Error> {
+        if ! m.matches(text) {
+            return Err(Error(format!("no match for {:?}", text)));
+        }
+        let captures = if m.n_match > 1 {
+            (1..m.n_match).map(|i| m.capture(i)).collect()
+        } else {
+            vec![m.range()]
+        };
+        Ok(Deserializer{text, captures, names: None})
+    }
+
+    /// Name the captures in order, for deserializing maps and structs
+    pub fn with_names(self, names: &'n [&'n str]) -> Deserializer<'n,'de> {
+        Deserializer{names: Some(names), ..self}
+    }
+
+    fn capture(&self, i: usize) -> Capture<'de> {
+        Capture(&self.text[self.captures[i].clone()])
+    }
+}
+
+struct CaptureMap<'n,'de> {
+    de: Deserializer<'n,'de>,
+    keys: &'n [&'n str],
+    idx: usize,
+}
+
+impl <'n,'de> de::MapAccess<'de> for CaptureMap<'n,'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>,Error> {
+        if self.idx < self.keys.len().min(self.de.captures.len()) {
+            let key: de::value::StrDeserializer<Error> = self.keys[self.idx].into_deserializer();
+            seed.deserialize(key).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value,Error> {
+        let value = self.de.capture(self.idx);
+        self.idx += 1;
+        seed.deserialize(value)
+    }
+}
+
+struct CaptureSeq<'n,'de> {
+    de: Deserializer<'n,'de>,
+    idx: usize,
+}
+
+impl <'n,'de> de::SeqAccess<'de> for CaptureSeq<'n,'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>,Error> {
+        if self.idx < self.de.captures.len() {
+            let value = self.de.capture(self.idx);
+            self.idx += 1;
+            seed.deserialize(value).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.de.captures.len() - self.idx)
+    }
+}
+
+// a single value is taken from the first capture
+macro_rules! first_capture {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value,Error> {
+                self.capture(0).$method(visitor)
+            }
+        )*
+    }
+}
+
+impl <'n,'de> de::Deserializer<'de> for Deserializer<'n,'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value,Error> {
+        if self.names.is_some() {
+            self.deserialize_map(visitor)
+        } else if self.captures.len() == 1 {
+            self.capture(0).deserialize_any(visitor)
+        } else {
+            self.deserialize_seq(visitor)
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str, fields: &'static [&'static str], visitor: V) -> Result<V::Value,Error> {
+        let keys = self.names.unwrap_or(fields);
+        visitor.visit_map(CaptureMap{de: self, keys, idx: 0})
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value,Error> {
+        match self.names {
+            Some(keys) => visitor.visit_map(CaptureMap{de: self, keys, idx: 0}),
+            None => Err(Error("captures need names to be deserialized as a map".into()))
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value,Error> {
+        visitor.visit_seq(CaptureSeq{de: self, idx: 0})
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value,Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, _len: usize, visitor: V) -> Result<V::Value,Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value,Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, name: &'static str, variants: &'static [&'static str], visitor: V) -> Result<V::Value,Error> {
+        self.capture(0).deserialize_enum(name, variants, visitor)
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, name: &'static str, visitor: V) -> Result<V::Value,Error> {
+        self.capture(0).deserialize_unit_struct(name, visitor)
+    }
+
+    first_capture! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+        deserialize_f32 deserialize_f64 deserialize_char deserialize_str deserialize_string
+        deserialize_bytes deserialize_byte_buf deserialize_option deserialize_unit
+        deserialize_identifier deserialize_ignored_any
+    }
+}
+
+// A single capture, which is parsed as needed
+struct Capture<'de>(&'de str);
+
+impl <'de> Capture<'de> {
+    fn parse<T: FromStr>(&self) -> Result<T,Error> {
+        self.0.parse().map_err(|_| Error(format!("cannot parse {:?} as {}", self.0, ::std::any::type_name::<T>())))
+    }
+}
+
+macro_rules! parse_capture {
+    ($($method:ident $visit:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value,Error> {
+                visitor.$visit(self.parse()?)
+            }
+        )*
+    }
+}
+
+impl <'de> de::Deserializer<'de> for Capture<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value,Error> {
+        visitor.visit_borrowed_str(self.0)
+    }
+
+    parse_capture! {
+        deserialize_bool visit_bool
+        deserialize_i8 visit_i8 deserialize_i16 visit_i16 deserialize_i32 visit_i32 deserialize_i64 visit_i64
+        deserialize_u8 visit_u8 deserialize_u16 visit_u16 deserialize_u32 visit_u32 deserialize_u64 visit_u64
+        deserialize_f32 visit_f32 deserialize_f64 visit_f64 deserialize_char visit_char
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value,Error> {
+        if self.0.is_empty() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value,Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V) -> Result<V::Value,Error> {
+        let variant: de::value::BorrowedStrDeserializer<Error> = de::value::BorrowedStrDeserializer::new(self.0);
+        visitor.visit_enum(variant)
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 str string bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[derive(Deserialize,Debug,PartialEq)]
+    enum Level {
+        Info,
+        Error,
+    }
+
+    #[derive(Deserialize,Debug,PartialEq)]
+    struct Entry {
+        level: Level,
+        code: Option<i32>,
+        message: String,
+    }
+
+    #[derive(Deserialize,Debug,PartialEq)]
+    struct Port(u16);
+
+    #[test]
+    fn structs() {
+        let mut m = LuaPattern::new("^(%a+)%s*(%-?%d*): (.*)$");
+        let e: Entry = from_str(&mut m, "Error -2: disk full").unwrap();
+        assert_eq!(e, Entry{level: Level::Error, code: Some(-2), message: "disk full".into()});
+        let e: Entry = from_str(&mut m, "Info: started").unwrap();
+        assert_eq!(e, Entry{level: Level::Info, code: None, message: "started".into()});
+        let err = from_str::<Entry>(&mut m, "Debug: started").unwrap_err();
+        assert!(err.0.contains("unknown variant `Debug`"), "{}", err);
+    }
+
+    #[test]
+    fn named() {
+        let mut m = LuaPattern::new("(%S+) (%d+) (%a+)");
+        let e: Entry = from_str_named(&mut m, &["message","code","level"], "hello 42 Info").unwrap();
+        assert_eq!(e, Entry{level: Level::Info, code: Some(42), message: "hello".into()});
+        let map: HashMap<String,&str> = from_str_named(&mut m, &["a","b"], "x 1 y").unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["b"], "1");
+        assert!(from_str::<HashMap<String,String>>(&mut m, "x 1 y").is_err());
+    }
+
+    #[test]
+    fn single_values() {
+        let mut m = LuaPattern::new("port (%d+)");
+        assert_eq!(from_str::<u16>(&mut m, "on port 8080"), Ok(8080));
+        assert_eq!(from_str::<Port>(&mut m, "on port 8080"), Ok(Port(8080)));
+        assert_eq!(from_str::<String>(&mut LuaPattern::new("%d+"), "ab 12"), Ok("12".to_string()));
+        let err = from_str::<u8>(&mut m, "port 300").unwrap_err();
+        assert_eq!(err.0, "cannot parse \"300\" as u8");
+        let err = from_str::<u8>(&mut m, "nothing").unwrap_err();
+        assert_eq!(err.0, "no match for \"nothing\"");
+    }
+
+    #[test]
+    fn sequences() {
+        let mut m = LuaPattern::new("(%d+)%.(%d+)%.(%d+)");
+        let v: Vec<u32> = from_str(&mut m, "version 1.22.3").unwrap();
+        assert_eq!(v, &[1,22,3]);
+        let t: (u8, u8, bool) = from_str(&mut LuaPattern::new("(%d)(%d) (%a+)"), "12 true").unwrap();
+        assert_eq!(t, (1, 2, true));
+    }
+}
@@ -1,19 +1,158 @@
 use std::fmt;
 use std::error::Error;
 
+/// What went wrong, for errors found checking a pattern or while matching
+///
+/// More kinds may be added, so matches on this need a wildcard arm.
+#[derive(Copy,Clone,Debug,PartialEq,Eq,Hash)]
+#[non_exhaustive]
+pub enum PatternErrorKind {
+	/// The pattern ends with a lone `%`
+	EndsWithEscape,
+	/// `%b` is not followed by two characters
+	MissingBalanceArgs,
+	/// `%f` is not followed by a set
+	MissingFrontierSet,
+	/// A back reference like `%1` to a capture which is not closed
+	InvalidCaptureIndex,
+	/// A set which is not closed with `]`
+	MalformedClass,
+	/// More captures than the matcher allows
+	TooManyCaptures,
+	/// A `)` without a matching `(`
+	NoOpenCapture,
+	/// A `(` without a matching `)`
+	UnfinishedCapture,
+	/// A quantifier which does not follow a single character item
+	MisplacedQuantifier,
+	/// The match took too many steps
+	StepLimit,
+	/// The match took too long
+	Timeout,
+	/// The matcher recursed too deeply
+	TooComplex,
+	/// A replacement string refers to a capture the pattern does not have,
+	/// or has a `%` not followed by a digit or `%`
+	InvalidReplacement,
+	/// Writing the result of a substitution to a `fmt::Write` sink failed
+	WriteFailed,
+	/// A match or capture in a string does not start and end on
+	/// character boundaries, as `.` against a multi-byte character
+	SplitsCharacter,
+	/// A set given to `LuaPatternBuilder` which would match nothing,
+	/// such as an empty range
+	EmptySet,
+}
+
+impl PatternErrorKind {
+	/// Is this a problem found while matching, rather than with the pattern itself?
+	///
+	/// ```
+	/// use lua_patterns::errors::PatternErrorKind;
+	/// assert!(PatternErrorKind::Timeout.is_runtime());
+	/// assert!(! PatternErrorKind::UnfinishedCapture.is_runtime());
+	/// ```
+	pub fn is_runtime(self) -> bool {
+		use self::PatternErrorKind::*;
+		matches!(self, StepLimit | Timeout | TooComplex | InvalidReplacement | WriteFailed | SplitsCharacter)
+	}
+}
+
 /// Error type returned by _try methods
-#[derive(Debug,PartialEq)]
-pub struct PatternError(pub String);
+///
+/// As well as the message Lua would give, this has the kind of error and,
+/// for errors found when checking a pattern, the byte offset in the pattern
+/// where the problem was found.
+///
+/// ```
+/// use lua_patterns::errors::PatternErrorKind;
+/// let err = lua_patterns::LuaPattern::new_try("(%d+) [%a").unwrap_err();
+/// assert_eq!(err.kind(), PatternErrorKind::MalformedClass);
+/// assert_eq!(err.message(), "malformed pattern (missing ']')");
+/// assert_eq!(err.position(), Some(6));
+/// assert_eq!(err.caret().unwrap(), "      ^");
+/// ```
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct PatternError {
+	kind: PatternErrorKind,
+	message: String,
+	position: Option<usize>,
+}
+
+impl PatternError {
+	/// An error of the given kind, with no position
+	pub fn new(kind: PatternErrorKind, message: &str) -> PatternError {
+		PatternError{kind, message: message.into(), position: None}
+	}
+
+	/// This error, found at a byte offset in the pattern
+	pub fn at(mut self, position: usize) -> PatternError {
+		self.position = Some(position);
+		self
+	}
+
+	pub(crate) fn step_limit() -> PatternError {
+		PatternError::new(PatternErrorKind::StepLimit, "match step limit exceeded")
+	}
+
+	pub(crate) fn timeout() -> PatternError {
+		PatternError::new(PatternErrorKind::Timeout, "match timed out")
+	}
+
+	pub(crate) fn too_complex() -> PatternError {
+		PatternError::new(PatternErrorKind::TooComplex, "pattern too complex")
+	}
+
+	/// What went wrong
+	pub fn kind(&self) -> PatternErrorKind {
+		self.kind
+	}
+
+	/// The message, as given by Lua
+	pub fn message(&self) -> &str {
+		&self.message
+	}
+
+	/// The byte offset in the pattern where the problem was found, if known
+	pub fn position(&self) -> Option<usize> {
+		self.position
+	}
+
+	/// A line with a `^` under the problem, to print below the pattern
+	pub fn caret(&self) -> Option<String> {
+		self.position.map(|pos| format!("{}^", " ".repeat(pos)))
+	}
+
+	/// Was the match abandoned because its deadline passed?
+	pub fn is_timeout(&self) -> bool {
+		self.kind == PatternErrorKind::Timeout
+	}
+
+	/// Was the match abandoned because it took too many steps?
+	pub fn is_step_limit(&self) -> bool {
+		self.kind == PatternErrorKind::StepLimit
+	}
+
+	/// Was the match abandoned because the matcher recursed too deeply?
+	pub fn is_too_complex(&self) -> bool {
+		self.kind == PatternErrorKind::TooComplex
+	}
+}
+
+impl From<fmt::Error> for PatternError {
+	fn from(_: fmt::Error) -> PatternError {
+		PatternError::new(PatternErrorKind::WriteFailed, "writing the result failed")
+	}
+}
 
 impl fmt::Display for PatternError {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		write!(f,"{}",self.0)
+		write!(f,"{}",self.message)
 	}
 }
 
 impl Error for PatternError {
 	fn description(&self) -> &str {
-		&self.0
+		&self.message
 	}
 }
-
@@ -0,0 +1,104 @@
+//! Converting captures into typed values.
+//!
+//! `LuaPattern::match_tuple` generalizes `match_maybe_2` and `match_maybe_3`
+//! to tuples of up to six values, each of which may be a string slice, a
+//! `String`, or anything else implementing `FromCapture`, like numbers.
+//! The match fails if the pattern does not have exactly that many captures,
+//! or if any conversion fails.
+//!
+//! ```
+//! let mut m = lua_patterns::LuaPattern::new("(%a+) is (%d+)");
+//! let (name, age): (&str, u32) = m.match_tuple("Fred is 42").unwrap();
+//! assert_eq!((name, age), ("Fred", 42));
+//! assert_eq!(m.match_tuple::<(&str, u8)>("Fred is 420"), None);
+//! ```
+
+use Captures;
+
+/// A value which can be converted from a single capture
+pub trait FromCapture<'t>: Sized {
+    /// convert the capture, or `None` if it is not valid
+    fn from_capture(s: &'t str) -> Option<Self>;
+}
+
+impl <'t> FromCapture<'t> for &'t str {
+    fn from_capture(s: &'t str) -> Option<&'t str> {
+        Some(s)
+    }
+}
+
+macro_rules! from_capture_via_parse {
+    ($($t:ty)*) => {
+        $(
+            impl <'t> FromCapture<'t> for $t {
+                fn from_capture(s: &'t str) -> Option<$t> {
+                    s.parse().ok()
+                }
+            }
+        )*
+    }
+}
+
+from_capture_via_parse! {
+    String char bool
+    i8 i16 i32 i64 i128 isize
+    u8 u16 u32 u64 u128 usize
+    f32 f64
+}
+
+/// An empty capture gives `None`
+impl <'t, T: FromCapture<'t>> FromCapture<'t> for Option<T> {
+    fn from_capture(s: &'t str) -> Option<Option<T>> {
+        if s.is_empty() {
+            Some(None)
+        } else {
+            T::from_capture(s).map(Some)
+        }
+    }
+}
+
+/// A value which can be built from all the captures of a match
+pub trait FromCaptures<'t>: Sized {
+    /// convert the captures, or `None` if they do not fit
+    fn from_captures(cc: &Captures<'_,'t,'_>) -> Option<Self>;
+}
+
+macro_rules! from_captures_for_tuple {
+    ($n:expr; $($t:ident $i:expr),*) => {
+        impl <'t, $($t: FromCapture<'t>),*> FromCaptures<'t> for ($($t,)*) {
+            fn from_captures(cc: &Captures<'_,'t,'_>) -> Option<Self> {
+                if cc.num_matches() != $n + 1 {
+                    return None;
+                }
+                Some(($($t::from_capture(cc.get($i))?,)*))
+            }
+        }
+    }
+}
+
+from_captures_for_tuple!(1; A 1);
+from_captures_for_tuple!(2; A 1, B 2);
+from_captures_for_tuple!(3; A 1, B 2, C 3);
+from_captures_for_tuple!(4; A 1, B 2, C 3, D 4);
+from_captures_for_tuple!(5; A 1, B 2, C 3, D 4, E 5);
+from_captures_for_tuple!(6; A 1, B 2, C 3, D 4, E 5, F 6);
+
+#[cfg(test)]
+mod tests {
+    use LuaPattern;
+
+    #[test]
+    fn tuples() {
+        let mut m = LuaPattern::new("(%d+)/(%d+)/(%d+)");
+        assert_eq!(m.match_tuple("on 2017/11/10"), Some((2017u16, 11u8, 10u8)));
+        assert_eq!(m.match_tuple::<(u16,u8)>("2017/11/10"), None);
+        let mut m = LuaPattern::new("(%a+)%s*=%s*(%S*)");
+        assert_eq!(m.match_tuple("x = 1.5"), Some(("x".to_string(), Some(1.5f64))));
+        assert_eq!(m.match_tuple("x ="), Some(("x".to_string(), None::<f64>)));
+        assert_eq!(m.match_tuple::<(char,bool)>("x = true"), Some(('x', true)));
+        assert_eq!(m.match_tuple::<(char,bool)>("xy = true"), None);
+        let mut m = LuaPattern::new("(%d)(%d)(%d)(%d)(%d)(%d)");
+        assert_eq!(m.match_tuple("123456"), Some((1u8,2u8,3u8,4u8,5u8,6u8)));
+        assert_eq!(LuaPattern::new("(%a+)").match_tuple::<(&str,)>("  hi  "), Some(("hi",)));
+    }
+}
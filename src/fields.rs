@@ -0,0 +1,157 @@
+//! Splitting delimited data like CSV, where fields may be quoted.
+//!
+//! This is for quick jobs which don't deserve a full CSV parser. A field in
+//! double quotes may contain the separator and line breaks, and a quote is
+//! written inside it as `""`. Quoted fields are found with `%b""`, and a
+//! quote which is never closed takes the rest of the text. Text between a
+//! closing quote and the next separator is kept, so `"a"b` gives `ab`.
+//! Fields are borrowed from the text unless quotes had to be removed
+//! from inside them.
+//!
+//! ```
+//! use lua_patterns::fields::{split_fields, records};
+//!
+//! assert_eq!(split_fields(r#"1,"Soap, Joe","say ""hi""""#, ','), &["1", "Soap, Joe", r#"say "hi""#]);
+//! let rows: Vec<_> = records("name;notes\r\nx;\"two\nlines\"\n", ';').collect();
+//! assert_eq!(rows, &[vec!["name", "notes"], vec!["x", "two\nlines"]]);
+//! ```
+
+use std::borrow::Cow;
+use LuaPattern;
+
+// the separator escaped for use in a set
+fn separator(sep: char) -> String {
+    assert!(sep.is_ascii() && sep != '"' && sep != '\r' && sep != '\n', "separator must be ASCII and not a quote or line break");
+    if sep.is_ascii_punctuation() {format!("%{}", sep)} else {sep.to_string()}
+}
+
+/// Iterator over the records of delimited text, from `records`
+pub struct Records<'t> {
+    text: &'t str,
+    pos: usize,
+    bare: String,
+}
+
+impl <'t> Records<'t> {
+    // a field starting at `pos`, leaving `pos` after it
+    fn field(&mut self) -> Cow<'t, str> {
+        let bytes = self.text.as_bytes();
+        let mut bare = LuaPattern::new(&self.bare);
+        if bytes.get(self.pos) != Some(&b'"') {
+            bare.matches_bytes_at(bytes, self.pos, true);
+            self.pos = bare.range().end;
+            return Cow::Borrowed(&self.text[bare.range()]);
+        }
+        // each `%b""` is a quoted part, and adjacent parts have a quote between them
+        let mut quoted = LuaPattern::new("%b\"\"");
+        let mut value = Cow::Borrowed("");
+        let mut first = true;
+        while bytes.get(self.pos) == Some(&b'"') {
+            let part = if quoted.matches_bytes_at(bytes, self.pos, true) {
+                let r = quoted.range();
+                self.pos = r.end;
+                &self.text[r.start + 1 .. r.end - 1]
+            } else {
+                let part = &self.text[self.pos + 1 ..];
+                self.pos = self.text.len();
+                part
+            };
+            if first {
+                value = Cow::Borrowed(part);
+                first = false;
+            } else {
+                let s = value.to_mut();
+                s.push('"');
+                s.push_str(part);
+            }
+        }
+        bare.matches_bytes_at(bytes, self.pos, true);
+        if ! bare.range().is_empty() {
+            value.to_mut().push_str(&self.text[bare.range()]);
+            self.pos = bare.range().end;
+        }
+        value
+    }
+}
+
+impl <'t> Iterator for Records<'t> {
+    type Item = Vec<Cow<'t, str>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut eol = LuaPattern::new("^\r?\n");
+        // blank lines are skipped
+        while eol.matches_bytes_at(self.text.as_bytes(), self.pos, true) {
+            self.pos = eol.range().end;
+        }
+        if self.pos == self.text.len() {
+            return None;
+        }
+        let mut fields = vec![self.field()];
+        loop {
+            let bytes = self.text.as_bytes();
+            if eol.matches_bytes_at(bytes, self.pos, true) {
+                self.pos = eol.range().end;
+                break;
+            }
+            if self.pos == self.text.len() {
+                break;
+            }
+            // otherwise we are at a separator
+            self.pos += 1;
+            fields.push(self.field());
+        }
+        Some(fields)
+    }
+}
+
+/// Iterate over the records of delimited text, one per line except where
+/// a quoted field contains line breaks.
+///
+/// Lines may end with "\n" or "\r\n", and blank lines are skipped.
+/// Panics if `sep` is not ASCII, or is a quote or line break.
+pub fn records(text: &str, sep: char) -> Records<'_> {
+    Records{text, pos: 0, bare: format!("[^{}\r\n]*", separator(sep))}
+}
+
+/// Split a single line into fields.
+///
+/// Any line break outside quotes ends the line, and is not included.
+pub fn split_fields(line: &str, sep: char) -> Vec<Cow<'_, str>> {
+    let mut rec = records(line, sep);
+    if line.is_empty() {
+        // an empty line is a single empty field, not a blank line to skip
+        return vec![rec.field()];
+    }
+    rec.next().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields() {
+        assert_eq!(split_fields("a,,c,", ','), &["a", "", "c", ""]);
+        assert_eq!(split_fields("", ','), &[""]);
+        assert_eq!(split_fields(r#""" , "a""b""#, ','), &[" ", r#" "a""b""#]);
+        assert_eq!(split_fields(r#""a"x,"""",b"#, ','), &["ax", "\"", "b"]);
+        assert_eq!(split_fields("x|\"unterminated, with | in it", '|'), &["x", "unterminated, with | in it"]);
+        assert_eq!(split_fields("a.b.c\nd", '.'), &["a", "b", "c"]);
+        let f = split_fields(r#""plain",say ""no"""#, ',');
+        assert!(matches!(f[0], Cow::Borrowed("plain")));
+    }
+
+    #[test]
+    fn multiple_records() {
+        let text = "\na\tb\r\n\r\n\"c\r\nd\"\t\n\ne";
+        let rows: Vec<_> = records(text, '\t').collect();
+        assert_eq!(rows, &[vec!["a", "b"], vec!["c\r\nd", ""], vec!["e"]]);
+        assert_eq!(records("\n\n", ',').count(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "separator must be")]
+    fn quote_separator() {
+        split_fields("a\"b", '"');
+    }
+}
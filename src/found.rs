@@ -0,0 +1,437 @@
+//! Match results which own their positions, in the style of the `regex` crate.
+//!
+//! `LuaPattern::matches` keeps the results of the last match inside the
+//! pattern, so they must be read before the next match overwrites them.
+//! `find` and `find_captures` only need `&self` and return the results
+//! instead, so a pattern can be shared and the results kept around.
+//!
+//! ```
+//! let m = lua_patterns::LuaPattern::new("(%a+)=(%d+)");
+//! let text = "set x=10, y=20";
+//! let first = m.find(text).unwrap();
+//! assert_eq!(first.as_str(), "x=10");
+//! let cc = m.find_captures(&text[first.end()..]).unwrap();
+//! assert_eq!(cc.get(1).unwrap().as_str(), "y");
+//! assert_eq!(first.range(), 4..8);
+//! ```
+
+use std::ops;
+use {LuaPattern, Cursor};
+
+/// A single match or capture: where it is in the text, and the text itself
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub struct Match<'t> {
+    text: &'t str,
+    start: usize,
+    end: usize,
+}
+
+impl <'t> Match<'t> {
+    pub(crate) fn new(text: &'t str, range: ops::Range<usize>) -> Match<'t> {
+        Match{text, start: range.start, end: range.end}
+    }
+
+    /// Byte offset of the start of the match
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// Byte offset of the end of the match
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    /// Byte range of the match
+    pub fn range(&self) -> ops::Range<usize> {
+        self.start..self.end
+    }
+
+    /// Length of the match in bytes
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Is this an empty match, such as a position capture?
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// The matched text
+    ///
+    /// Panics if this splits a UTF-8 character, as a match of `.` can.
+    pub fn as_str(&self) -> &'t str {
+        &self.text[self.range()]
+    }
+
+    /// Range of the match in chars rather than bytes, if it starts and
+    /// ends on char boundaries
+    ///
+    /// ```
+    /// let m = lua_patterns::LuaPattern::new("v%a+");
+    /// let mat = m.find("déjà vu").unwrap();
+    /// assert_eq!((mat.range(), mat.char_range()), (7..9, Some(5..7)));
+    /// ```
+    pub fn char_range(&self) -> Option<ops::Range<usize>> {
+        char_range(self.text, self.range())
+    }
+}
+
+/// Convert a byte range in `text` into a range of char indices
+///
+/// Matches are byte ranges, but text widgets and the like often count
+/// chars. This is `None` if the range is out of bounds or does not start
+/// and end on char boundaries, which can happen since `.` matches one byte.
+///
+/// ```
+/// use lua_patterns::found::{char_range, byte_range};
+/// let text = "naïve café";
+/// let mut m = lua_patterns::LuaPattern::new("caf.+");
+/// assert!(m.matches(text));
+/// assert_eq!(m.range(), 7..12);
+/// assert_eq!(char_range(text, m.range()), Some(6..10));
+/// assert_eq!(byte_range(text, 6..10), Some(7..12));
+/// assert_eq!(char_range(text, 3..4), None);
+/// ```
+pub fn char_range(text: &str, bytes: ops::Range<usize>) -> Option<ops::Range<usize>> {
+    if bytes.start > bytes.end || ! text.is_char_boundary(bytes.start) || ! text.is_char_boundary(bytes.end) {
+        return None;
+    }
+    let start = text[..bytes.start].chars().count();
+    Some(start..start + text[bytes].chars().count())
+}
+
+/// Convert a range of char indices in `text` into a byte range,
+/// or `None` if it is out of bounds.
+///
+/// This is the inverse of `char_range`.
+pub fn byte_range(text: &str, chars: ops::Range<usize>) -> Option<ops::Range<usize>> {
+    if chars.start > chars.end {
+        return None;
+    }
+    // byte offset of each char, and of the end of the text
+    let mut offsets = text.char_indices().map(|(i, _)| i).chain(Some(text.len()));
+    let start = offsets.nth(chars.start)?;
+    let end = if chars.end == chars.start {start} else {offsets.nth(chars.end - chars.start - 1)?};
+    Some(start..end)
+}
+
+/// All the captures of a match, starting with the whole match
+///
+/// The ranges are copied out of the matcher, so this does not
+/// borrow the pattern.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Captures<'t> {
+    text: &'t str,
+    ranges: Vec<ops::Range<usize>>,
+}
+
+impl <'t> Captures<'t> {
+    pub(crate) fn new(text: &'t str, ranges: Vec<ops::Range<usize>>) -> Captures<'t> {
+        Captures{text, ranges}
+    }
+
+    // the same captures in a larger text, where the original text starts at `offset`
+    pub(crate) fn shifted(self, text: &'t str, offset: usize) -> Captures<'t> {
+        let ranges = self.ranges.into_iter().map(|r| r.start + offset..r.end + offset).collect();
+        Captures{text, ranges}
+    }
+
+    /// The nth capture, where 0 is the whole match
+    pub fn get(&self, i: usize) -> Option<Match<'t>> {
+        self.ranges.get(i).map(|r| Match::new(self.text, r.clone()))
+    }
+
+    /// The whole match
+    pub fn get_match(&self) -> Match<'t> {
+        Match::new(self.text, self.ranges[0].clone())
+    }
+
+    /// Number of captures, including the whole match
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    /// Always false, since there is at least the whole match
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Iterate over the captures, starting with the whole match
+    ///
+    /// ```
+    /// let m = lua_patterns::LuaPattern::new("(%a+)=(%d+)");
+    /// let cc = m.find_captures("x=1").unwrap();
+    /// let parts: Vec<_> = cc.iter().map(|c| c.as_str()).collect();
+    /// assert_eq!(parts, &["x=1", "x", "1"]);
+    /// ```
+    pub fn iter<'c>(&'c self) -> impl Iterator<Item=Match<'t>> + 'c {
+        self.ranges.iter().map(move |r| Match::new(self.text, r.clone()))
+    }
+}
+
+/// `cc[i]` is the text of the nth capture, and panics if there is no such capture
+///
+/// ```
+/// let m = lua_patterns::LuaPattern::new("(%a+)=(%d+)");
+/// let cc = m.find_captures("set x=10").unwrap();
+/// assert_eq!((&cc[0], &cc[1], &cc[2]), ("x=10", "x", "10"));
+/// ```
+impl <'t> ops::Index<usize> for Captures<'t> {
+    type Output = str;
+
+    fn index(&self, i: usize) -> &str {
+        match self.get(i) {
+            Some(m) => m.as_str(),
+            None => panic!("no capture {}", i)
+        }
+    }
+}
+
+/// Iterator over the captures of all matches, from `captures_iter`
+// 'a is for the pattern bytes, 'c for the borrow of the LuaPattern,
+// and 't for the text
+pub struct CaptureMatches<'a,'c,'t> where 'a: 'c {
+    m: &'c LuaPattern<'a>,
+    text: &'t str,
+    cursor: Cursor
+}
+
+impl <'a,'c,'t> CaptureMatches<'a,'c,'t> {
+    pub(crate) fn new(m: &'c LuaPattern<'a>, text: &'t str) -> CaptureMatches<'a,'c,'t> {
+        CaptureMatches{m, text, cursor: Cursor::new(true)}
+    }
+}
+
+impl <'a,'c,'t> Iterator for CaptureMatches<'a,'c,'t> {
+    type Item = Captures<'t>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // an anchored pattern can only match at the start
+        if self.cursor.last_end.is_some() && self.m.patt.first() == Some(&b'^') {
+            return None;
+        }
+        let s = self.text.as_bytes();
+        while self.cursor.init <= s.len() {
+            let ranges = self.m.search_at(s, self.cursor.init)?;
+            if self.cursor.advance(s, ranges[0].clone()) {
+                return Some(Captures::new(self.text, ranges));
+            }
+        }
+        None
+    }
+}
+
+/// Iterator over the text between matches, from `gaps`
+// lifetimes as for CaptureMatches
+pub struct Gaps<'a,'c,'t> where 'a: 'c {
+    matches: CaptureMatches<'a,'c,'t>,
+    text: &'t str,
+    // end of the last match
+    last: usize,
+}
+
+impl <'a,'c,'t> Gaps<'a,'c,'t> {
+    pub(crate) fn new(m: &'c LuaPattern<'a>, text: &'t str) -> Gaps<'a,'c,'t> {
+        Gaps{matches: CaptureMatches::new(m, text), text, last: 0}
+    }
+}
+
+impl <'a,'c,'t> Iterator for Gaps<'a,'c,'t> {
+    type Item = Match<'t>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for cc in &mut self.matches {
+            let all = cc.get_match();
+            let start = self.last;
+            self.last = all.end();
+            if all.start() > start {
+                return Some(Match::new(self.text, start..all.start()));
+            }
+        }
+        let start = self.last;
+        self.last = self.text.len();
+        if start < self.text.len() {
+            Some(Match::new(self.text, start..self.text.len()))
+        } else {
+            None
+        }
+    }
+}
+
+/// A piece of text from `split_keep`: either text between
+/// delimiters, or a delimiter
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Piece<'t> {
+    /// Text which is not part of any match
+    Text(Match<'t>),
+    /// A match of the pattern
+    Delimiter(Match<'t>),
+}
+
+impl <'t> Piece<'t> {
+    /// The text or delimiter itself
+    pub fn as_str(&self) -> &'t str {
+        self.get().as_str()
+    }
+
+    /// Byte range of the piece
+    pub fn range(&self) -> ops::Range<usize> {
+        self.get().range()
+    }
+
+    /// Is this a delimiter?
+    pub fn is_delimiter(&self) -> bool {
+        matches!(*self, Piece::Delimiter(_))
+    }
+
+    fn get(&self) -> Match<'t> {
+        match *self {
+            Piece::Text(m) | Piece::Delimiter(m) => m
+        }
+    }
+}
+
+/// Iterator over text and delimiters, from `split_keep`
+// lifetimes as for CaptureMatches
+pub struct SplitKeep<'a,'c,'t> where 'a: 'c {
+    matches: CaptureMatches<'a,'c,'t>,
+    text: &'t str,
+    // end of the last delimiter
+    last: usize,
+    // a delimiter which follows the text just returned
+    pending: Option<Match<'t>>,
+}
+
+impl <'a,'c,'t> SplitKeep<'a,'c,'t> {
+    pub(crate) fn new(m: &'c LuaPattern<'a>, text: &'t str) -> SplitKeep<'a,'c,'t> {
+        SplitKeep{matches: CaptureMatches::new(m, text), text, last: 0, pending: None}
+    }
+}
+
+impl <'a,'c,'t> Iterator for SplitKeep<'a,'c,'t> {
+    type Item = Piece<'t>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(delim) = self.pending.take() {
+            return Some(Piece::Delimiter(delim));
+        }
+        for cc in &mut self.matches {
+            let all = cc.get_match();
+            if all.is_empty() {
+                continue;
+            }
+            let start = self.last;
+            self.last = all.end();
+            if all.start() > start {
+                self.pending = Some(all);
+                return Some(Piece::Text(Match::new(self.text, start..all.start())));
+            }
+            return Some(Piece::Delimiter(all));
+        }
+        let start = self.last;
+        self.last = self.text.len();
+        if start < self.text.len() {
+            Some(Piece::Text(Match::new(self.text, start..self.text.len())))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_pattern() {
+        let m = LuaPattern::new("%$(%a+)");
+        let text = "hello $dolly";
+        let (a, b) = (m.find(text).unwrap(), m.find_captures(text).unwrap());
+        assert_eq!((a.start(), a.end(), a.len()), (6, 12, 6));
+        assert_eq!(b.get_match(), a);
+        assert_eq!(b.get(1).unwrap().as_str(), "dolly");
+        assert_eq!(b.get(2), None);
+        assert_eq!(b.len(), 2);
+        assert_eq!(&b[1], "dolly");
+        assert!(m.find("none").is_none());
+        assert!(m.find_captures("none").is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "no capture 2")]
+    fn missing_capture() {
+        let m = LuaPattern::new("(%a+)");
+        let cc = m.find_captures("hello").unwrap();
+        let _ = &cc[2];
+    }
+
+    #[test]
+    fn between_matches() {
+        let m = LuaPattern::new("%d+");
+        let gaps: Vec<_> = m.gaps("12ab3c45").map(|g| (g.range(), g.as_str())).collect();
+        assert_eq!(gaps, &[(2..4, "ab"), (5..6, "c")]);
+        let mut rest = m.gaps("x1");
+        assert_eq!(rest.next().map(|g| g.range()), Some(0..1));
+        assert_eq!(rest.next(), None);
+        assert_eq!(rest.next(), None);
+        assert_eq!(m.gaps("none").map(|g| g.as_str()).collect::<Vec<_>>(), &["none"]);
+        assert_eq!(LuaPattern::new("x*").gaps("axxb").map(|g| g.as_str()).collect::<Vec<_>>(), &["a", "b"]);
+        assert_eq!(m.gaps("").count(), 0);
+    }
+
+    #[test]
+    fn keeping_delimiters() {
+        let m = LuaPattern::new("[,;]%s*");
+        let pieces: Vec<_> = m.split_keep(",a, b;;c").map(|p| (p.is_delimiter(), p.as_str())).collect();
+        assert_eq!(pieces, &[(true, ","), (false, "a"), (true, ", "), (false, "b"), (true, ";"), (true, ";"), (false, "c")]);
+        let m = LuaPattern::new("%s*");
+        let pieces: Vec<_> = m.split_keep("a b").map(|p| p.range()).collect();
+        assert_eq!(pieces, &[0..1, 1..2, 2..3]);
+        assert_eq!(m.split_keep("").count(), 0);
+    }
+
+    #[test]
+    fn char_ranges() {
+        let text = "αβγ abc";
+        for i in 0..=7 {
+            for j in i..=7 {
+                let bytes = byte_range(text, i..j).unwrap();
+                assert_eq!(char_range(text, bytes), Some(i..j));
+            }
+        }
+        assert_eq!(byte_range(text, 2..8), None);
+        assert_eq!(byte_range(text, 7..7), Some(10..10));
+        assert_eq!(char_range(text, 0..11), None);
+        assert_eq!(char_range("", 0..0), Some(0..0));
+    }
+
+    #[test]
+    fn offsets() {
+        let m = LuaPattern::new("()%d+()");
+        let text = "ab 42 7";
+        let cc = m.find_captures_at(text, 5).unwrap();
+        assert_eq!(cc.get_match().range(), 6..7);
+        assert!(cc.get(1).unwrap().is_empty());
+        assert_eq!(cc.get(2).unwrap().start(), 7);
+        assert_eq!(m.find_at(text, 0).unwrap().as_str(), "42");
+        assert!(m.find_at(text, 8).is_none());
+        assert!(m.is_match(text));
+        assert!(! m.is_match("ab"));
+    }
+
+    #[test]
+    fn all_captures() {
+        let mut m = LuaPattern::new("(%a*)");
+        let text = "ab, cd";
+        let all: Vec<_> = m.captures_iter(text)
+            .map(|cc| cc.get(1).unwrap().range())
+            .collect();
+        let expected: Vec<_> = m.gmatch_ranges(text).collect();
+        assert_eq!(all, expected);
+        assert_eq!(all, &[0..2, 3..3, 4..6]);
+        let m = LuaPattern::new("^%a");
+        assert_eq!(m.captures_iter("abc").count(), 1);
+        let m = LuaPattern::new("%d");
+        assert_eq!(m.captures_iter("é1é2").map(|cc| cc.get_match().start()).collect::<Vec<_>>(), &[2, 5]);
+    }
+}
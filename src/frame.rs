@@ -0,0 +1,236 @@
+//! Extracting protocol frames from a byte stream.
+//!
+//! `FrameReader` reads into a buffer and applies a byte pattern to it,
+//! yielding each frame once it is complete and keeping any partial frame
+//! for the next read. Bytes before a match are skipped, unless the pattern
+//! is anchored with `^`, in which case frames must follow each other.
+//!
+//! A frame is complete when the pattern matches, so the pattern should end
+//! with something definite like a delimiter; "%d+" would happily match the
+//! first digits of a number which is still arriving. For header + length
+//! protocols, `with_body_length` reads a body whose length is worked out from
+//! the captures of the header.
+//!
+//! Since a frame may always be completed by the next read, data is kept
+//! until a frame is found; `with_max_buffer` limits how much, so that a
+//! stream with no frames in it is an error rather than using up memory.
+//! After each read, an unanchored pattern is only tried from the first place
+//! where a frame could still start, so skipped data is not searched again.
+//!
+//! ```
+//! use lua_patterns::LuaPattern;
+//! use lua_patterns::frame::FrameReader;
+//!
+//! // messages are "$" followed by the length, "*", and then the body
+//! let input: &[u8] = b"$5*hello$3*abc$2*x";
+//! let frames = FrameReader::new(input, LuaPattern::new("^%$(%d+)%*"))
+//!     .with_body_length(|caps| std::str::from_utf8(caps.get(1)).unwrap().parse().unwrap());
+//! let bodies: Vec<_> = frames.map(|f| f.unwrap().body).collect();
+//! assert_eq!(bodies, vec![b"hello".to_vec(), b"abc".to_vec()]);
+//! ```
+
+use std::io::{self, Read};
+use {ByteCaptures, LuaPattern};
+
+const READ_SIZE: usize = 8 * 1024;
+
+/// The most data kept while waiting for a frame, unless changed
+/// with `with_max_buffer`
+pub const DEFAULT_MAX_BUFFER: usize = 1024 * 1024;
+
+// works out the length of a body from the captures of its header
+type BodyLength<'a> = Box<dyn FnMut(&ByteCaptures) -> usize + 'a>;
+
+/// A frame found by `FrameReader`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// the whole match followed by any captures
+    pub captures: Vec<Vec<u8>>,
+    /// the bytes following the match, if there is a body length
+    pub body: Vec<u8>,
+}
+
+/// Iterator over the frames in a reader
+pub struct FrameReader<'a,R> {
+    reader: R,
+    m: LuaPattern<'a>,
+    body_length: Option<BodyLength<'a>>,
+    buf: Vec<u8>,
+    // no frame can start before this, however much more is read
+    scanned: usize,
+    max_buffer: usize,
+    eof: bool,
+}
+
+impl <'a,R: Read> FrameReader<'a,R> {
+    /// Read frames matching a byte pattern
+    pub fn new(reader: R, m: LuaPattern<'a>) -> FrameReader<'a,R> {
+        FrameReader{reader, m, body_length: None, buf: Vec::new(), scanned: 0, max_buffer: DEFAULT_MAX_BUFFER, eof: false}
+    }
+
+    /// Limit the data kept while waiting for a complete frame, which must
+    /// include any skipped bytes before it and its body. Going over the
+    /// limit is an `InvalidData` error, after which there are no more frames.
+    ///
+    /// ```
+    /// use lua_patterns::LuaPattern;
+    /// use lua_patterns::frame::FrameReader;
+    ///
+    /// let input = vec![b'x'; 100];
+    /// let mut frames = FrameReader::new(&input[..], LuaPattern::new("<.->"))
+    ///     .with_max_buffer(64);
+    /// let err = frames.next().unwrap().unwrap_err();
+    /// assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    /// assert!(frames.next().is_none());
+    /// ```
+    pub fn with_max_buffer(mut self, max: usize) -> FrameReader<'a,R> {
+        self.max_buffer = max;
+        self
+    }
+
+    /// Each frame has a body following the match, with a length given by
+    /// a function of the captures
+    pub fn with_body_length<F>(mut self, body_length: F) -> FrameReader<'a,R>
+    where F: FnMut(&ByteCaptures) -> usize + 'a {
+        self.body_length = Some(Box::new(body_length));
+        self
+    }
+
+    /// The data read but not yet part of a frame
+    pub fn remaining(&self) -> &[u8] {
+        &self.buf
+    }
+
+    // take a complete frame from the start of the buffer
+    fn take_frame(&mut self) -> Option<Frame> {
+        // an unanchored search carries on from where it could not rule out
+        // a match, rather than going over the whole buffer after each read
+        let anchored = self.m.patt.first() == Some(&b'^');
+        let found = if anchored {
+            self.m.matches_bytes(&self.buf)
+        } else {
+            let (found, open) = self.m.matches_bytes_resumable(&self.buf, self.scanned);
+            self.scanned = open;
+            found
+        };
+        // an empty match would never consume anything
+        if ! found || self.m.range().start == self.m.range().end {
+            return None;
+        }
+        let end = self.m.range().end;
+        let body_len = match self.body_length {
+            Some(ref mut f) => f(&ByteCaptures{m: &self.m, bytes: &self.buf}),
+            None => 0
+        };
+        if self.buf.len() - end < body_len {
+            return None;
+        }
+        let captures = (0..self.m.n_match).map(|i| self.buf[self.m.capture(i)].to_vec()).collect();
+        let body = self.buf[end..end + body_len].to_vec();
+        self.buf.drain(..end + body_len);
+        self.scanned = 0;
+        Some(Frame{captures, body})
+    }
+}
+
+impl <'a,R: Read> Iterator for FrameReader<'a,R> {
+    type Item = io::Result<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut chunk = [0; READ_SIZE];
+        loop {
+            if let Some(frame) = self.take_frame() {
+                return Some(Ok(frame));
+            }
+            if self.eof {
+                return None;
+            }
+            if self.buf.len() > self.max_buffer {
+                self.eof = true;
+                let msg = format!("no complete frame in {} bytes", self.buf.len());
+                return Some(Err(io::Error::new(io::ErrorKind::InvalidData, msg)));
+            }
+            match self.reader.read(&mut chunk) {
+                Ok(0) => self.eof = true,
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a reader which returns a few bytes at a time
+    struct Trickle(&'static [u8]);
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.0.len().min(buf.len()).min(3);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn delimited() {
+        let mut frames = FrameReader::new(Trickle(b"junk<a=1><bb=22><c"), LuaPattern::new("<([^>]*)>"));
+        let caps: Vec<_> = frames.by_ref().map(|f| f.unwrap().captures[1].clone()).collect();
+        assert_eq!(caps, &[b"a=1".to_vec(), b"bb=22".to_vec()]);
+        assert_eq!(frames.remaining(), b"<c");
+    }
+
+    // a reader which never ends
+    struct Endless(u8);
+
+    impl Read for Endless {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            for b in buf.iter_mut() {
+                *b = self.0;
+            }
+            Ok(buf.len())
+        }
+    }
+
+    #[test]
+    fn no_frames() {
+        let max = 4 * READ_SIZE;
+        let mut frames = FrameReader::new(Endless(b'x'), LuaPattern::new("<([^>]*)>"))
+            .with_max_buffer(max);
+        let err = frames.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(frames.next().is_none());
+        assert!(frames.remaining().len() <= max + READ_SIZE);
+        // and none of it was searched more than once
+        assert_eq!(frames.scanned, frames.remaining().len());
+        // a frame which is found is fine, even if the read goes over the limit
+        let mut frames = FrameReader::new(Trickle(b"<abc><defghijk>"), LuaPattern::new("<([^>]*)>"))
+            .with_max_buffer(6);
+        assert_eq!(frames.next().unwrap().unwrap().captures[1], b"abc");
+        assert!(frames.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn anchored() {
+        let frames = FrameReader::new(Trickle(b"A\r\nB\r\n?C\r\n"), LuaPattern::new("^%u\r\n"));
+        let found: Vec<_> = frames.map(|f| f.unwrap().captures[0].clone()).collect();
+        assert_eq!(found, &[b"A\r\n".to_vec(), b"B\r\n".to_vec()]);
+    }
+
+    #[test]
+    fn header_and_length() {
+        // a two-byte big-endian length, then the body
+        let input: &'static [u8] = b"\x00\x03abc\x00\x00\x00\x05hello\x00\x09partial";
+        let frames = FrameReader::new(Trickle(input), LuaPattern::from_bytes(b"^(..)"))
+            .with_body_length(|caps| {
+                let len = caps.get(1);
+                (len[0] as usize) << 8 | len[1] as usize
+            });
+        let bodies: Vec<_> = frames.map(|f| f.unwrap().body).collect();
+        assert_eq!(bodies, &[b"abc".to_vec(), b"".to_vec(), b"hello".to_vec()]);
+    }
+}
@@ -0,0 +1,322 @@
+//! Syntax highlighting with Lua patterns.
+//!
+//! Many editors highlight code with a list of (style, pattern) rules: at each
+//! point the rule which matches first wins, and its match is given that style.
+//! `Highlighter` does this, giving spans which do not overlap. When two rules
+//! match at the same place, the rule added first wins, so put more specific
+//! rules like keywords before general ones like identifiers.
+//!
+//! Text is highlighted one line at a time, so a rule starting with '^' only
+//! matches at the start of a line. This also means that after an edit only
+//! the lines it touches need highlighting again, which `HighlightedDocument`
+//! keeps track of for editors.
+//!
+//! ```
+//! use lua_patterns::highlight::Highlighter;
+//!
+//! #[derive(Clone, Copy, Debug, PartialEq)]
+//! enum Style { Keyword, Name, Number, Comment }
+//!
+//! let lua = Highlighter::new()
+//!     .rule(Style::Comment, "%-%-.*")
+//!     .rule(Style::Keyword, "%f[%w_]local%f[^%w_]")
+//!     .rule(Style::Name, "[%a_][%w_]*")
+//!     .rule(Style::Number, "%d+");
+//! let spans = lua.highlight_line("local x = 42 -- answer");
+//! let styled: Vec<_> = spans.iter().map(|s| (s.style, s.range.clone())).collect();
+//! assert_eq!(styled, &[
+//!     (Style::Keyword, 0..5), (Style::Name, 6..7),
+//!     (Style::Number, 10..12), (Style::Comment, 13..22),
+//! ]);
+//! ```
+
+use std::ops::Range;
+use errors::*;
+use found::Match;
+use lines::LineRanges;
+use {LuaPattern, LuaPatternOwned};
+
+/// A part of the text given a style
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct StyledSpan<S> {
+    /// The style of the rule which matched
+    pub style: S,
+    /// Byte range of the span in the text
+    pub range: Range<usize>,
+}
+
+/// An ordered list of (style, pattern) rules
+#[derive(Clone,Debug)]
+pub struct Highlighter<S> {
+    rules: Vec<(S,LuaPatternOwned)>,
+}
+
+impl <S: Clone> Default for Highlighter<S> {
+    fn default() -> Highlighter<S> {
+        Highlighter{rules: Vec::new()}
+    }
+}
+
+impl <S: Clone> Highlighter<S> {
+    /// A highlighter with no rules
+    pub fn new() -> Highlighter<S> {
+        Highlighter::default()
+    }
+
+    /// Add a rule, or report a bad pattern. Earlier rules have priority.
+    pub fn try_rule(mut self, style: S, patt: &str) -> Result<Highlighter<S>,PatternError> {
+        self.rules.push((style, LuaPatternOwned::new(patt)?));
+        Ok(self)
+    }
+
+    /// Add a rule, panicking if the pattern is bad
+    pub fn rule(self, style: S, patt: &str) -> Highlighter<S> {
+        self.try_rule(style, patt).expect("bad pattern")
+    }
+
+    /// Styled spans for a single line, in order.
+    ///
+    /// Text which no rule matches is not in any span, and
+    /// empty matches are ignored.
+    pub fn highlight_line(&self, line: &str) -> Vec<StyledSpan<S>> {
+        let mut spans = Vec::new();
+        self.spans_in(&self.patterns(), line, 0, &mut spans);
+        spans
+    }
+
+    /// Styled spans for each line of a text, with offsets in the whole text
+    ///
+    /// ```
+    /// use lua_patterns::highlight::Highlighter;
+    /// let h = Highlighter::new().rule("heading", "^#.*").rule("code", "`[^`]*`");
+    /// let spans = h.highlight("# Title\nsome `code` # not a heading\n");
+    /// let styled: Vec<_> = spans.into_iter().map(|s| (s.style, s.range)).collect();
+    /// assert_eq!(styled, &[("heading", 0..7), ("code", 13..19)]);
+    /// ```
+    pub fn highlight(&self, text: &str) -> Vec<StyledSpan<S>> {
+        let patterns = self.patterns();
+        let mut spans = Vec::new();
+        for line in LineRanges::new(text) {
+            self.spans_in(&patterns, &text[line.clone()], line.start, &mut spans);
+        }
+        spans
+    }
+
+    fn patterns(&self) -> Vec<LuaPattern<'_>> {
+        self.rules.iter().map(|r| r.1.pattern()).collect()
+    }
+
+    // add the spans of a line starting at `offset` in the text
+    fn spans_in(&self, patterns: &[LuaPattern], line: &str, offset: usize, spans: &mut Vec<StyledSpan<S>>) {
+        // the next non-empty match of each rule, if it has been looked for
+        let mut next: Vec<Option<Option<Match>>> = vec![None; patterns.len()];
+        let mut pos = 0;
+        loop {
+            let mut best: Option<(usize, Match)> = None;
+            for (i, m) in patterns.iter().enumerate() {
+                let stale = match next[i] {
+                    Some(Some(mat)) => mat.start() < pos,
+                    Some(None) => false,
+                    None => true
+                };
+                if stale {
+                    next[i] = Some(next_non_empty(m, line, pos));
+                }
+                if let Some(Some(mat)) = next[i] {
+                    let better = match best {
+                        Some((_, b)) => mat.start() < b.start(),
+                        None => true
+                    };
+                    if better {
+                        best = Some((i, mat));
+                    }
+                }
+            }
+            let (i, mat) = match best {
+                Some(best) => best,
+                None => break
+            };
+            spans.push(StyledSpan{
+                style: self.rules[i].0.clone(),
+                range: mat.start() + offset..mat.end() + offset
+            });
+            pos = mat.end();
+        }
+    }
+}
+
+// a line of a document, with its spans relative to the start of the line
+#[derive(Clone,Debug)]
+struct Line<S> {
+    start: usize,
+    spans: Vec<StyledSpan<S>>,
+}
+
+/// The highlighting of a document, which is kept up to date as it is edited
+///
+/// After each edit, only the lines which the edit touched are highlighted
+/// again; the spans of the other lines are kept, and moved if the edit
+/// changed the length of the text before them.
+///
+/// ```
+/// use lua_patterns::highlight::{Highlighter, HighlightedDocument};
+/// let h = Highlighter::new().rule("number", "%d+");
+/// let mut text = "a = 1\nb = 2\nc = 3\n".to_string();
+/// let mut doc = HighlightedDocument::new(h, &text);
+/// // the user types "0" after the "2"
+/// text.insert(11, '0');
+/// let rematched = doc.edit(&text, 11..11, 1);
+/// assert_eq!(rematched, 1);
+/// let ranges: Vec<_> = doc.spans().into_iter().map(|s| s.range).collect();
+/// assert_eq!(ranges, &[4..5, 10..12, 17..18]);
+/// ```
+#[derive(Clone,Debug)]
+pub struct HighlightedDocument<S> {
+    highlighter: Highlighter<S>,
+    lines: Vec<Line<S>>,
+    len: usize,
+    // does the last line end with a line ending?
+    terminated: bool,
+}
+
+impl <S: Clone> HighlightedDocument<S> {
+    /// Highlight all of a document
+    pub fn new(highlighter: Highlighter<S>, text: &str) -> HighlightedDocument<S> {
+        let mut doc = HighlightedDocument{highlighter, lines: Vec::new(), len: 0, terminated: true};
+        doc.edit(text, 0..0, text.len());
+        doc
+    }
+
+    /// The highlighter used for the document
+    pub fn highlighter(&self) -> &Highlighter<S> {
+        &self.highlighter
+    }
+
+    /// Number of lines in the document
+    pub fn num_lines(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// All the styled spans, with offsets in the whole text
+    pub fn spans(&self) -> Vec<StyledSpan<S>> {
+        self.lines.iter()
+            .flat_map(|line| line.spans.iter().map(move |span| StyledSpan{
+                style: span.style.clone(),
+                range: span.range.start + line.start..span.range.end + line.start
+            }))
+            .collect()
+    }
+
+    /// Update the highlighting after an edit, returning the number of lines
+    /// which were highlighted again.
+    ///
+    /// The bytes `old` of the previous text were replaced with `new_len`
+    /// bytes, giving `text`.
+    pub fn edit(&mut self, text: &str, old: Range<usize>, new_len: usize) -> usize {
+        let old_len = self.len;
+        assert!(old.start <= old.end && old.end <= old_len, "edit {:?} outside the text", old);
+        assert_eq!(text.len() + old.len(), old_len + new_len, "edit does not match the new text");
+        // where the line after each line starts; an unfinished last line
+        // runs on past the end, so that text added to it is part of it
+        let lines = &self.lines;
+        let end_of = |i: usize| match lines.get(i + 1) {
+            Some(next) => next.start,
+            None => if self.terminated {old_len} else {old_len + 1}
+        };
+        // the lines which the edit touches
+        let first = (0..lines.len()).find(|&i| end_of(i) > old.start).unwrap_or(lines.len());
+        let last = (first..lines.len()).find(|&i| end_of(i) > old.end).map_or(lines.len(), |i| i + 1);
+        let region_start = lines.get(first).map_or(old_len, |line| line.start);
+        let region_end = if last > first {end_of(last - 1).min(old_len)} else {old_len};
+        let new_region_end = region_end + text.len() - old_len;
+
+        let patterns = self.highlighter.patterns();
+        let region = &text[region_start..new_region_end];
+        let fresh: Vec<_> = LineRanges::new(region).map(|r| {
+            let mut spans = Vec::new();
+            self.highlighter.spans_in(&patterns, &region[r.clone()], 0, &mut spans);
+            Line{start: r.start + region_start, spans}
+        }).collect();
+        let rematched = fresh.len();
+        let tail = self.lines.split_off(last);
+        self.lines.truncate(first);
+        self.lines.extend(fresh);
+        self.lines.extend(tail.into_iter().map(|line| Line{start: line.start + text.len() - old_len, ..line}));
+        self.len = text.len();
+        self.terminated = text.is_empty() || text.ends_with('\n');
+        rematched
+    }
+}
+
+// the first non-empty match at or after `pos`
+fn next_non_empty<'t>(m: &LuaPattern, line: &'t str, mut pos: usize) -> Option<Match<'t>> {
+    // an anchored rule can only match at the start
+    let anchored = m.patt.first() == Some(&b'^');
+    while pos <= line.len() && ! (anchored && pos > 0) {
+        let mat = m.find_at(line, pos)?;
+        if ! mat.is_empty() {
+            return Some(mat);
+        }
+        pos = mat.start() + line[mat.start()..].chars().next().map_or(1, |c| c.len_utf8());
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn priorities() {
+        let h = Highlighter::new()
+            .rule(1, "if")
+            .rule(2, "%a+")
+            .rule(3, "%s*")
+            .rule(4, "^%s+");
+        let spans: Vec<_> = h.highlight_line("  iffy if").into_iter()
+            .map(|s| (s.style, s.range))
+            .collect();
+        // the empty "%s*" matches are ignored, and "^%s+" loses to the earlier rule
+        assert_eq!(spans, &[(3, 0..2), (1, 2..4), (2, 4..6), (3, 6..7), (1, 7..9)]);
+        assert!(h.highlight("").is_empty());
+        assert!(Highlighter::new().try_rule(0, "[a").is_err());
+        let lines = Highlighter::new().rule((), "^%a");
+        let starts: Vec<_> = lines.highlight("ab\r\ncd\n\nef").into_iter().map(|s| s.range.start).collect();
+        assert_eq!(starts, &[0, 4, 8]);
+    }
+
+    #[test]
+    fn incremental() {
+        let h = Highlighter::new().rule('k', "^%a+").rule('d', "%d+").rule('w', "%a+");
+        let mut text = String::from("abc 12\ndef\n");
+        let mut doc = HighlightedDocument::new(h.clone(), &text);
+        assert_eq!(doc.num_lines(), 2);
+        // random edits, always agreeing with highlighting from scratch
+        let pieces = ["", "x", "1", "\n", " ", "\r\n", "ab9\n", "é"];
+        let mut seed: u32 = 7;
+        let mut rand = |n: usize| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as usize % n
+        };
+        let rounds = if cfg!(miri) {50} else {500};
+        for _ in 0..rounds {
+            let boundaries: Vec<_> = (0..=text.len()).filter(|&i| text.is_char_boundary(i)).collect();
+            let a = boundaries[rand(boundaries.len())];
+            let b = boundaries[rand(boundaries.len())];
+            let (start, end) = (a.min(b), a.max(b));
+            let end = if rand(3) == 0 {end} else {start + (end - start).min(2)};
+            let end = (end..=text.len()).find(|&i| text.is_char_boundary(i)).unwrap();
+            let piece = pieces[rand(pieces.len())];
+            text.replace_range(start..end, piece);
+            doc.edit(&text, start..end, piece.len());
+            assert_eq!(doc.spans(), h.highlight(&text), "{:?}", text);
+            assert_eq!(doc.num_lines(), text.lines().count());
+        }
+        // an edit within a line only highlights that line again
+        let text = "one\ntwo\nthree";
+        let mut doc = HighlightedDocument::new(h.clone(), text);
+        assert_eq!(doc.edit("one\nto\nthree", 5..6, 0), 1);
+        assert_eq!(doc.edit("one\nto\nthree!", 12..12, 1), 1);
+        assert_eq!(doc.edit("one\nto\nthree!\n4", 13..13, 2), 2);
+    }
+}
@@ -0,0 +1,73 @@
+//! Iterator adapters for filtering and extracting with patterns.
+//!
+//! `LuaPatternIterExt` is implemented for every iterator over string slices,
+//! so pipelines over lines read from left to right:
+//!
+//! ```
+//! use lua_patterns::{LuaPattern, LuaPatternIterExt};
+//!
+//! let config = "# settings\nwidth = 80\nheight=24\n\nname = box";
+//! let setting = LuaPattern::new("^(%a+)%s*=%s*(%d+)$");
+//! let numbers: Vec<(&str, u32)> = config.lines()
+//!     .filter_map_captures(&setting, |cc| Some((cc.get(1)?.as_str(), cc[2].parse().ok()?)))
+//!     .collect();
+//! assert_eq!(numbers, &[("width", 80), ("height", 24)]);
+//! let comments = config.lines().filter_matching(&LuaPattern::new("^#")).count();
+//! assert_eq!(comments, 1);
+//! ```
+
+use found::Captures;
+use LuaPattern;
+
+/// Adapters for iterators over string slices
+pub trait LuaPatternIterExt<'t>: Iterator<Item=&'t str> + Sized {
+    /// Only the strings which the pattern matches
+    fn filter_matching<'c,'a>(self, m: &'c LuaPattern<'a>) -> FilterMatching<'a,'c,Self> {
+        FilterMatching{iter: self, m}
+    }
+
+    /// The results of `f` applied to the captures of each string which the
+    /// pattern matches, skipping those where `f` returns `None`
+    fn filter_map_captures<'c,'a,B,F>(self, m: &'c LuaPattern<'a>, f: F) -> FilterMapCaptures<'a,'c,Self,F>
+    where F: FnMut(Captures<'t>) -> Option<B> {
+        FilterMapCaptures{iter: self, m, f}
+    }
+}
+
+impl <'t,I: Iterator<Item=&'t str>> LuaPatternIterExt<'t> for I {}
+
+/// Iterator from `filter_matching`
+pub struct FilterMatching<'a,'c,I> where 'a: 'c {
+    iter: I,
+    m: &'c LuaPattern<'a>,
+}
+
+impl <'t,'a,'c,I: Iterator<Item=&'t str>> Iterator for FilterMatching<'a,'c,I> {
+    type Item = &'t str;
+
+    fn next(&mut self) -> Option<&'t str> {
+        let m = self.m;
+        self.iter.find(|s| m.is_match(s))
+    }
+}
+
+/// Iterator from `filter_map_captures`
+pub struct FilterMapCaptures<'a,'c,I,F> where 'a: 'c {
+    iter: I,
+    m: &'c LuaPattern<'a>,
+    f: F,
+}
+
+impl <'t,'a,'c,B,I,F> Iterator for FilterMapCaptures<'a,'c,I,F>
+where I: Iterator<Item=&'t str>, F: FnMut(Captures<'t>) -> Option<B> {
+    type Item = B;
+
+    fn next(&mut self) -> Option<B> {
+        for s in &mut self.iter {
+            if let Some(res) = self.m.find_captures(s).and_then(&mut self.f) {
+                return Some(res);
+            }
+        }
+        None
+    }
+}
@@ -1,16 +1,49 @@
 //! This is a Rust binding to [Lua string patterns](https://www.lua.org/pil/20.2.html),
-//! using the original code from Lua 5.2.
+//! using the original code from Lua 5.4.
 //!
 //! Although not regular expressions (they lack alternation) they are a powerful
 //! and lightweight way to process text. Please note that they are not
 //! UTF-8-aware, and in fact can process arbitrary binary data.
 //!
+//! Since an item like `.` matches a single byte, a match in a string can
+//! start or end in the middle of a character. The methods which give back
+//! string slices panic if that happens; the `_bytes` methods never do, so
+//! use them when the pattern or the text is not under your control.
+//!
 //! `LuaPattern` can be created from a string _or_ a byte slice, and has
 //! methods which are similar to the original Lua API. Please see
 //! [the README](https://github.com/stevedonovan/lua-patterns/blob/master/readme.md)
 //! for more discussion.
 //!
 //! [LuaPattern](struct.LuaPattern.html) implements the public API.
+//! The [parse](parse/index.html) module turns patterns into a syntax tree
+//! for tools which need to look inside them, and the [analyze](analyze/index.html)
+//! module uses it to warn about likely mistakes. The [extract](extract/index.html)
+//! module converts captures into typed values, and with the `derive`
+//! feature `#[derive(FromCaptures)]` fills in structs. The
+//! [patterns](patterns/index.html) module has ready-made patterns for
+//! numbers, identifiers, dates and so on, and the [logs](logs/index.html)
+//! module parses access logs, syslog and logfmt. The [datetime](datetime/index.html)
+//! module finds ISO 8601 and RFC 2822 timestamps, and the [fields](fields/index.html)
+//! module splits CSV-like data with quoted fields. With the `url` feature the
+//! [url](url/index.html) module splits URLs and query strings.
+//! [LuaAlternation](alternation/struct.LuaAlternation.html) is an extension
+//! which allows alternatives separated by `|`, and the [search](search/index.html)
+//! module finds matching lines in files and rewrites them. The
+//! [frame](frame/index.html) module extracts protocol frames from a stream.
+//! With the `mlua` feature, the [lua](lua/index.html) module works with
+//! values from an embedded Lua, and the `reference` feature adds the
+//! [reference](reference/index.html) module, for checking results against
+//! Lua's own string functions. With the `serde` feature the
+//! [de](de/index.html) module deserializes structs from captures.
+//! With the `nom` feature, the [nom_adapter](nom_adapter/index.html) module
+//! makes patterns into `nom` parsers. The `parallel` feature adds
+//! `par_gmatch_lines` and `par_gsub_lines`, which use `rayon` for large texts,
+//! and with the `tokio` feature the [stream](stream/index.html) module matches
+//! input from an `AsyncRead`.
+//!
+//! With a nightly compiler, the `nightly` feature lets patterns be used with
+//! `str::find`, `str::split` and so on; see the [searcher](searcher/index.html) module.
 //!
 //! ## Examples
 //!
@@ -41,27 +74,166 @@
 //! }
 //! ```
 
+#![cfg_attr(feature = "nightly", feature(pattern))]
+
+#[cfg(feature = "memchr")]
+extern crate memchr;
+#[cfg(feature = "mlua")]
+extern crate mlua;
+#[cfg(feature = "nom")]
+extern crate nom;
+#[cfg(feature = "parallel")]
+extern crate rayon;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(any(feature = "derive", feature = "macros"))]
+extern crate lua_patterns_derive;
+#[cfg(feature = "chrono")]
+extern crate chrono;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+#[cfg(all(test, feature = "serde"))]
+#[macro_use]
+extern crate serde_derive;
+
 use std::ops;
+use std::str;
+use std::fmt;
+use std::io;
+use std::mem;
+use std::time::{Duration,Instant};
+use std::borrow::{Borrow, Cow};
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::convert::Infallible;
+use std::ffi::{CStr, OsStr};
+use std::path::Path;
 
 pub mod errors;
+pub mod parse;
+pub mod analyze;
+pub mod alternation;
+pub mod extract;
+pub mod patterns;
+pub mod logs;
+pub mod datetime;
+pub mod fields;
+pub mod search;
+pub mod template;
+pub mod frame;
+pub mod cache;
+pub mod pipeline;
+pub mod found;
+pub mod highlight;
+pub mod iter_ext;
+pub mod lines;
+pub use alternation::LuaAlternation;
+pub use cache::PatternCache;
+pub use pipeline::GsubPipeline;
+pub use extract::{FromCapture, FromCaptures};
+pub use iter_ext::LuaPatternIterExt;
+#[cfg(feature = "derive")]
+pub use lua_patterns_derive::FromCaptures;
+#[cfg(feature = "macros")]
+pub use lua_patterns_derive::lua_pat;
+#[cfg(feature = "mlua")]
+pub mod lua;
+#[cfg(feature = "reference")]
+pub mod reference;
+#[cfg(feature = "serde")]
+mod serde_impls;
+#[cfg(feature = "serde")]
+pub mod de;
+#[cfg(feature = "nom")]
+pub mod nom_adapter;
+#[cfg(feature = "nightly")]
+pub mod searcher;
+#[cfg(feature = "parallel")]
+mod parallel;
+#[cfg(feature = "tokio")]
+pub mod stream;
+#[cfg(feature = "url")]
+pub mod url;
 use errors::*;
+use errors::PatternErrorKind::*;
 mod luapat;
 use luapat::*;
+mod scan;
 
 
 /// Represents a Lua string pattern and the results of a match
+///
+/// Patterns compare and hash by their source, so they can be used as map keys;
+/// a clone has its own copy of the results of the last match.
+///
+/// ```
+/// let mut m = lua_patterns::LuaPattern::new("(%a+)");
+/// assert!(m.matches(" hello "));
+/// let c = m.clone();
+/// assert_eq!(c.range(), 1..6);
+/// assert!(c == lua_patterns::LuaPattern::new("(%a+)"));
+/// assert_eq!(format!("{:?}", c), "LuaPattern(\"(%a+)\")");
+/// ```
+#[derive(Clone)]
 pub struct LuaPattern<'a> {
     patt: &'a [u8],
-    matches: [LuaMatch; LUA_MAXCAPTURES],
-    n_match: usize
+    matches: Vec<LuaMatch>,
+    n_match: usize,
+    limits: Limits,
+    prescan: Prescan,
 }
 
 impl <'a> LuaPattern<'a> {
     /// Maybe create a new Lua pattern from a slice of bytes
+    ///
+    /// The pattern is checked here, as with `new_try`, so that
+    /// matching does not fail later.
+    ///
+    /// ```
+    /// let err = lua_patterns::LuaPattern::from_bytes_try(b"\xFF(%d+").unwrap_err();
+    /// assert_eq!(err.message(), "unfinished capture");
+    /// assert_eq!(err.position(), Some(1));
+    /// let mut m = lua_patterns::LuaPattern::from_bytes_try(b"\xFF(%d+)").unwrap();
+    /// assert_eq!(m.match_maybe_bytes(b"\x00\xFF42"), Some(&b"42"[..]));
+    /// ```
     pub fn from_bytes_try (bytes: &'a [u8]) -> Result<LuaPattern<'a>,PatternError> {
-        str_check(bytes)?;
-        let matches = [LuaMatch{start: 0, end: 0}; LUA_MAXCAPTURES];
-        Ok(LuaPattern{patt: bytes, matches: matches, n_match: 0})
+        LuaPattern::from_bytes_with_max_captures(bytes, LUA_MAXCAPTURES)
+    }
+
+    /// Maybe create a new Lua pattern from a slice of bytes, allowing
+    /// up to `max_captures` captures rather than the usual 32
+    pub fn from_bytes_with_max_captures (bytes: &'a [u8], max_captures: usize) -> Result<LuaPattern<'a>,PatternError> {
+        str_check(bytes, max_captures)?;
+        let matches = vec![LuaMatch{start: 0, end: 0}; max_captures + 1];
+        let limits = Limits{max_captures, ..Limits::default()};
+        let prescan = Prescan::new(bytes);
+        Ok(LuaPattern{patt: bytes, matches, n_match: 0, limits, prescan})
+    }
+
+    // a pattern already known to pass `str_check` with the usual captures
+    fn checked(bytes: &'a [u8]) -> LuaPattern<'a> {
+        let matches = vec![LuaMatch{start: 0, end: 0}; LUA_MAXCAPTURES + 1];
+        LuaPattern{patt: bytes, matches, n_match: 0, limits: Limits::default(), prescan: Prescan::new(bytes)}
+    }
+
+    /// Maybe create a new Lua pattern from a string, allowing
+    /// up to `max_captures` captures rather than the usual 32
+    ///
+    /// This is useful for machine-generated patterns.
+    ///
+    /// ```
+    /// let patt = "(%a)".repeat(40);
+    /// assert!(lua_patterns::LuaPattern::new_try(&patt).is_err());
+    /// let mut m = lua_patterns::LuaPattern::new_with_max_captures(&patt, 40).unwrap();
+    /// let text = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMN";
+    /// assert!(m.matches(text));
+    /// assert_eq!(&text[m.capture(40)], "N");
+    /// ```
+    pub fn new_with_max_captures(patt: &'a str, max_captures: usize) -> Result<LuaPattern<'a>,PatternError> {
+        LuaPattern::from_bytes_with_max_captures(patt.as_bytes(), max_captures)
     }
 
     /// Maybe create a new Lua pattern from a string
@@ -79,6 +251,38 @@ impl <'a> LuaPattern<'a> {
         LuaPattern::from_bytes_try(bytes).expect("bad pattern")
     }
 
+    /// Maybe create a new Lua pattern from a C string, such as one
+    /// passed across an FFI boundary. The terminating nul is not part
+    /// of the pattern.
+    pub fn from_cstr_try (patt: &'a CStr) -> Result<LuaPattern<'a>,PatternError> {
+        LuaPattern::from_bytes_try(patt.to_bytes())
+    }
+
+    /// Create a new Lua pattern from a C string, panicking if bad
+    pub fn from_cstr (patt: &'a CStr) -> LuaPattern<'a> {
+        LuaPattern::from_cstr_try(patt).expect("bad pattern")
+    }
+
+    /// A human-readable description of the pattern, one item per line
+    ///
+    /// Useful for debugging, and for showing to people who provide
+    /// patterns in configuration files.
+    ///
+    /// ```
+    /// let m = lua_patterns::LuaPattern::new("(%a+)=(%d+)");
+    /// assert_eq!(m.explain(), "\
+    /// capture 1: one or more letters
+    /// literal '='
+    /// capture 2: one or more digits
+    /// ");
+    /// ```
+    pub fn explain(&self) -> String {
+        match parse::parse(self.patt) {
+            Ok(patt) => patt.explain(),
+            Err(e) => format!("invalid pattern: {}\n", e)
+        }
+    }
+
     /// Match a slice of bytes with a pattern
     ///
     /// ```
@@ -89,12 +293,160 @@ impl <'a> LuaPattern<'a> {
     /// assert_eq!(&bytes[m.range()], &[0xFE,0xEE,0xEE,0xED]);
     /// ```
     pub fn matches_bytes(&mut self, s: &[u8]) -> bool {
-        self.n_match = str_match(s,self.patt,&mut self.matches).expect("Should not fail - report as bug");
-        self.n_match > 0
+        self.matches_bytes_at(s,0,false)
+    }
+
+    /// Limit the number of steps the matcher may take for each match.
+    ///
+    /// A pathological pattern can take a very long time to fail on
+    /// crafted input, so this is useful for patterns from untrusted
+    /// sources. A match that runs out of steps is treated as failed,
+    /// unless you use `try_matches`, which reports the error.
+    /// `None` (the default) means no limit.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("(.-)%s*$");
+    /// m.set_step_limit(Some(1000));
+    /// let text = format!("x{}x", " ".repeat(1000));
+    /// assert!(m.try_matches(&text).unwrap_err().is_step_limit());
+    /// assert!(! m.matches(&text));
+    /// assert_eq!(m.try_matches("hello  "), Ok(true));
+    /// ```
+    pub fn set_step_limit(&mut self, steps: Option<usize>) {
+        self.limits.max_steps = steps;
+    }
+
+    /// Match a slice of bytes with a pattern, reporting errors
+    /// such as running out of steps.
+    ///
+    /// Like Lua, the matcher limits how deeply it recurses, so very deeply
+    /// nested patterns fail with an error where `is_too_complex` is true.
+    /// `matches` treats this as no match.
+    pub fn try_matches_bytes(&mut self, s: &[u8]) -> Result<bool,PatternError> {
+        let patt = self.patt;
+        self.try_matches_patt(s,patt,0,false)
+    }
+
+    /// Match a string with a pattern, reporting errors
+    /// such as running out of steps.
+    pub fn try_matches(&mut self, text: &str) -> Result<bool,PatternError> {
+        self.try_matches_bytes(text.as_bytes())
+    }
+
+    /// Match a slice of bytes with a pattern, giving up with an error
+    /// if the match takes longer than `timeout`.
+    pub fn matches_bytes_with_deadline(&mut self, s: &[u8], timeout: Duration) -> Result<bool,PatternError> {
+        let saved = self.limits;
+        self.limits.deadline = Some(Instant::now() + timeout);
+        let res = self.try_matches_bytes(s);
+        self.limits = saved;
+        res
+    }
+
+    /// Match a string with a pattern, giving up with an error
+    /// if the match takes longer than `timeout`.
+    ///
+    /// The clock is checked regularly as the matcher works, so
+    /// this is suitable for keeping the latency of request handlers
+    /// under control. Use `is_timeout` to recognize the error.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// let mut m = lua_patterns::LuaPattern::new("(.-)%s*$");
+    /// let res = m.matches_with_deadline("hello  ", Duration::from_secs(1));
+    /// assert_eq!(res, Ok(true));
+    /// let text = format!("x{}x", " ".repeat(5000));
+    /// let res = m.matches_with_deadline(&text, Duration::from_secs(0));
+    /// assert!(res.unwrap_err().is_timeout());
+    /// ```
+    pub fn matches_with_deadline(&mut self, text: &str, timeout: Duration) -> Result<bool,PatternError> {
+        self.matches_bytes_with_deadline(text.as_bytes(), timeout)
+    }
+
+    // match starting at `init`, optionally only at `init`, using a
+    // pattern which may differ from the original (e.g. extra anchors)
+    fn try_matches_patt(&mut self, s: &[u8], patt: &[u8], init: usize, anchored: bool) -> Result<bool,PatternError> {
+        let res = if patt == self.patt {
+            str_match_at(s,patt,&self.prescan,init,anchored,self.limits,&mut self.matches)
+        } else {
+            str_match_at(s,patt,&Prescan::new(patt),init,anchored,self.limits,&mut self.matches)
+        };
+        self.set_matched(res)
+    }
+
+    // record the number of captures found by a match
+    fn set_matched(&mut self, res: Result<usize,PatternError>) -> Result<bool,PatternError> {
+        let stale = self.n_match;
+        self.n_match = *res.as_ref().unwrap_or(&0);
+        if self.n_match == 0 {
+            // don't leave the results of an earlier match lying around
+            for m in &mut self.matches[..stale] {
+                *m = LuaMatch{start: 0, end: 0};
+            }
+        }
+        Ok(res? > 0)
+    }
+
+    // match starting at `init`, also giving the first position where a match
+    // could start if more bytes were added to `s`, so that a search can carry
+    // on from there as more arrive. Errors are no match, and no progress.
+    pub(crate) fn matches_bytes_resumable(&mut self, s: &[u8], init: usize) -> (bool,usize) {
+        let mut open = init;
+        let res = str_match_resumable(s,self.patt,&self.prescan,init,false,self.limits,&mut self.matches)
+            .map(|(n, o)| { open = o; n });
+        (or_no_match(self.set_matched(res)), open)
+    }
+
+    fn matches_patt(&mut self, s: &[u8], patt: &[u8], init: usize, anchored: bool) -> bool {
+        or_no_match(self.try_matches_patt(s,patt,init,anchored))
+    }
+
+    // match starting at `init`, optionally only at `init`
+    fn matches_bytes_at(&mut self, s: &[u8], init: usize, anchored: bool) -> bool {
+        let patt = self.patt;
+        self.matches_patt(s,patt,init,anchored)
+    }
+
+    // match the last position in the string where the pattern matches
+    fn matches_last(&mut self, text: &str) -> bool {
+        let s = text.as_bytes();
+        if self.patt.first() == Some(&b'^') {
+            // an anchored pattern can only match at the start
+            return self.matches_bytes_at(s,0,true);
+        }
+        // a forward scan finds the first match, so that text with no match
+        // is only scanned once, and the backward scan can stop there
+        if ! self.matches_bytes_at(s,0,false) {
+            return false;
+        }
+        let first = self.range().start;
+        for i in (first..=s.len()).rev() {
+            if text.is_char_boundary(i) && self.matches_bytes_at(s,i,true) {
+                return true;
+            }
+        }
+        false
+    }
+
+    // the pattern with '$' appended, unless already anchored at the end
+    fn end_anchored(&self) -> Vec<u8> {
+        let mut patt = self.patt.to_vec();
+        // the parser knows when a final '$' is part of an item like `%b$$`
+        let anchored = match parse::parse(self.patt) {
+            Ok(parsed) => parsed.end_anchored,
+            Err(_) => false
+        };
+        if ! anchored {
+            patt.push(b'$');
+        }
+        patt
     }
 
     /// Match a string with a pattern
     ///
+    /// Hitting the step limit, time limit or recursion depth counts as
+    /// no match here; use `try_matches` to tell these errors apart.
+    ///
     /// ```
     /// let mut m = lua_patterns::LuaPattern::new("(%a+) one");
     /// let text = " hello one two";
@@ -104,8 +456,90 @@ impl <'a> LuaPattern<'a> {
         self.matches_bytes(text.as_bytes())
     }
 
+    /// Match a C string (or a `CString`), not including its terminating nul
+    ///
+    /// ```
+    /// use std::ffi::CString;
+    /// let mut m = lua_patterns::LuaPattern::new("^%d+$");
+    /// assert!(m.matches_cstr(&CString::new("42").unwrap()));
+    /// ```
+    pub fn matches_cstr(&mut self, s: &CStr) -> bool {
+        self.matches_bytes(s.to_bytes())
+    }
+
+    /// Match a slice of bytes only if the pattern matches all of it
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::from_bytes(&[0x01,b'+']);
+    /// assert!(m.matches_bytes_full(&[0x01,0x01]));
+    /// assert!(! m.matches_bytes_full(&[0x01,0x01,0x02]));
+    /// ```
+    pub fn matches_bytes_full(&mut self, s: &[u8]) -> bool {
+        let patt = self.end_anchored();
+        self.matches_patt(s,&patt,0,true)
+    }
+
+    /// Match a string only if the pattern matches all of it
+    ///
+    /// This is like wrapping the pattern in `^...$`, which is
+    /// usually what you want for validation.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("[%a_][%w_]*");
+    /// assert!(m.matches_full("hello_dolly"));
+    /// assert!(! m.matches_full("hello dolly"));
+    /// assert!(m.matches("hello dolly"));
+    /// ```
+    pub fn matches_full(&mut self, text: &str) -> bool {
+        self.matches_bytes_full(text.as_bytes())
+    }
+
+    /// Match an OS string, such as a file name or environment variable.
+    ///
+    /// This matches the raw bytes on Unix, and on Windows the WTF-8
+    /// encoding used by `OsStr::as_encoded_bytes`, which is UTF-8 for valid
+    /// Unicode. So there is no lossy conversion, and ranges refer to those bytes.
+    ///
+    /// ```
+    /// use std::ffi::OsStr;
+    /// let mut m = lua_patterns::LuaPattern::new("^LC_");
+    /// assert!(m.matches_os(OsStr::new("LC_ALL")));
+    /// ```
+    pub fn matches_os(&mut self, s: &OsStr) -> bool {
+        self.matches_bytes(s.as_encoded_bytes())
+    }
+
+    /// Match a whole path, as for `matches_os`
+    ///
+    /// ```
+    /// use std::path::Path;
+    /// let mut m = lua_patterns::LuaPattern::new("^/tmp/");
+    /// assert!(m.matches_path(Path::new("/tmp/x.log")));
+    /// ```
+    pub fn matches_path(&mut self, path: &Path) -> bool {
+        self.matches_os(path.as_os_str())
+    }
+
+    /// Match the file name of a path, as for `matches_os`.
+    /// Paths without a file name (like "/" or "..") do not match.
+    ///
+    /// ```
+    /// use std::path::Path;
+    /// let mut m = lua_patterns::LuaPattern::new("%.rs$");
+    /// assert!(m.matches_file_name(Path::new("src/lib.rs")));
+    /// assert!(! m.matches_file_name(Path::new("lib.rs/data")));
+    /// ```
+    pub fn matches_file_name(&mut self, path: &Path) -> bool {
+        match path.file_name() {
+            Some(name) => self.matches_os(name),
+            None => false
+        }
+    }
+
     /// Match a string, returning first capture if successful
     ///
+    /// Panics if a capture splits a UTF-8 character, as `.` can; see the crate docs.
+    ///
     /// ```
     /// let mut m = lua_patterns::LuaPattern::new("OK%s+(%d+)");
     /// let res = m.match_maybe("and that's OK 400 to you");
@@ -119,8 +553,61 @@ impl <'a> LuaPattern<'a> {
         }
     }
 
+    /// Match a slice of bytes, returning first capture if successful
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::from_bytes(b"\xFF\xD8(..)");
+    /// let res = m.match_maybe_bytes(&[0x00,0xFF,0xD8,0xFF,0xE0]);
+    /// assert_eq!(res, Some(&[0xFF,0xE0][..]));
+    /// assert_eq!(m.match_maybe_bytes(b"GIF89a"), None);
+    /// ```
+    pub fn match_maybe_bytes<'t>(&mut self, bytes: &'t [u8]) -> Option<&'t [u8]> {
+        if self.matches_bytes(bytes) {
+            Some(&bytes[self.first_capture()])
+        } else {
+            None
+        }
+    }
+
+    /// Find the last match in a string, returning its range if successful
+    ///
+    /// The last match is the one starting furthest to the right, as
+    /// with the Lua idiom `s:find(".*()pat")`. The captures are
+    /// available afterwards, just as with `matches`.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("%$(%a+)");
+    /// let text = "$one $two three";
+    /// assert_eq!(m.rfind(text), Some(5..9));
+    /// assert_eq!(&text[m.capture(1)], "two");
+    /// ```
+    pub fn rfind(&mut self, text: &str) -> Option<ops::Range<usize>> {
+        if self.matches_last(text) {
+            Some(self.range())
+        } else {
+            None
+        }
+    }
+
+    /// Find the last match in a string, returning first capture if successful
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("ERROR (%d+)");
+    /// let res = m.match_last("ERROR 10, ERROR 20, WARN 30");
+    /// assert_eq!(res, Some("20"));
+    /// ```
+    pub fn match_last<'t>(&mut self, text: &'t str) -> Option<&'t str> {
+        if self.matches_last(text) {
+            Some(&text[self.first_capture()])
+        } else {
+            None
+        }
+    }
+
     /// Match a string, returning first two explicit captures if successful
     ///
+    /// Panics if a capture splits a UTF-8 character, as `.` can; see the crate docs.
+    ///
     /// ```
     /// let mut p = lua_patterns::LuaPattern::new("%s*(%d+)%s+(%S+)");
     /// let (int,rest) = p.match_maybe_2(" 233   hello dolly").unwrap();
@@ -139,6 +626,8 @@ impl <'a> LuaPattern<'a> {
 
     /// Match a string, returning first three explicit captures if successful
     ///
+    /// Panics if a capture splits a UTF-8 character, as `.` can; see the crate docs.
+    ///
     /// ```
     /// let mut p = lua_patterns::LuaPattern::new("(%d+)/(%d+)/(%d+)");
     /// let (y,m,d) = p.match_maybe_3("2017/11/10").unwrap();
@@ -156,8 +645,128 @@ impl <'a> LuaPattern<'a> {
         }
     }
 
+    /// Match a string, converting the explicit captures into a tuple
+    ///
+    /// Each capture may become a string slice, a `String`, a number and so
+    /// on; see the [extract](extract/index.html) module. The match fails if
+    /// the number of captures is different, or if a conversion fails.
+    ///
+    /// ```
+    /// let mut p = lua_patterns::LuaPattern::new("(%d+)/(%d+)/(%d+)");
+    /// let (y,m,d): (u32,u8,u8) = p.match_tuple("2017/11/10").unwrap();
+    /// assert_eq!((y,m,d), (2017,11,10));
+    /// ```
+    pub fn match_tuple<'t,T: FromCaptures<'t>>(&mut self, text: &'t str) -> Option<T> {
+        if self.matches(text) {
+            T::from_captures(&self.match_captures(text))
+        } else {
+            None
+        }
+    }
+
+    /// Match a string, converting the captures into any `FromCaptures` type
+    ///
+    /// This is like `match_tuple`, but also works with structs using
+    /// `#[derive(FromCaptures)]` (with the `derive` feature).
+    ///
+    /// ```
+    /// let mut p = lua_patterns::LuaPattern::new("(%a+)=(%d+)");
+    /// let (key,value) = p.extract::<(String,i64)>("x=-1 y=20").unwrap();
+    /// assert_eq!((key.as_str(),value), ("y",20));
+    /// ```
+    pub fn extract<'t,T: FromCaptures<'t>>(&mut self, text: &'t str) -> Option<T> {
+        self.match_tuple(text)
+    }
+
+    /// Split a string into the parts before, at and after the first match
+    ///
+    /// Panics if a match splits a UTF-8 character, as `.` can; see the crate docs.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("%s*=%s*");
+    /// let (key,eq,value) = m.partition("name = dolly = fine").unwrap();
+    /// assert_eq!((key,eq,value), ("name"," = ","dolly = fine"));
+    /// ```
+    pub fn partition<'t>(&mut self, text: &'t str) -> Option<(&'t str,&'t str,&'t str)> {
+        if self.matches(text) {
+            let r = self.range();
+            Some((&text[..r.start],&text[r.clone()],&text[r.end..]))
+        } else {
+            None
+        }
+    }
+
+    /// Split a string into the parts before, at and after the last match
+    ///
+    /// The last match is the one starting furthest to the right, as
+    /// with the Lua idiom `s:find(".*()pat")`.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("/");
+    /// let (dir,sep,file) = m.rpartition("/usr/local/bin").unwrap();
+    /// assert_eq!((dir,sep,file), ("/usr/local","/","bin"));
+    /// ```
+    pub fn rpartition<'t>(&mut self, text: &'t str) -> Option<(&'t str,&'t str,&'t str)> {
+        if self.matches_last(text) {
+            let r = self.range();
+            Some((&text[..r.start],&text[r.clone()],&text[r.end..]))
+        } else {
+            None
+        }
+    }
+
+    /// Remove a match at the start of a string, if there is one
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("%d+%.%s*");
+    /// assert_eq!(m.strip_prefix("10. hello"), Some("hello"));
+    /// assert_eq!(m.strip_prefix("hello 10."), None);
+    /// ```
+    pub fn strip_prefix<'t>(&mut self, text: &'t str) -> Option<&'t str> {
+        if self.matches_bytes_at(text.as_bytes(),0,true) {
+            Some(&text[self.range().end..])
+        } else {
+            None
+        }
+    }
+
+    /// Remove a match at the end of a string, if there is one
+    ///
+    /// The longest such match is removed.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("%s*;+");
+    /// assert_eq!(m.strip_suffix("hello ;;"), Some("hello"));
+    /// assert_eq!(m.strip_suffix("hello; dolly"), None);
+    /// ```
+    pub fn strip_suffix<'t>(&mut self, text: &'t str) -> Option<&'t str> {
+        let patt = self.end_anchored();
+        if self.matches_patt(text.as_bytes(),&patt,0,false) {
+            Some(&text[..self.range().start])
+        } else {
+            None
+        }
+    }
+
+    /// Remove a match at the start and a match at the end of a string
+    ///
+    /// Unlike `strip_prefix` and `strip_suffix`, this always returns
+    /// a slice, which is the original text if neither end matches.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("[%s%-]+");
+    /// assert_eq!(m.trim_matches("-- hello dolly --"), "hello dolly");
+    /// assert_eq!(m.trim_matches("hello"), "hello");
+    /// ```
+    pub fn trim_matches<'t>(&mut self, text: &'t str) -> &'t str {
+        let text = self.strip_prefix(text).unwrap_or(text);
+        self.strip_suffix(text).unwrap_or(text)
+    }
+
     /// Match and collect all captures as a vector of string slices
     ///
+    /// Panics if a capture splits a UTF-8 character, as `.` can; see the crate docs.
+    ///
     /// ```
     /// let mut m = lua_patterns::LuaPattern::new("(one).+");
     /// assert_eq!(m.captures(" one two"), &["one two","one"]);
@@ -180,7 +789,21 @@ impl <'a> LuaPattern<'a> {
     /// }
     /// ```
     pub fn match_captures<'b,'c>(&'c self, text: &'b str) -> Captures<'a,'b,'c> {
-        Captures {m: self, text: text}
+        Captures {m: self, text}
+    }
+
+    /// Access the captures of a byte match with no allocation
+    ///
+    /// ```rust
+    /// let bytes = &[0x00,0x01,0x02];
+    /// let mut m = lua_patterns::LuaPattern::from_bytes(&[b'(',0x01,b')']);
+    /// if m.matches_bytes(bytes) {
+    ///     let cc = m.match_captures_bytes(bytes);
+    ///     assert_eq!(cc.get(1), &[0x01]);
+    /// }
+    /// ```
+    pub fn match_captures_bytes<'b,'c>(&'c self, bytes: &'b [u8]) -> ByteCaptures<'a,'b,'c> {
+        ByteCaptures {m: self, bytes}
     }
 
     /// Match and collect all captures into the provided vector.
@@ -202,13 +825,88 @@ impl <'a> LuaPattern<'a> {
         self.n_match > 0
     }
 
+    /// Match bytes and collect all captures into the provided vector.
+    ///
+    /// The vector can be reused for each match, so there is no allocation
+    /// once it is big enough.
+    ///
+    /// ```rust
+    /// let mut m = lua_patterns::LuaPattern::from_bytes(b"\x7E(.)(.)");
+    /// let mut v = Vec::new();
+    /// for packet in &[&[0x7E,0x01,0x02][..], &[0x00,0x7E,0x03,0x04]] {
+    ///     assert!(m.capture_bytes_into(packet, &mut v));
+    ///     assert_eq!(v.len(), 3);
+    /// }
+    /// assert_eq!(v[1], &[0x03]);
+    /// ```
+    pub fn capture_bytes_into<'b>(&mut self, bytes: &'b [u8], vec: &mut Vec<&'b [u8]>) -> bool {
+        self.matches_bytes(bytes);
+        vec.clear();
+        for i in 0..self.n_match {
+            vec.push(&bytes[self.capture(i)]);
+        }
+        self.n_match > 0
+    }
+
+    /// Match bytes and collect the ranges of all captures into the provided vector.
+    ///
+    /// Unlike `capture_bytes_into`, the vector does not borrow the bytes.
+    ///
+    /// ```rust
+    /// let mut m = lua_patterns::LuaPattern::from_bytes(b"\x7E(.)(.)");
+    /// let mut v = Vec::new();
+    /// assert!(m.capture_ranges_into(&[0x00,0x7E,0x03,0x04], &mut v));
+    /// assert_eq!(v, &[1..4, 2..3, 3..4]);
+    /// ```
+    pub fn capture_ranges_into(&mut self, bytes: &[u8], vec: &mut Vec<ops::Range<usize>>) -> bool {
+        self.matches_bytes(bytes);
+        vec.clear();
+        vec.extend((0..self.n_match).map(|i| self.capture(i)));
+        self.n_match > 0
+    }
+
+    /// The ranges of all captures of the last match, starting with the whole match.
+    ///
+    /// This is empty if the last match failed.
+    ///
+    /// ```rust
+    /// let mut m = lua_patterns::LuaPattern::new("(%a+)=(%d+)");
+    /// let text = "set x=10";
+    /// assert!(m.matches(text));
+    /// let ranges = m.capture_ranges();
+    /// assert_eq!(ranges, &[4..8, 4..5, 6..8]);
+    /// assert_eq!(&text[ranges[2].clone()], "10");
+    /// assert!(! m.matches("none"));
+    /// assert!(m.capture_ranges().is_empty());
+    /// ```
+    pub fn capture_ranges(&self) -> Vec<ops::Range<usize>> {
+        (0..self.n_match).map(|i| self.capture(i)).collect()
+    }
+
     /// The full match (same as `capture(0)`)
+    ///
+    /// After a failed match this is the empty range `0..0`, as are
+    /// all the captures; `try_capture(0)` tells the two cases apart.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("(%d+)");
+    /// assert!(m.matches("a 42"));
+    /// assert_eq!(m.range(), 2..4);
+    /// assert!(! m.matches("none"));
+    /// assert_eq!(m.range(), 0..0);
+    /// assert_eq!(m.capture(1), 0..0);
+    /// ```
     pub fn range(&self) -> ops::Range<usize> {
         self.capture(0)
     }
 
     /// Get the nth capture of the match.
     ///
+    /// This is only meaningful after a successful match, for `i` no more
+    /// than the number of captures. Otherwise the range is empty, and `i`
+    /// beyond the maximum number of captures panics; use `try_capture`
+    /// when unsure.
+    ///
     /// ```
     /// let mut m = lua_patterns::LuaPattern::new("(%a+) one");
     /// let text = " hello one two";
@@ -218,8 +916,30 @@ impl <'a> LuaPattern<'a> {
     /// ```
     pub fn capture(&self, i: usize) -> ops::Range<usize> {
         ops::Range{
-            start: self.matches[i].start as usize,
-            end: self.matches[i].end as usize
+            start: self.matches[i].start,
+            end: self.matches[i].end
+        }
+    }
+
+    /// Get the nth capture of the match, if there is one.
+    ///
+    /// This is `None` if the last match failed, or if the pattern
+    /// has fewer captures.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("(%a+) one");
+    /// assert!(m.matches(" hello one two"));
+    /// assert_eq!(m.try_capture(1), Some(1..6));
+    /// assert_eq!(m.try_capture(2), None);
+    /// assert_eq!(m.try_capture(100), None);
+    /// assert!(! m.matches("two"));
+    /// assert_eq!(m.try_capture(0), None);
+    /// ```
+    pub fn try_capture(&self, i: usize) -> Option<ops::Range<usize>> {
+        if i < self.n_match {
+            Some(self.capture(i))
+        } else {
+            None
         }
     }
 
@@ -232,35 +952,355 @@ impl <'a> LuaPattern<'a> {
         self.capture(idx)
     }
 
-    /// An iterator over all matches in a string.
+    /// Does the pattern match anywhere in the string?
     ///
-    /// The matches are returned as string slices; if there are no
-    /// captures the full match is used, otherwise the first capture.
-    /// That is, this example will also work with the pattern "(%S+)".
+    /// Unlike `matches` this does not keep the results, so it only needs `&self`.
+    pub fn is_match(&self, text: &str) -> bool {
+        self.with_match_buffer(|mm| {
+            let res = str_match_at(text.as_bytes(),self.patt,&self.prescan,0,false,self.limits,mm);
+            or_no_match(res.map(|n| n > 0))
+        })
+    }
+
+    /// Turn the pattern into a predicate, for `Iterator::filter`,
+    /// `Vec::retain` and the like.
+    ///
+    /// As with `is_match`, calling it does not allocate.
     ///
     /// ```
-    /// let mut m = lua_patterns::LuaPattern::new("%S+");
-    /// let split: Vec<_> = m.gmatch("dog  cat leopard wolf").collect();
-    /// assert_eq!(split,&["dog","cat","leopard","wolf"]);
+    /// let mut lines = vec!["# comment", "key = 1", "", "other = 2"];
+    /// lines.retain(lua_patterns::LuaPattern::new("^%a+%s*=").into_match_fn());
+    /// assert_eq!(lines, &["key = 1", "other = 2"]);
+    /// let is_number = lua_patterns::LuaPattern::new("^%d+$").into_match_fn();
+    /// let count = "1 two 3".split(' ').filter(is_number).count();
+    /// assert_eq!(count, 2);
     /// ```
-    pub fn gmatch<'b,'c>(&'c mut self, text: &'b str) -> GMatch<'a,'b,'c> {
-        GMatch{m: self, text: text}
+    pub fn into_match_fn<T: AsRef<str> + ?Sized>(self) -> impl Fn(&T) -> bool + 'a {
+        move |text| self.is_match(text.as_ref())
+    }
+
+    /// Find the first match in a string, without keeping the results
+    /// in the pattern.
+    ///
+    /// ```
+    /// let m = lua_patterns::LuaPattern::new("%d+");
+    /// let mat = m.find("room 101").unwrap();
+    /// assert_eq!(mat.as_str(), "101");
+    /// assert_eq!(mat.range(), 5..8);
+    /// ```
+    pub fn find<'t>(&self, text: &'t str) -> Option<found::Match<'t>> {
+        self.find_at(text, 0)
+    }
+
+    /// Find the first match starting at or after the byte offset `start`.
+    ///
+    /// The match is relative to the whole string, so anchors like '^'
+    /// only match at `start`.
+    pub fn find_at<'t>(&self, text: &'t str, start: usize) -> Option<found::Match<'t>> {
+        self.search_at(text.as_bytes(), start)
+            .map(|ranges| found::Match::new(text, ranges[0].clone()))
+    }
+
+    /// Find the first match in a string and return all of its captures,
+    /// starting with the whole match.
+    ///
+    /// ```
+    /// let m = lua_patterns::LuaPattern::new("(%a+)=(%d+)");
+    /// let cc = m.find_captures("set x=10").unwrap();
+    /// assert_eq!(cc.get(0).unwrap().as_str(), "x=10");
+    /// assert_eq!(cc.get(2).unwrap().range(), 6..8);
+    /// ```
+    pub fn find_captures<'t>(&self, text: &'t str) -> Option<found::Captures<'t>> {
+        self.find_captures_at(text, 0)
+    }
+
+    /// Find the captures of the first match starting at or after the byte offset `start`.
+    pub fn find_captures_at<'t>(&self, text: &'t str, start: usize) -> Option<found::Captures<'t>> {
+        self.search_at(text.as_bytes(), start)
+            .map(|ranges| found::Captures::new(text, ranges))
+    }
+
+    /// An iterator over the captures of all matches in a string.
+    ///
+    /// Each item owns its ranges, which are offsets in the whole string,
+    /// and the pattern is only borrowed immutably, so items can be
+    /// collected and kept.
+    ///
+    /// ```
+    /// let m = lua_patterns::LuaPattern::new("(%a+)=(%d+)");
+    /// let all: Vec<_> = m.captures_iter("x=1, y=22").collect();
+    /// assert_eq!(all.len(), 2);
+    /// assert_eq!(all[1].get(1).unwrap().as_str(), "y");
+    /// assert_eq!(all[1].get(2).unwrap().range(), 7..9);
+    /// ```
+    pub fn captures_iter<'c,'t>(&'c self, text: &'t str) -> found::CaptureMatches<'a,'c,'t> {
+        found::CaptureMatches::new(self, text)
+    }
+
+    /// An iterator over the text which is not part of any match.
+    ///
+    /// These are the pieces which `gsub` with an empty replacement would
+    /// leave behind, but kept apart and with their ranges. Empty pieces are
+    /// skipped.
+    ///
+    /// ```
+    /// let m = lua_patterns::LuaPattern::new("%b()");
+    /// let text = "f(x) + g(y, z) * 2";
+    /// let rest: Vec<_> = m.gaps(text).map(|g| g.as_str()).collect();
+    /// assert_eq!(rest, &["f", " + g", " * 2"]);
+    /// assert_eq!(m.gaps(text).last().unwrap().range(), 14..18);
+    /// ```
+    pub fn gaps<'c,'t>(&'c self, text: &'t str) -> found::Gaps<'a,'c,'t> {
+        found::Gaps::new(self, text)
+    }
+
+    /// An iterator over the pieces of a string separated by matches,
+    /// which keeps the matches as delimiters.
+    ///
+    /// Text and delimiters are tagged with `found::Piece`. Empty pieces of
+    /// text and empty matches are skipped, so two delimiters may follow
+    /// each other.
+    ///
+    /// ```
+    /// use lua_patterns::found::Piece;
+    /// let m = lua_patterns::LuaPattern::new("%p");
+    /// let words: Vec<_> = m.split_keep("Hello, world!").map(|p| match p {
+    ///     Piece::Text(t) => t.as_str().trim(),
+    ///     Piece::Delimiter(d) => d.as_str(),
+    /// }).collect();
+    /// assert_eq!(words, &["Hello", ",", "world", "!"]);
+    /// ```
+    pub fn split_keep<'c,'t>(&'c self, text: &'t str) -> found::SplitKeep<'a,'c,'t> {
+        found::SplitKeep::new(self, text)
+    }
+
+    /// An iterator over the captures of all matches in each line of a string.
+    ///
+    /// The pattern is matched against each line separately, without its
+    /// line ending, so '^' and '$' anchor at the start and end of lines.
+    /// The ranges are still offsets in the whole string.
+    ///
+    /// ```
+    /// let m = lua_patterns::LuaPattern::new("^#%s*(.*)");
+    /// let text = "x = 1\n# note\ny = 2 # not a comment\n";
+    /// let comments: Vec<_> = m.match_lines(text).map(|cc| cc.get(1).unwrap()).collect();
+    /// assert_eq!(comments.len(), 1);
+    /// assert_eq!(comments[0].as_str(), "note");
+    /// assert_eq!(comments[0].range(), 8..12);
+    /// ```
+    pub fn match_lines<'c,'t>(&'c self, text: &'t str) -> lines::MatchLines<'a,'c,'t> {
+        lines::MatchLines::new(self, text)
+    }
+
+    /// Does the pattern match at the very start of the string?
+    ///
+    /// Only the start is tried, so this does not search the rest of
+    /// the string as `is_match` would.
+    ///
+    /// ```
+    /// let m = lua_patterns::LuaPattern::new("/api/v%d+/");
+    /// assert!(m.starts_with_pattern("/api/v2/users"));
+    /// assert!(! m.starts_with_pattern("/static/api/v2/"));
+    /// ```
+    pub fn starts_with_pattern(&self, text: &str) -> bool {
+        self.with_match_buffer(|mm| self.matches_here(text.as_bytes(), self.patt, &self.prescan, 0, mm))
+    }
+
+    /// Does the pattern match at the very end of the string?
+    ///
+    /// Matches are tried starting from the end and working back, so a
+    /// short match at the end is found without scanning the whole string.
+    ///
+    /// ```
+    /// let m = lua_patterns::LuaPattern::new("%.[jt]sx?");
+    /// assert!(m.ends_with_pattern("src/app.tsx"));
+    /// assert!(! m.ends_with_pattern("src/app.js.map"));
+    /// ```
+    pub fn ends_with_pattern(&self, text: &str) -> bool {
+        let s = text.as_bytes();
+        let patt = self.end_anchored();
+        let prescan = Prescan::new(&patt);
+        self.with_match_buffer(|mm| {
+            if self.patt.first() == Some(&b'^') {
+                // an anchored pattern can only match all of the string
+                return self.matches_here(s, &patt, &prescan, 0, mm);
+            }
+            (0..=s.len()).rev().any(|i| text.is_char_boundary(i) && self.matches_here(s, &patt, &prescan, i, mm))
+        })
+    }
+
+    // call `f` with room for the results of a match, for matching with `&self`.
+    // This is on the stack, unless the pattern allows more captures than usual
+    fn with_match_buffer<F,R>(&self, f: F) -> R
+    where F: FnOnce(&mut [LuaMatch]) -> R {
+        let n = self.limits.max_captures + 1;
+        if n <= LUA_MAXCAPTURES + 1 {
+            let mut mm = [LuaMatch{start: 0, end: 0}; LUA_MAXCAPTURES + 1];
+            f(&mut mm[..n])
+        } else {
+            f(&mut vec![LuaMatch{start: 0, end: 0}; n])
+        }
+    }
+
+    // does the pattern match at `init` and nowhere else?
+    fn matches_here(&self, s: &[u8], patt: &[u8], prescan: &Prescan, init: usize, mm: &mut [LuaMatch]) -> bool {
+        let res = str_match_at(s,patt,prescan,init,true,self.limits,mm);
+        or_no_match(res.map(|n| n > 0))
+    }
+
+    // the ranges of all captures of the first match at or after `init`,
+    // leaving the results of the last match alone
+    fn search_at(&self, s: &[u8], init: usize) -> Option<Vec<ops::Range<usize>>> {
+        self.with_match_buffer(|mm| {
+            let res = str_match_at(s,self.patt,&self.prescan,init,false,self.limits,mm);
+            let n = *res.as_ref().unwrap_or(&0);
+            if or_no_match(res.map(|n| n > 0)) {
+                Some(mm[..n].iter().map(|m| m.start..m.end).collect())
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Count the non-overlapping matches in a string.
+    ///
+    /// This does no allocation or re-slicing. As in Lua 5.4, an empty
+    /// match immediately after the previous match is not counted.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("%a+");
+    /// assert_eq!(m.count("dog  cat leopard 42"), 3);
+    /// ```
+    pub fn count(&mut self, text: &str) -> usize {
+        let s = text.as_bytes();
+        let mut cursor = Cursor::new(false);
+        let mut count = 0;
+        while self.find_next(s, &mut cursor) {
+            count += 1;
+        }
+        count
+    }
+
+    // find the next match after the cursor, which is then moved past it.
+    // Offsets are always relative to the start of `s`.
+    fn find_next(&mut self, s: &[u8], cursor: &mut Cursor) -> bool {
+        or_no_match(self.try_find_next(s, cursor))
+    }
+
+    // as `find_next`, but reporting errors such as running out of steps
+    fn try_find_next(&mut self, s: &[u8], cursor: &mut Cursor) -> Result<bool,PatternError> {
+        // an anchored pattern can only match at the start
+        if cursor.last_end.is_some() && self.patt.first() == Some(&b'^') {
+            return Ok(false);
+        }
+        let patt = self.patt;
+        while cursor.init <= s.len() && self.try_matches_patt(s,patt,cursor.init,false)? {
+            if cursor.advance(s, self.range()) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// An iterator over all matches in a string.
+    ///
+    /// The matches are returned as string slices; if there are no
+    /// captures the full match is used, otherwise the first capture.
+    /// That is, this example will also work with the pattern "(%S+)".
+    ///
+    /// Panics if a match splits a UTF-8 character, as `.` can; see the crate docs.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("%S+");
+    /// let split: Vec<_> = m.gmatch("dog  cat leopard wolf").collect();
+    /// assert_eq!(split,&["dog","cat","leopard","wolf"]);
+    /// ```
+    pub fn gmatch<'b,'c>(&'c mut self, text: &'b str) -> GMatch<'a,'b,'c> {
+        GMatch{m: self, text, cursor: Cursor::new(true)}
+    }
+
+    /// An iterator over the ranges of all matches in a string.
+    ///
+    /// As with `gmatch`, each range is of the first capture, or of the whole
+    /// match if there are no captures. The ranges are offsets in the text.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("%$(%a+)");
+    /// let text = "hello $dolly, $fine";
+    /// let ranges: Vec<_> = m.gmatch_ranges(text).collect();
+    /// assert_eq!(ranges, &[7..12, 15..19]);
+    /// assert_eq!(&text[ranges[1].clone()], "fine");
+    /// ```
+    pub fn gmatch_ranges<'b,'c>(&'c mut self, text: &'b str) -> GMatchRanges<'a,'b,'c> {
+        GMatchRanges{m: self, bytes: text.as_bytes(), cursor: Cursor::new(true)}
+    }
+
+    /// An iterator over the ranges of all matches in a slice of bytes.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::from_bytes(b"\xFF+");
+    /// let ranges: Vec<_> = m.gmatch_bytes_ranges(b"\x00\xFF\xFF\x00\xFF").collect();
+    /// assert_eq!(ranges, &[1..3, 4..5]);
+    /// ```
+    pub fn gmatch_bytes_ranges<'b,'c>(&'c mut self, bytes: &'b [u8]) -> GMatchRanges<'a,'b,'c> {
+        GMatchRanges{m: self, bytes, cursor: Cursor::new(false)}
+    }
+
+    /// An iterator over all matches in a string, with their offsets.
+    ///
+    /// The matches are as for `gmatch`, and each comes with its
+    /// byte offset in the text.
+    ///
+    /// Panics if a match splits a UTF-8 character, as `.` can; see the crate docs.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("%$(%a+)");
+    /// let vars: Vec<_> = m.gmatch_indexed("hello $dolly, $fine").collect();
+    /// assert_eq!(vars, &[(7, "dolly"), (15, "fine")]);
+    /// ```
+    pub fn gmatch_indexed<'b,'c>(&'c mut self, text: &'b str) -> GMatchIndexed<'a,'b,'c> {
+        GMatchIndexed{ranges: self.gmatch_ranges(text), text}
+    }
+
+    /// An iterator over all matches in a string, reporting errors.
+    ///
+    /// `gmatch` stops quietly if a match runs out of steps; this gives
+    /// the error instead, and then stops.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("%S+");
+    /// let split: Result<Vec<_>,_> = m.try_gmatch("dog cat").collect();
+    /// assert_eq!(split.unwrap(), &["dog","cat"]);
+    ///
+    /// let mut m = lua_patterns::LuaPattern::new("(.-)%s*$");
+    /// m.set_step_limit(Some(1000));
+    /// let text = format!("x{}x", " ".repeat(1000));
+    /// let mut iter = m.try_gmatch(&text);
+    /// assert!(iter.next().unwrap().unwrap_err().is_step_limit());
+    /// assert!(iter.next().is_none());
+    /// ```
+    pub fn try_gmatch<'b,'c>(&'c mut self, text: &'b str) -> TryGMatch<'a,'b,'c> {
+        TryGMatch{iter: self.gmatch(text), failed: false}
     }
 
     /// An iterator over all captures in a string.
     ///
-    /// The matches are returned as captures; this is a _streaming_
-    /// iterator, so don't try to collect the captures directly; extract
-    /// the string slices using `get`.
+    /// The matches are returned as captures, which can be kept
+    /// after the iterator has moved on.
+    ///
+    /// Panics if a capture splits a UTF-8 character, as `.` can; see the crate docs.
     ///
     /// ```
     /// let mut m = lua_patterns::LuaPattern::new("(%S)%S+");
     /// let split: Vec<_> = m.gmatch_captures("dog  cat leopard wolf")
     ///       .map(|cc| cc.get(1)).collect();
     /// assert_eq!(split,&["d","c","l","w"]);
+    /// let all: Vec<_> = m.gmatch_captures("dog cat").collect();
+    /// assert_eq!(all[0].get(1), "d");
+    /// assert_eq!(all[1].get(1), "c");
     /// ```
     pub fn gmatch_captures<'b,'c>(&'c mut self, text: &'b str) -> GMatchCaptures<'a,'b,'c> {
-        GMatchCaptures{m: self, text: text}
+        GMatchCaptures{m: self, text, cursor: Cursor::new(true)}
     }
 
     /// An iterator over all matches in a slice of bytes.
@@ -274,13 +1314,104 @@ impl <'a> LuaPattern<'a> {
     /// assert_eq!(iter.next().unwrap(), &[0x01,0x01,0x01]);
     /// assert_eq!(iter.next(), None);
     /// ```
-    pub fn gmatch_bytes<'b>(&'a mut self, bytes: &'b [u8]) -> GMatchBytes<'a,'b> {
-        GMatchBytes{m: self, bytes: bytes}
+    pub fn gmatch_bytes<'b,'c>(&'c mut self, bytes: &'b [u8]) -> GMatchBytes<'a,'b,'c> {
+        GMatchBytes{m: self, bytes, cursor: Cursor::new(false)}
+    }
+
+    /// An iterator over all captures in a slice of bytes.
+    ///
+    /// As with `gmatch_captures`, the captures can be kept.
+    ///
+    /// ```
+    /// // records of a tag byte, a length byte and that many bytes of value
+    /// let bytes = &[0x01,0x02,0xAA,0xBB,0x02,0x01,0xCC];
+    /// let mut m = lua_patterns::LuaPattern::from_bytes(b"([\x01\x02])\x02(..)");
+    /// let fields: Vec<_> = m.gmatch_bytes_captures(bytes)
+    ///       .map(|cc| (cc.get(1)[0], cc.get(2))).collect();
+    /// assert_eq!(fields, &[(0x01, &[0xAA,0xBB][..])]);
+    /// ```
+    pub fn gmatch_bytes_captures<'b,'c>(&'c mut self, bytes: &'b [u8]) -> GMatchBytesCaptures<'a,'b,'c> {
+        GMatchBytesCaptures{m: self, bytes, cursor: Cursor::new(false)}
+    }
+
+    /// An iterator over the substrings separated by matches.
+    ///
+    /// This is the inverse of `gmatch`. Empty matches at the start
+    /// of a piece or the end of the text are ignored.
+    ///
+    /// Panics if a match splits a UTF-8 character, as `.` can; see the crate docs.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("%s*,%s*");
+    /// let split: Vec<_> = m.split("dog, cat ,leopard,").collect();
+    /// assert_eq!(split,&["dog","cat","leopard",""]);
+    /// ```
+    pub fn split<'b,'c>(&'c mut self, text: &'b str) -> Split<'a,'b,'c> {
+        Split{m: self, text, start: 0, init: 0, limit: None, terminator: false, done: false}
+    }
+
+    /// An iterator over at most `n` substrings separated by matches.
+    ///
+    /// The last substring is the rest of the text.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("%s+");
+    /// let split: Vec<_> = m.splitn("dog  cat leopard wolf", 2).collect();
+    /// assert_eq!(split,&["dog","cat leopard wolf"]);
+    /// ```
+    pub fn splitn<'b,'c>(&'c mut self, text: &'b str, n: usize) -> Split<'a,'b,'c> {
+        Split{m: self, text, start: 0, init: 0, limit: Some(n), terminator: false, done: false}
+    }
+
+    /// An iterator over the substrings separated by matches,
+    /// skipping a trailing empty substring.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new(";");
+    /// let split: Vec<_> = m.split_terminator("a;b;c;").collect();
+    /// assert_eq!(split,&["a","b","c"]);
+    /// ```
+    pub fn split_terminator<'b,'c>(&'c mut self, text: &'b str) -> Split<'a,'b,'c> {
+        Split{m: self, text, start: 0, init: 0, limit: None, terminator: true, done: false}
+    }
+
+    /// An iterator over all matches in a string which owns the pattern.
+    ///
+    /// This is like `gmatch`, but the iterator can be returned
+    /// from a function.
+    ///
+    /// ```
+    /// fn words(text: &str) -> lua_patterns::IntoGMatch<'static,'_> {
+    ///     lua_patterns::LuaPattern::new("%a+").into_gmatch(text)
+    /// }
+    /// let split: Vec<_> = words("dog  cat, leopard").collect();
+    /// assert_eq!(split,&["dog","cat","leopard"]);
+    /// ```
+    pub fn into_gmatch<'b>(self, text: &'b str) -> IntoGMatch<'a,'b> {
+        IntoGMatch{m: self, text, cursor: Cursor::new(true)}
+    }
+
+    /// An iterator over all matches in a slice of bytes which owns the pattern.
+    ///
+    /// ```
+    /// let m = lua_patterns::LuaPattern::from_bytes(&[0x01,b'+']);
+    /// let mut iter = m.into_gmatch_bytes(&[0xAA,0x01,0x01,0xBB,0x01]);
+    /// assert_eq!(iter.next().unwrap(), &[0x01,0x01]);
+    /// assert_eq!(iter.next().unwrap(), &[0x01]);
+    /// assert_eq!(iter.next(), None);
+    /// ```
+    pub fn into_gmatch_bytes<'b>(self, bytes: &'b [u8]) -> IntoGMatchBytes<'a,'b> {
+        IntoGMatchBytes{m: self, bytes, cursor: Cursor::new(false)}
     }
 
     /// Globally substitute all matches with a replacement
     /// provided by a function of the captures.
     ///
+    /// The captures refer to the whole text, so `cc.range(0)`
+    /// is the position of the match.
+    ///
+    /// Panics if a match splits a UTF-8 character, as `.` can; see the crate docs.
+    ///
     /// ```
     /// let mut m = lua_patterns::LuaPattern::new("%$(%S+)");
     /// let res = m.gsub_with("hello $dolly you're so $fine!",
@@ -288,22 +1419,112 @@ impl <'a> LuaPattern<'a> {
     /// );
     /// assert_eq!(res, "hello DOLLY you're so FINE!");
     /// ```
-    pub fn gsub_with <F> (&mut self, text: &str, lookup: F) -> String
-    where F: Fn(Captures)-> String {
-        let mut slice = text;
+    pub fn gsub_with <F> (&mut self, text: &str, mut lookup: F) -> String
+    where F: FnMut(Captures)-> String {
+        match self.try_gsub_with(text, |cc| Ok::<_,Infallible>(lookup(cc))) {
+            Ok(res) => res,
+            Err(e) => match e {}
+        }
+    }
+
+    /// Globally substitute all matches with a replacement
+    /// provided by a fallible function of the captures.
+    ///
+    /// The first error returned by the function stops the substitution.
+    ///
+    /// Panics if a match splits a UTF-8 character, as `.` can; see the crate docs.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("%d+");
+    /// let res = m.try_gsub_with("10 and 20",
+    ///     |cc| cc.get(0).parse::<u8>().map(|n| (n / 2).to_string())
+    /// );
+    /// assert_eq!(res.unwrap(), "5 and 10");
+    /// let res = m.try_gsub_with("10 and 300",
+    ///     |cc| cc.get(0).parse::<u8>().map(|n| (n / 2).to_string())
+    /// );
+    /// assert!(res.is_err());
+    /// ```
+    pub fn try_gsub_with <F,E> (&mut self, text: &str, mut lookup: F) -> Result<String,E>
+    where F: FnMut(Captures)-> Result<String,E> {
+        let res = self.subst_loop(text, 0, |m, s, cursor| Ok(m.find_next(s, cursor)), |res, captures| {
+            res.push_str(&lookup(captures)?);
+            Ok(())
+        })?;
+        Ok(res.unwrap_or_else(|| text.to_string()))
+    }
+
+    /// Globally substitute all matches with values looked up in a map
+    ///
+    /// As with a table in Lua's `string.gsub`, the key is the first capture,
+    /// or the whole match if there are no captures, and matches whose key
+    /// is not in the map are left as they are.
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// let mut map = HashMap::new();
+    /// map.insert("dolly", "baby");
+    /// map.insert("fine", "cool");
+    /// let mut m = lua_patterns::LuaPattern::new("%$%((.-)%)");
+    /// let res = m.gsub_map("hello $(dolly) you're so $(fine) and $(shy)", &map);
+    /// assert_eq!(res, "hello baby you're so cool and $(shy)");
+    /// ```
+    pub fn gsub_map<K,V,H> (&mut self, text: &str, map: &HashMap<K,V,H>) -> String
+    where K: Borrow<str> + Hash + Eq, V: AsRef<str>, H: BuildHasher {
+        self.gsub_with(text, |cc| {
+            let key = if cc.num_matches() > 1 {1} else {0};
+            match map.get(cc.get(key)) {
+                Some(value) => value.as_ref().to_string(),
+                None => cc.get(0).to_string()
+            }
+        })
+    }
+
+    // The substitution loop for strings: `find` moves to each of at most
+    // `limit` matches (zero for all), failing if the matcher does, and
+    // `append` adds the replacement. The captures refer to the whole text.
+    // Returns `None` if there was no match.
+    fn subst_loop<S,F,E> (&mut self, text: &str, limit: usize, mut find: S, mut append: F) -> Result<Option<String>,E>
+    where S: FnMut(&mut Self, &[u8], &mut Cursor)-> Result<bool,E>, F: FnMut(&mut String, Captures)-> Result<(),E> {
+        let mut cursor = Cursor::new(true);
         let mut res = String::new();
-        while self.matches(slice) {
+        let mut last = 0;
+        let mut count = 0;
+        while (limit == 0 || count < limit) && find(self, text.as_bytes(), &mut cursor)? {
             // full range of match
             let all = self.range();
             // append everything up to match
-            res.push_str(&slice[0..all.start]);
-            let captures = Captures{m: self, text: slice};
-            let repl = lookup(captures);
-            res.push_str(&repl);
-            slice = &slice[all.end..];
+            res.push_str(&text[last..all.start]);
+            append(&mut res, Captures{m: self, text})?;
+            last = all.end;
+            count += 1;
         }
-        res.push_str(slice);
-        res
+        if count == 0 {
+            return Ok(None);
+        }
+        res.push_str(&text[last..]);
+        Ok(Some(res))
+    }
+
+    // As above, for bytes
+    fn subst_bytes_loop<F,E> (&mut self, bytes: &[u8], mut append: F) -> Result<Option<Vec<u8>>,E>
+    where F: FnMut(&mut Vec<u8>, ByteCaptures)-> Result<(),E> {
+        let mut cursor = Cursor::new(false);
+        let mut res = Vec::new();
+        let mut last = 0;
+        let mut count = 0;
+        while self.find_next(bytes, &mut cursor) {
+            let all = self.range();
+            res.extend_from_slice(&bytes[last..all.start]);
+            append(&mut res, ByteCaptures{m: self, bytes})?;
+            last = all.end;
+            count += 1;
+        }
+        if count == 0 {
+            return Ok(None);
+        }
+        res.extend_from_slice(&bytes[last..]);
+        Ok(Some(res))
     }
 
     /// Globally substitute all matches with a replacement string
@@ -316,75 +1537,426 @@ impl <'a> LuaPattern<'a> {
     /// let res = m.gsub("a=2; b=3; c = 4;", "'%2':%1 ");
     /// assert_eq!(res,"'2':a '3':b '4':c ");
     /// ```
+    ///
+    /// `%0` is the whole match, and so is `%1` if the pattern has no captures.
+    /// Panics if the replacement is bad, as found by `check_replacement`;
+    /// `try_gsub` returns the error instead.
+    /// It also panics if a match splits a UTF-8 character, as `.` can; see the crate docs.
     pub fn gsub (&mut self, text: &str, repl: &str) -> String {
-        let repl = generate_gsub_patterns(repl);
-        let mut slice = text;
-        let mut res = String::new();
-        while self.matches(slice) {
-            let all = self.range();
-            res.push_str(&slice[0..all.start]);
-            let captures = Captures{m: self, text: slice};
-            for r in &repl {
-                match *r {
-                    Subst::Text(ref s) => res.push_str(&s),
-                    Subst::Capture(i) => res.push_str(captures.get(i))
+        self.replacen(text, repl, 0).into_owned()
+    }
+
+    /// The number of captures in the pattern, including position captures
+    ///
+    /// ```
+    /// assert_eq!(lua_patterns::LuaPattern::new("(%a+)=()(%d+)").num_captures(), 3);
+    /// assert_eq!(lua_patterns::LuaPattern::new("%a+").num_captures(), 0);
+    /// ```
+    pub fn num_captures(&self) -> usize {
+        parse::parse(self.patt).map(|p| p.num_captures).unwrap_or(0)
+    }
+
+    /// Check a `gsub` replacement string against this pattern
+    ///
+    /// As in Lua, `%0` is the whole match, `%1` to `%9` are captures,
+    /// `%1` is also the whole match if there are no captures, and `%%`
+    /// is a literal `%`. Anything else after `%` is an error, as is a
+    /// reference to a capture the pattern does not have. The position
+    /// of the error is in the replacement string.
+    ///
+    /// ```
+    /// let m = lua_patterns::LuaPattern::new("(%a+)=(%d+)");
+    /// assert!(m.check_replacement("%0: %2=%1 (100%%)").is_ok());
+    /// let err = m.check_replacement("%2=%9").unwrap_err();
+    /// assert_eq!(err.message(), "invalid capture index %9 in replacement string");
+    /// assert_eq!(err.position(), Some(3));
+    /// let err = m.check_replacement("100%").unwrap_err();
+    /// assert_eq!(err.message(), "invalid use of '%' in replacement string");
+    /// ```
+    pub fn check_replacement(&self, repl: &str) -> Result<(),PatternError> {
+        self.check_replacement_bytes(repl.as_bytes())
+    }
+
+    /// Check a `gsub_bytes` replacement against this pattern, as for `check_replacement`
+    pub fn check_replacement_bytes(&self, repl: &[u8]) -> Result<(),PatternError> {
+        let captures = self.num_captures();
+        let mut i = 0;
+        while i < repl.len() {
+            if repl[i] == b'%' {
+                let msg = match repl.get(i + 1) {
+                    Some(b'%') | Some(b'0') => None,
+                    Some(&d @ b'1' ..= b'9') => {
+                        let index = (d - b'0') as usize;
+                        if index <= captures || (index == 1 && captures == 0) {
+                            None
+                        } else {
+                            Some(format!("invalid capture index %{} in replacement string", index))
+                        }
+                    },
+                    _ => Some("invalid use of '%' in replacement string".to_string())
+                };
+                if let Some(msg) = msg {
+                    return Err(PatternError::new(InvalidReplacement, &msg).at(i));
                 }
+                i += 2;
+            } else {
+                i += 1;
             }
-            slice = &slice[all.end..];
         }
-        res.push_str(slice);
-        res
+        Ok(())
     }
 
-    /// Globally substitute all _byte_ matches with a replacement
-    /// provided by a function of the captures.
+    /// Globally substitute all matches with a replacement string, reporting errors
+    ///
+    /// Unlike `gsub`, a match which runs out of steps is an error rather than
+    /// no match. The replacement is checked first with `check_replacement`,
+    /// so a capture reference like `%3` when the pattern has fewer captures
+    /// is an error even if nothing matches.
+    ///
+    /// Panics if a match splits a UTF-8 character, as `.` can; see the crate docs.
     ///
     /// ```
-    /// let bytes = &[0xAA,0x01,0x02,0x03,0xBB];
-    /// let patt = &[0x01,0x02];
-    /// let mut m = lua_patterns::LuaPattern::from_bytes(patt);
-    /// let res = m.gsub_bytes_with(bytes,|cc| vec![0xFF]);
-    /// assert_eq!(res, &[0xAA,0xFF,0x03,0xBB]);
+    /// use lua_patterns::errors::PatternErrorKind;
+    /// let mut m = lua_patterns::LuaPattern::new("(%a+)=(%d+)");
+    /// assert_eq!(m.try_gsub("a=1 b=2", "%2=%1").unwrap(), "1=a 2=b");
+    /// let err = m.try_gsub("a=1 b=2", "%3").unwrap_err();
+    /// assert_eq!(err.kind(), PatternErrorKind::InvalidReplacement);
+    /// assert_eq!(err.message(), "invalid capture index %3 in replacement string");
+    ///
+    /// let mut m = lua_patterns::LuaPattern::new("(.-)%s*$");
+    /// m.set_step_limit(Some(1000));
+    /// let text = format!("x{}x", " ".repeat(1000));
+    /// assert!(m.try_gsub(&text, "").unwrap_err().is_step_limit());
     /// ```
-    pub fn gsub_bytes_with <F> (&mut self, bytes: &[u8], lookup: F) -> Vec<u8>
-    where F: Fn(ByteCaptures)-> Vec<u8> {
-        let mut slice = bytes;
-        let mut res = Vec::new();
-        while self.matches_bytes(slice) {
-            let all = self.range();
-            let capture = &slice[0..all.start];
-            res.extend_from_slice(capture);
-            let captures = ByteCaptures{m: self, bytes: slice};
-            let repl = lookup(captures);
-            res.extend(repl);
-            slice = &slice[all.end..];
-        }
-        res.extend_from_slice(slice);
-        res
+    pub fn try_gsub (&mut self, text: &str, repl: &str) -> Result<String,PatternError> {
+        self.check_replacement(repl)?;
+        let repl = generate_gsub_patterns(repl);
+        let res = self.subst_loop(text, 0, LuaPattern::try_find_next, |res, captures| {
+            let m = captures.m;
+            expand_subst(&repl, text, |i| m.try_capture(i), res).expect("writing to a String");
+            Ok(())
+        })?;
+        Ok(res.unwrap_or_else(|| text.to_string()))
     }
 
-}
+    /// Globally substitute all matches with a replacement string,
+    /// writing the result to a `fmt::Write` sink such as a `String`
+    ///
+    /// The replacement is as for `gsub`. Returns the number of
+    /// substitutions made. A bad replacement, as found by `check_replacement`,
+    /// is returned as an error before anything is written, and so is
+    /// a failure of the sink, with kind `WriteFailed`.
+    ///
+    /// Panics if a match splits a UTF-8 character, as `.` can; see the crate docs.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("%$(%a+)");
+    /// let mut out = String::from("> ");
+    /// let n = m.gsub_to("hello $dolly, $fine", "<%1>", &mut out).unwrap();
+    /// assert_eq!(out, "> hello <dolly>, <fine>");
+    /// assert_eq!(n, 2);
+    /// assert!(m.gsub_to("hello $dolly", "<%2>", &mut out).is_err());
+    /// ```
+    pub fn gsub_to<W: fmt::Write> (&mut self, text: &str, repl: &str, w: &mut W) -> Result<usize,PatternError> {
+        self.check_replacement(repl)?;
+        let repl = generate_gsub_patterns(repl);
+        let mut cursor = Cursor::new(true);
+        let mut last = 0;
+        let mut count = 0;
+        while self.find_next(text.as_bytes(), &mut cursor) {
+            let all = self.range();
+            w.write_str(&text[last..all.start])?;
+            expand_subst(&repl, text, |i| self.try_capture(i), w)?;
+            last = all.end;
+            count += 1;
+        }
+        w.write_str(&text[last..])?;
+        Ok(count)
+    }
 
-#[derive(Debug)]
-pub enum Subst {
-    Text(String),
-    Capture(usize)
-}
+    /// Globally substitute all matches with a replacement string,
+    /// changing the string itself
+    ///
+    /// The replacement is as for `gsub`. The text is rearranged within its
+    /// own buffer, which only grows if the result is longer and does not fit.
+    /// Besides that, the ranges of the captures of every match and the length
+    /// of each replacement are kept, which is O(matches × captures) extra
+    /// memory, along with room for the longest match and its replacement.
+    /// Returns the number of substitutions made.
+    ///
+    /// A bad replacement, as found by `check_replacement`, is an error, and
+    /// so is a match or capture which splits a UTF-8 character, with kind
+    /// `SplitsCharacter`. Either way the text is left as it was.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("%s+");
+    /// let mut text = String::from("one  two\t\tthree");
+    /// let capacity = text.capacity();
+    /// assert_eq!(m.gsub_in_place(&mut text, " "), Ok(2));
+    /// assert_eq!(text, "one two three");
+    /// assert_eq!(text.capacity(), capacity);
+    ///
+    /// let mut m = lua_patterns::LuaPattern::new(".");
+    /// let mut text = String::from("é");
+    /// assert!(m.gsub_in_place(&mut text, "x").is_err());
+    /// assert_eq!(text, "é");
+    /// ```
+    pub fn gsub_in_place (&mut self, text: &mut String, repl: &str) -> Result<usize,PatternError> {
+        self.check_replacement(repl)?;
+        let repl = generate_gsub_patterns(repl);
+        // the ranges of the captures of each match, and the length of its replacement
+        let mut ranges = Vec::new();
+        let mut lens = Vec::new();
+        let mut n = 0;
+        let mut added = String::new();
+        let mut cursor = Cursor::new(true);
+        while self.find_next(text.as_bytes(), &mut cursor) {
+            n = self.n_match;
+            let start = ranges.len();
+            ranges.extend((0..n).map(|i| self.capture(i)));
+            if let Some(r) = ranges[start..].iter().find(|r| ! text.is_char_boundary(r.start) || ! text.is_char_boundary(r.end)) {
+                let msg = format!("match or capture {:?} splits a character", r);
+                return Err(PatternError::new(SplitsCharacter, &msg));
+            }
+            added.clear();
+            expand_subst(&repl, text, |i| self.try_capture(i), &mut added).expect("writing to a String");
+            lens.push(added.len());
+        }
+        if lens.is_empty() {
+            return Ok(0);
+        }
+        // the text is pieces which are kept and matches which are replaced,
+        // each with where it is now and where it goes
+        let old_len = text.len();
+        let mut pieces = Vec::with_capacity(2 * lens.len() + 1);
+        let (mut last, mut dest) = (0, 0);
+        for (k, &len) in lens.iter().enumerate() {
+            let all = ranges[k * n].clone();
+            pieces.push((last..all.start, dest..dest + all.start - last, None));
+            dest += all.start - last;
+            pieces.push((all.clone(), dest..dest + len, Some(k)));
+            dest += len;
+            last = all.end;
+        }
+        pieces.push((last..old_len, dest..dest + old_len - last, None));
+        let new_len = dest + old_len - last;
 
-impl Subst {
-    fn new_text(text: &str) -> Subst {
-        Subst::Text(text.to_string())
+        let mut bytes = mem::take(text).into_bytes();
+        bytes.resize(old_len.max(new_len), 0);
+        let mut matched = Vec::new();
+        let mut move_piece = |bytes: &mut Vec<u8>, piece: &(ops::Range<usize>, ops::Range<usize>, Option<usize>)| {
+            let (ref from, ref to, edit) = *piece;
+            match edit {
+                None => bytes.copy_within(from.clone(), to.start),
+                Some(k) => {
+                    // the replacement may repeat captures, so the match is copied out first
+                    matched.clear();
+                    matched.extend_from_slice(&bytes[from.clone()]);
+                    let text = str::from_utf8(&matched).expect("matches are on char boundaries");
+                    let captures = &ranges[k * n..(k + 1) * n];
+                    let capture = |i: usize| captures.get(i).map(|r| r.start - from.start..r.end - from.start);
+                    added.clear();
+                    expand_subst(&repl, text, capture, &mut added).expect("writing to a String");
+                    bytes[to.clone()].copy_from_slice(added.as_bytes());
+                }
+            }
+        };
+        // pieces which do not end further on can be moved in order from the start,
+        // since they only overwrite what has already been moved; the others
+        // are then moved in order from the end
+        for piece in pieces.iter().filter(|p| p.1.end <= p.0.end) {
+            move_piece(&mut bytes, piece);
+        }
+        for piece in pieces.iter().rev().filter(|p| p.1.end > p.0.end) {
+            move_piece(&mut bytes, piece);
+        }
+        bytes.truncate(new_len);
+        *text = String::from_utf8(bytes).expect("pieces of strings make a string");
+        Ok(lens.len())
     }
-}
 
-pub fn generate_gsub_patterns(repl: &str) -> Vec<Subst> {
-    let mut m = LuaPattern::new("%%([%%%d])");
+    /// Globally substitute all matches with a replacement string,
+    /// borrowing the original text if there is no match
+    ///
+    /// This is `gsub` without the copy in the common case where
+    /// there is nothing to replace.
+    ///
+    /// ```
+    /// use std::borrow::Cow;
+    /// let mut m = lua_patterns::LuaPattern::new("\r");
+    /// let res = m.replace_all_cow("one\r\ntwo\r\n", "");
+    /// assert_eq!(res, "one\ntwo\n");
+    /// let res = m.replace_all_cow("one\ntwo\n", "");
+    /// assert!(matches!(res, Cow::Borrowed(_)));
+    /// ```
+    pub fn replace_all_cow<'t> (&mut self, text: &'t str, repl: &str) -> Cow<'t,str> {
+        self.replacen(text, repl, 0)
+    }
+
+    /// Substitute the first match with a replacement string
+    ///
+    /// The replacement is as for `gsub`, and it panics if that is bad.
+    /// If there is no match, the original text is returned without copying.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("(%a+)=(%d+)");
+    /// let res = m.replace("a=1 b=2", "%2=%1");
+    /// assert_eq!(res, "1=a b=2");
+    /// ```
+    pub fn replace<'t> (&mut self, text: &'t str, repl: &str) -> Cow<'t,str> {
+        self.replacen(text, repl, 1)
+    }
+
+    /// Substitute at most `limit` matches with a replacement string
+    ///
+    /// A `limit` of zero means all matches are substituted.
+    /// If there is no match, the original text is returned without copying.
+    /// Panics if the replacement is bad, as found by `check_replacement`.
+    /// It also panics if a match splits a UTF-8 character, as `.` can; see the crate docs.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("%d");
+    /// assert_eq!(m.replacen("1 2 3 4", "#", 2), "# # 3 4");
+    /// assert_eq!(m.replacen("1 2 3 4", "#", 0), "# # # #");
+    /// assert_eq!(m.replacen("one", "#", 2), "one");
+    /// ```
+    pub fn replacen<'t> (&mut self, text: &'t str, repl: &str, limit: usize) -> Cow<'t,str> {
+        self.check_replacement(repl).expect("bad replacement");
+        let repl = generate_gsub_patterns(repl);
+        let res = self.subst_loop(text, limit, |m, s, cursor| Ok(m.find_next(s, cursor)), |res, captures| {
+            let m = captures.m;
+            expand_subst(&repl, text, |i| m.try_capture(i), res).expect("writing to a String");
+            Ok::<_,Infallible>(())
+        });
+        match res {
+            Ok(Some(res)) => Cow::Owned(res),
+            Ok(None) => Cow::Borrowed(text),
+            Err(e) => match e {}
+        }
+    }
+
+    /// Globally substitute all _byte_ matches with a replacement
+    /// provided by a function of the captures.
+    ///
+    /// ```
+    /// let bytes = &[0xAA,0x01,0x02,0x03,0xBB];
+    /// let patt = &[0x01,0x02];
+    /// let mut m = lua_patterns::LuaPattern::from_bytes(patt);
+    /// let res = m.gsub_bytes_with(bytes,|cc| vec![0xFF]);
+    /// assert_eq!(res, &[0xAA,0xFF,0x03,0xBB]);
+    /// ```
+    pub fn gsub_bytes_with <F> (&mut self, bytes: &[u8], mut lookup: F) -> Vec<u8>
+    where F: FnMut(ByteCaptures)-> Vec<u8> {
+        self.gsub_bytes_append(bytes, |captures, res| res.extend(lookup(captures)))
+    }
+
+    /// Globally substitute all _byte_ matches, where the function
+    /// appends the replacement to the result itself.
+    ///
+    /// This avoids allocating a vector for each replacement, as
+    /// `gsub_bytes_with` does.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::from_bytes(b"\x01(.)");
+    /// let res = m.gsub_bytes_append(b"\xAA\x01\x03\xBB", |cc, out| {
+    ///     out.extend_from_slice(cc.get(1));
+    ///     out.extend_from_slice(cc.get(1));
+    /// });
+    /// assert_eq!(res, b"\xAA\x03\x03\xBB");
+    /// ```
+    pub fn gsub_bytes_append <F> (&mut self, bytes: &[u8], mut append: F) -> Vec<u8>
+    where F: FnMut(ByteCaptures, &mut Vec<u8>) {
+        let res = self.subst_bytes_loop(bytes, |res, captures| {
+            append(captures, res);
+            Ok::<_,Infallible>(())
+        });
+        match res {
+            Ok(res) => res.unwrap_or_else(|| bytes.to_vec()),
+            Err(e) => match e {}
+        }
+    }
+
+    /// Globally substitute all _byte_ matches with a replacement
+    ///
+    /// Like `gsub`, the replacement _may_ have capture references
+    /// ("%0",..), and "%%" represents "%". Panics if the replacement
+    /// is bad, as found by `check_replacement_bytes`.
+    ///
+    /// ```
+    /// let bytes = &[0xAA,0x01,0x02,0x03,0xBB];
+    /// let patt = &[b'(',0x01,b')',b'(',0x02,b')'];
+    /// let mut m = lua_patterns::LuaPattern::from_bytes(patt);
+    /// let res = m.gsub_bytes(bytes,&[b'%',b'2',0xFF,b'%',b'1']);
+    /// assert_eq!(res, &[0xAA,0x02,0xFF,0x01,0x03,0xBB]);
+    /// ```
+    pub fn gsub_bytes (&mut self, bytes: &[u8], repl: &[u8]) -> Vec<u8> {
+        self.check_replacement_bytes(repl).expect("bad replacement");
+        let repl = generate_gsub_byte_patterns(repl);
+        let res = self.subst_bytes_loop(bytes, |res, captures| {
+            let m = captures.m;
+            expand_byte_subst(&repl, bytes, |i| m.try_capture(i), res).expect("writing to a Vec");
+            Ok::<_,Infallible>(())
+        });
+        match res {
+            Ok(res) => res.unwrap_or_else(|| bytes.to_vec()),
+            Err(e) => match e {}
+        }
+    }
+
+    /// Globally substitute all _byte_ matches with a replacement,
+    /// writing the result to an `io::Write` sink such as a file
+    ///
+    /// The replacement is as for `gsub_bytes`. Returns the number of
+    /// substitutions made. A bad replacement, as found by
+    /// `check_replacement_bytes`, is an `InvalidInput` error and
+    /// nothing is written.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::from_bytes(&[0x01,b'+']);
+    /// let mut out = Vec::new();
+    /// let n = m.gsub_bytes_to(&[0xAA,0x01,0x01,0xBB], &[0xFF], &mut out).unwrap();
+    /// assert_eq!(out, &[0xAA,0xFF,0xBB]);
+    /// assert_eq!(n, 1);
+    /// ```
+    pub fn gsub_bytes_to<W: io::Write> (&mut self, bytes: &[u8], repl: &[u8], w: &mut W) -> io::Result<usize> {
+        self.check_replacement_bytes(repl).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let repl = generate_gsub_byte_patterns(repl);
+        let mut cursor = Cursor::new(false);
+        let mut last = 0;
+        let mut count = 0;
+        while self.find_next(bytes, &mut cursor) {
+            let all = self.range();
+            w.write_all(&bytes[last..all.start])?;
+            expand_byte_subst(&repl, bytes, |i| self.try_capture(i), w)?;
+            last = all.end;
+            count += 1;
+        }
+        w.write_all(&bytes[last..])?;
+        Ok(count)
+    }
+
+}
+
+#[derive(Clone,Debug)]
+pub enum Subst {
+    Text(String),
+    Capture(usize)
+}
+
+impl Subst {
+    fn new_text(text: &str) -> Subst {
+        Subst::Text(text.to_string())
+    }
+}
+
+pub fn generate_gsub_patterns(repl: &str) -> Vec<Subst> {
+    let mut m = LuaPattern::new("%%([%%%d])");
     let mut res = Vec::new();
     let mut slice = repl;
     while m.matches(slice) {
         let all = m.range();
         let before = &slice[0..all.start];
-        if before != "" {
+        if ! before.is_empty() {
             res.push(Subst::new_text(before));
         }
         let capture = &slice[m.capture(1)];
@@ -400,6 +1972,69 @@ pub fn generate_gsub_patterns(repl: &str) -> Vec<Subst> {
     res
 }
 
+#[derive(Debug)]
+pub enum ByteSubst {
+    Bytes(Vec<u8>),
+    Capture(usize)
+}
+
+pub fn generate_gsub_byte_patterns(repl: &[u8]) -> Vec<ByteSubst> {
+    let mut m = LuaPattern::new("%%([%%%d])");
+    let mut res = Vec::new();
+    let mut slice = repl;
+    while m.matches_bytes(slice) {
+        let all = m.range();
+        let before = &slice[0..all.start];
+        if ! before.is_empty() {
+            res.push(ByteSubst::Bytes(before.to_vec()));
+        }
+        let capture = slice[m.capture(1)][0];
+        if capture == b'%' { // escaped literal '%'
+            res.push(ByteSubst::Bytes(vec![b'%']));
+        } else { // has to be a digit
+            res.push(ByteSubst::Capture((capture - b'0') as usize));
+        }
+        slice = &slice[all.end..];
+    }
+    res.push(ByteSubst::Bytes(slice.to_vec()));
+    res
+}
+
+// Write the expansion of a replacement for one match in `text`, where
+// `capture` gives the range of each capture there is (0 is the whole match).
+// As in Lua, with no captures %1 is the whole match
+pub(crate) fn expand_subst<W,F>(repl: &[Subst], text: &str, capture: F, w: &mut W) -> fmt::Result
+where W: fmt::Write, F: Fn(usize) -> Option<ops::Range<usize>> {
+    for r in repl {
+        match *r {
+            Subst::Text(ref s) => w.write_str(s)?,
+            Subst::Capture(i) => if let Some(range) = subst_capture(&capture, i) {
+                w.write_str(&text[range])?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn subst_capture<F>(capture: &F, i: usize) -> Option<ops::Range<usize>>
+where F: Fn(usize) -> Option<ops::Range<usize>> {
+    capture(i).or_else(|| if i == 1 {capture(0)} else {None})
+}
+
+// As above, for bytes
+fn expand_byte_subst<W,F>(repl: &[ByteSubst], bytes: &[u8], capture: F, w: &mut W) -> io::Result<()>
+where W: io::Write, F: Fn(usize) -> Option<ops::Range<usize>> {
+    for r in repl {
+        match *r {
+            ByteSubst::Bytes(ref b) => w.write_all(b)?,
+            ByteSubst::Capture(i) => if let Some(range) = subst_capture(&capture, i) {
+                w.write_all(&bytes[range])?;
+            }
+        }
+    }
+    Ok(())
+}
+
 pub struct Substitute {
     repl: Vec<Subst>
 }
@@ -413,13 +2048,7 @@ impl Substitute {
 
     pub fn subst(&self, patt: &LuaPattern, text: &str) -> String {
         let mut res = String::new();
-        let captures = patt.match_captures(text);
-        for r in &self.repl {
-            match *r {
-                Subst::Text(ref s) => res.push_str(&s),
-                Subst::Capture(i) => res.push_str(captures.get(i))
-            }
-        }
+        expand_subst(&self.repl, text, |i| patt.try_capture(i), &mut res).expect("writing to a String");
         res
     }
 
@@ -432,6 +2061,7 @@ impl Substitute {
 // The lifetime 'a is for the _pattern_, the lifetime 'b is
 // for the _source string_, and 'c is for the reference to LuaPattern
 // And the LuaPattern reference cannot live longer than the pattern reference
+#[derive(Clone,Copy)]
 pub struct Captures<'a,'b,'c> where 'a: 'c {
     m: &'c LuaPattern<'a>,
     text: &'b str
@@ -439,218 +2069,1187 @@ pub struct Captures<'a,'b,'c> where 'a: 'c {
 
 impl <'a,'b,'c> Captures<'a,'b,'c> {
     /// get the capture as a string slice
+    ///
+    /// Panics if a capture splits a UTF-8 character, as `.` can; see the crate docs.
     pub fn get(&self, i: usize) -> &'b str {
         &self.text[self.m.capture(i)]
     }
 
+    /// get the range of the capture in the text.
+    ///
+    /// For `gsub_with` this is relative to the whole text, so
+    /// `range(0)` is the position of the match.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("%a+");
+    /// let res = m.gsub_with("one two", |cc| cc.range(0).start.to_string());
+    /// assert_eq!(res, "0 4");
+    /// ```
+    pub fn range(&self, i: usize) -> ops::Range<usize> {
+        self.m.capture(i)
+    }
+
     /// number of matches
     pub fn num_matches(&self) -> usize {
         self.m.n_match
     }
+
+    /// iterate over the captures, starting with the whole match
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("(%a+)=(%d+)");
+    /// let res = m.gsub_with("x=1", |cc| cc.iter().skip(1).collect::<Vec<_>>().join(":"));
+    /// assert_eq!(res, "x:1");
+    /// ```
+    pub fn iter(&self) -> CapturesIter<'a,'b,'c> {
+        CapturesIter{captures: *self, indices: 0..self.num_matches()}
+    }
+}
+
+/// `cc[i]` is the same as `cc.get(i)`, but panics if there is no such capture
+///
+/// ```
+/// let mut m = lua_patterns::LuaPattern::new("(%a+)=(%d+)");
+/// let res = m.gsub_with("x=1", |cc| format!("{}:{}", &cc[2], &cc[1]));
+/// assert_eq!(res, "1:x");
+/// ```
+impl <'a,'b,'c> ops::Index<usize> for Captures<'a,'b,'c> {
+    type Output = str;
+
+    fn index(&self, i: usize) -> &str {
+        assert!(i < self.num_matches(), "no capture {}", i);
+        self.get(i)
+    }
+}
+
+impl <'a,'b,'c> IntoIterator for Captures<'a,'b,'c> {
+    type Item = &'b str;
+    type IntoIter = CapturesIter<'a,'b,'c>;
+
+    fn into_iter(self) -> CapturesIter<'a,'b,'c> {
+        self.iter()
+    }
+}
+
+impl <'a,'b,'c> IntoIterator for &Captures<'a,'b,'c> {
+    type Item = &'b str;
+    type IntoIter = CapturesIter<'a,'b,'c>;
+
+    fn into_iter(self) -> CapturesIter<'a,'b,'c> {
+        self.iter()
+    }
+}
+
+/// Iterator over string captures, from `Captures::iter`
+// lifetimes as for Captures above!
+pub struct CapturesIter<'a,'b,'c> where 'a: 'c {
+    captures: Captures<'a,'b,'c>,
+    indices: ops::Range<usize>,
+}
+
+impl <'a,'b,'c> Iterator for CapturesIter<'a,'b,'c> {
+    type Item = &'b str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.indices.next().map(|i| self.captures.get(i))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.indices.size_hint()
+    }
+}
+
+impl <'a,'b,'c> DoubleEndedIterator for CapturesIter<'a,'b,'c> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.indices.next_back().map(|i| self.captures.get(i))
+    }
 }
 
+impl <'a,'b,'c> ExactSizeIterator for CapturesIter<'a,'b,'c> {}
+
 
 /// Low-overhead convenient access to byte match captures
-pub struct ByteCaptures<'a,'b> {
-    m: &'a LuaPattern<'a>,
+// lifetimes as for Captures above!
+#[derive(Clone,Copy)]
+pub struct ByteCaptures<'a,'b,'c> where 'a: 'c {
+    m: &'c LuaPattern<'a>,
     bytes: &'b [u8]
 }
 
-impl <'a,'b> ByteCaptures<'a,'b> {
+impl <'a,'b,'c> ByteCaptures<'a,'b,'c> {
     /// get the capture as a byte slice
     pub fn get(&self, i: usize) -> &'b [u8] {
         &self.bytes[self.m.capture(i)]
     }
 
+    /// get the capture as a string, replacing invalid UTF-8 with U+FFFD
+    ///
+    /// This only allocates if the capture is not valid UTF-8.
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("(%S+) (%S+)");
+    /// let res = m.gsub_bytes_with(b"GET caf\xE9", |cc| {
+    ///     assert_eq!(cc.get_lossy(1), "GET");
+    ///     cc.get_lossy(2).into_owned().into_bytes()
+    /// });
+    /// assert_eq!(res, "caf\u{FFFD}".as_bytes());
+    /// ```
+    pub fn get_lossy(&self, i: usize) -> Cow<'b,str> {
+        String::from_utf8_lossy(self.get(i))
+    }
+
+    /// get the capture as a string, if it is valid UTF-8
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("(%S+) (%S+)");
+    /// m.gsub_bytes_with(b"GET caf\xE9", |cc| {
+    ///     assert_eq!(cc.get_str(1), Ok("GET"));
+    ///     assert!(cc.get_str(2).is_err());
+    ///     Vec::new()
+    /// });
+    /// ```
+    pub fn get_str(&self, i: usize) -> Result<&'b str,str::Utf8Error> {
+        str::from_utf8(self.get(i))
+    }
+
+    /// get the range of the capture in the bytes
+    pub fn range(&self, i: usize) -> ops::Range<usize> {
+        self.m.capture(i)
+    }
+
     /// number of matches
     pub fn num_matches(&self) -> usize {
         self.m.n_match
     }
+
+    /// iterate over the captures, starting with the whole match
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::from_bytes(b"(.)(.)");
+    /// let res = m.gsub_bytes_with(b"\x01\x02", |cc| cc.iter().rev().take(2).flatten().cloned().collect());
+    /// assert_eq!(res, b"\x02\x01");
+    /// ```
+    pub fn iter(&self) -> ByteCapturesIter<'a,'b,'c> {
+        ByteCapturesIter{captures: *self, indices: 0..self.num_matches()}
+    }
+}
+
+impl <'a,'b,'c> IntoIterator for ByteCaptures<'a,'b,'c> {
+    type Item = &'b [u8];
+    type IntoIter = ByteCapturesIter<'a,'b,'c>;
+
+    fn into_iter(self) -> ByteCapturesIter<'a,'b,'c> {
+        self.iter()
+    }
+}
+
+impl <'a,'b,'c> IntoIterator for &ByteCaptures<'a,'b,'c> {
+    type Item = &'b [u8];
+    type IntoIter = ByteCapturesIter<'a,'b,'c>;
+
+    fn into_iter(self) -> ByteCapturesIter<'a,'b,'c> {
+        self.iter()
+    }
+}
+
+/// Iterator over byte captures, from `ByteCaptures::iter`
+// lifetimes as for Captures above!
+pub struct ByteCapturesIter<'a,'b,'c> where 'a: 'c {
+    captures: ByteCaptures<'a,'b,'c>,
+    indices: ops::Range<usize>,
+}
+
+impl <'a,'b,'c> Iterator for ByteCapturesIter<'a,'b,'c> {
+    type Item = &'b [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.indices.next().map(|i| self.captures.get(i))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.indices.size_hint()
+    }
+}
+
+impl <'a,'b,'c> DoubleEndedIterator for ByteCapturesIter<'a,'b,'c> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.indices.next_back().map(|i| self.captures.get(i))
+    }
+}
+
+impl <'a,'b,'c> ExactSizeIterator for ByteCapturesIter<'a,'b,'c> {}
+
+// Position of a global search, which follows Lua 5.4 in not
+// allowing an empty match straight after the previous match
+struct Cursor {
+    init: usize,
+    last_end: Option<usize>,
+    utf8: bool
+}
+
+impl Cursor {
+    fn new(utf8: bool) -> Cursor {
+        Cursor{init: 0, last_end: None, utf8}
+    }
+
+    // the position after `pos`, which stays on a char boundary for strings
+    fn step(&self, s: &[u8], pos: usize) -> usize {
+        let mut pos = pos + 1;
+        if self.utf8 {
+            while pos < s.len() && (s[pos] & 0xC0) == 0x80 {
+                pos += 1;
+            }
+        }
+        pos
+    }
+
+    // move past the match `all`; false if it is an empty match straight
+    // after the previous match, which does not count
+    fn advance(&mut self, s: &[u8], all: ops::Range<usize>) -> bool {
+        let empty = all.start == all.end;
+        if empty && self.last_end == Some(all.end) {
+            self.init = self.step(s, all.end);
+            return false;
+        }
+        self.last_end = Some(all.end);
+        self.init = if empty {self.step(s, all.end)} else {all.end};
+        true
+    }
 }
 
 /// Iterator for all string slices from `gmatch`
 // note lifetimes as for Captures above!
 pub struct GMatch<'a,'b,'c> where 'a: 'c {
     m: &'c mut LuaPattern<'a>,
-    text: &'b str
+    text: &'b str,
+    cursor: Cursor
+}
+
+impl <'a,'b,'c> GMatch<'a,'b,'c> {
+    /// The range of the last match in the original string
+    ///
+    /// ```
+    /// let mut m = lua_patterns::LuaPattern::new("%a+");
+    /// let mut iter = m.gmatch("dog  cat");
+    /// iter.next();
+    /// iter.next();
+    /// assert_eq!(iter.range(), 5..8);
+    /// ```
+    pub fn range(&self) -> ops::Range<usize> {
+        self.m.range()
+    }
+
+    /// The next match, reporting errors such as running out of steps
+    pub fn try_next(&mut self) -> Result<Option<&'b str>,PatternError> {
+        Ok(if self.m.try_find_next(self.text.as_bytes(), &mut self.cursor)? {
+            Some(&self.text[self.m.first_capture()])
+        } else {
+            None
+        })
+    }
 }
 
 impl <'a,'b,'c>Iterator for GMatch<'a,'b,'c> {
     type Item = &'b str;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if ! self.m.matches(self.text) {
+        if ! self.m.find_next(self.text.as_bytes(), &mut self.cursor) {
             None
         } else {
-            let slice = &self.text[self.m.first_capture()];
-            self.text = &self.text[self.m.range().end..];
-            Some(slice)
+            Some(&self.text[self.m.first_capture()])
         }
     }
 
 }
 
-/// Unsafe version of Captures, needed for gmatch_captures
-// It's unsafe because the lifetime only depends on the original
-// text, not the borrowed matches.
-pub struct CapturesUnsafe<'b>{
-    matches: *const LuaMatch,
-    text: &'b str
+/// Iterator for all ranges from `gmatch_ranges` and `gmatch_bytes_ranges`
+pub struct GMatchRanges<'a,'b,'c> where 'a: 'c {
+    m: &'c mut LuaPattern<'a>,
+    bytes: &'b [u8],
+    cursor: Cursor
 }
 
-impl <'b> CapturesUnsafe<'b> {
-    /// get the capture as a string slice
-    pub fn get(&self, i: usize) -> &'b str {
-        unsafe {
-            let p = self.matches.offset(i as isize);
-            let range =
-                ops::Range{
-                    start: (*p).start as usize,
-                    end: (*p).end as usize
-                };
-            &self.text[range]
+impl <'a,'b,'c> Iterator for GMatchRanges<'a,'b,'c> {
+    type Item = ops::Range<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if ! self.m.find_next(self.bytes, &mut self.cursor) {
+            None
+        } else {
+            Some(self.m.first_capture())
         }
     }
 }
 
-/// Streaming iterator for all captures from `gmatch_captures`
-// lifetimes as for Captures above!
-// 'a is pattern, 'b is text, 'c is ref to LuaPattern
-pub struct GMatchCaptures<'a,'b,'c> where 'a: 'c {
-    m: &'c mut LuaPattern<'a>,
-    text: &'b str
+/// Iterator for all offsets and string slices from `gmatch_indexed`
+pub struct GMatchIndexed<'a,'b,'c> where 'a: 'c {
+    ranges: GMatchRanges<'a,'b,'c>,
+    text: &'b str,
 }
 
-impl <'a,'b,'c> Iterator for GMatchCaptures<'a,'b,'c>  where 'a: 'c {
-    type Item = CapturesUnsafe<'b>;
+impl <'a,'b,'c> Iterator for GMatchIndexed<'a,'b,'c> {
+    type Item = (usize, &'b str);
 
     fn next(&mut self) -> Option<Self::Item> {
-        if ! self.m.matches(self.text) {
-            None
-        } else {
-            let split = self.text.split_at(self.m.range().end);
-            self.text = split.1;
-            let match_ptr: *const LuaMatch = self.m.matches.as_ptr();
-            Some(CapturesUnsafe{matches: match_ptr, text: split.0})
-        }
+        self.ranges.next().map(|r| (r.start, &self.text[r]))
     }
+}
 
+/// Iterator for all string slices from `try_gmatch`
+pub struct TryGMatch<'a,'b,'c> where 'a: 'c {
+    iter: GMatch<'a,'b,'c>,
+    failed: bool
 }
 
-/// Iterator for all byte slices from `gmatch_bytes`
-pub struct GMatchBytes<'a,'b> {
-    m: &'a mut LuaPattern<'a>,
-    bytes: &'b [u8]
+impl <'a,'b,'c> TryGMatch<'a,'b,'c> {
+    /// The range of the last match in the original string
+    pub fn range(&self) -> ops::Range<usize> {
+        self.iter.range()
+    }
 }
 
-impl <'a,'b>Iterator for GMatchBytes<'a,'b> {
-    type Item = &'b [u8];
+impl <'a,'b,'c> Iterator for TryGMatch<'a,'b,'c> {
+    type Item = Result<&'b str,PatternError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if ! self.m.matches_bytes(self.bytes) {
-            None
-        } else {
-            let slice = &self.bytes[self.m.first_capture()];
-            self.bytes = &self.bytes[self.m.range().end..];
-            Some(slice)
+        if self.failed {
+            return None;
+        }
+        match self.iter.try_next() {
+            Ok(res) => res.map(Ok),
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
         }
     }
+}
 
+/// The captures of one match from `gmatch_captures`
+///
+/// This keeps its own copy of the capture ranges, so it only borrows
+/// the original text and can be kept. (The name is historical: it
+/// used to point into the pattern's results.)
+pub struct CapturesUnsafe<'b>{
+    ranges: Vec<ops::Range<usize>>,
+    text: &'b str
+}
+
+impl <'b> CapturesUnsafe<'b> {
+    /// get the capture as a string slice
+    ///
+    /// Panics if a capture splits a UTF-8 character, as `.` can; see the crate docs.
+    pub fn get(&self, i: usize) -> &'b str {
+        &self.text[self.range(i)]
+    }
+
+    /// get the range of the capture in the original string
+    ///
+    /// Panics if there is no such capture.
+    pub fn range(&self, i: usize) -> ops::Range<usize> {
+        assert!(i < self.ranges.len(), "no capture {}", i);
+        self.ranges[i].clone()
+    }
+
+    /// number of matches
+    pub fn num_matches(&self) -> usize {
+        self.ranges.len()
+    }
+}
+
+
+/// Streaming iterator for all captures from `gmatch_captures`
+// lifetimes as for Captures above!
+// 'a is pattern, 'b is text, 'c is ref to LuaPattern
+pub struct GMatchCaptures<'a,'b,'c> where 'a: 'c {
+    m: &'c mut LuaPattern<'a>,
+    text: &'b str,
+    cursor: Cursor
+}
+
+impl <'a,'b,'c> GMatchCaptures<'a,'b,'c> {
+    /// The range of the last match in the original string
+    pub fn range(&self) -> ops::Range<usize> {
+        self.m.range()
+    }
+}
+
+impl <'a,'b,'c> Iterator for GMatchCaptures<'a,'b,'c>  where 'a: 'c {
+    type Item = CapturesUnsafe<'b>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if ! self.m.find_next(self.text.as_bytes(), &mut self.cursor) {
+            None
+        } else {
+            Some(CapturesUnsafe{ranges: self.m.capture_ranges(), text: self.text})
+        }
+    }
+
+}
+
+/// The captures of one match from `gmatch_bytes_captures`
+///
+/// Like `CapturesUnsafe`, this keeps its own copy of the capture ranges.
+pub struct ByteCapturesUnsafe<'b>{
+    ranges: Vec<ops::Range<usize>>,
+    bytes: &'b [u8]
+}
+
+impl <'b> ByteCapturesUnsafe<'b> {
+    /// get the capture as a byte slice
+    pub fn get(&self, i: usize) -> &'b [u8] {
+        &self.bytes[self.range(i)]
+    }
+
+    /// get the range of the capture in the original bytes
+    pub fn range(&self, i: usize) -> ops::Range<usize> {
+        assert!(i < self.ranges.len(), "no capture {}", i);
+        self.ranges[i].clone()
+    }
+
+    /// number of matches
+    pub fn num_matches(&self) -> usize {
+        self.ranges.len()
+    }
+}
+
+/// Streaming iterator for all captures from `gmatch_bytes_captures`
+// lifetimes as for Captures above!
+pub struct GMatchBytesCaptures<'a,'b,'c> where 'a: 'c {
+    m: &'c mut LuaPattern<'a>,
+    bytes: &'b [u8],
+    cursor: Cursor
+}
+
+impl <'a,'b,'c> GMatchBytesCaptures<'a,'b,'c> {
+    /// The range of the last match in the original bytes
+    pub fn range(&self) -> ops::Range<usize> {
+        self.m.range()
+    }
+}
+
+impl <'a,'b,'c> Iterator for GMatchBytesCaptures<'a,'b,'c> where 'a: 'c {
+    type Item = ByteCapturesUnsafe<'b>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if ! self.m.find_next(self.bytes, &mut self.cursor) {
+            None
+        } else {
+            Some(ByteCapturesUnsafe{ranges: self.m.capture_ranges(), bytes: self.bytes})
+        }
+    }
+}
+
+/// Iterator for all byte slices from `gmatch_bytes`
+// lifetimes as for Captures above!
+pub struct GMatchBytes<'a,'b,'c> where 'a: 'c {
+    m: &'c mut LuaPattern<'a>,
+    bytes: &'b [u8],
+    cursor: Cursor
+}
+
+impl <'a,'b,'c> GMatchBytes<'a,'b,'c> {
+    /// The range of the last match in the original bytes
+    pub fn range(&self) -> ops::Range<usize> {
+        self.m.range()
+    }
+
+    /// The next match, reporting errors such as running out of steps
+    pub fn try_next(&mut self) -> Result<Option<&'b [u8]>,PatternError> {
+        Ok(if self.m.try_find_next(self.bytes, &mut self.cursor)? {
+            Some(&self.bytes[self.m.first_capture()])
+        } else {
+            None
+        })
+    }
+}
+
+impl <'a,'b,'c>Iterator for GMatchBytes<'a,'b,'c> {
+    type Item = &'b [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if ! self.m.find_next(self.bytes, &mut self.cursor) {
+            None
+        } else {
+            Some(&self.bytes[self.m.first_capture()])
+        }
+    }
+
+}
+
+/// Iterator for all string slices from `into_gmatch`, owning the pattern
+pub struct IntoGMatch<'a,'b> {
+    m: LuaPattern<'a>,
+    text: &'b str,
+    cursor: Cursor
+}
+
+impl <'a,'b> IntoGMatch<'a,'b> {
+    /// The range of the last match in the original string
+    pub fn range(&self) -> ops::Range<usize> {
+        self.m.range()
+    }
+}
+
+impl <'a,'b> Iterator for IntoGMatch<'a,'b> {
+    type Item = &'b str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if ! self.m.find_next(self.text.as_bytes(), &mut self.cursor) {
+            None
+        } else {
+            Some(&self.text[self.m.first_capture()])
+        }
+    }
+
+}
+
+/// Iterator for all byte slices from `into_gmatch_bytes`, owning the pattern
+pub struct IntoGMatchBytes<'a,'b> {
+    m: LuaPattern<'a>,
+    bytes: &'b [u8],
+    cursor: Cursor
+}
+
+impl <'a,'b> IntoGMatchBytes<'a,'b> {
+    /// The range of the last match in the original bytes
+    pub fn range(&self) -> ops::Range<usize> {
+        self.m.range()
+    }
+}
+
+impl <'a,'b> Iterator for IntoGMatchBytes<'a,'b> {
+    type Item = &'b [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if ! self.m.find_next(self.bytes, &mut self.cursor) {
+            None
+        } else {
+            Some(&self.bytes[self.m.first_capture()])
+        }
+    }
+
+}
+
+/// Iterator for the substrings between matches from `split`, `splitn`
+/// and `split_terminator`
+// lifetimes as for Captures above!
+pub struct Split<'a,'b,'c> where 'a: 'c {
+    m: &'c mut LuaPattern<'a>,
+    text: &'b str,
+    // start of the current piece, and where to search next
+    start: usize,
+    init: usize,
+    limit: Option<usize>,
+    terminator: bool,
+    done: bool
+}
+
+impl <'a,'b,'c> Split<'a,'b,'c> {
+    fn finish(&mut self) -> Option<&'b str> {
+        self.done = true;
+        let rest = &self.text[self.start..];
+        if self.terminator && rest.is_empty() {
+            None
+        } else {
+            Some(rest)
+        }
+    }
+}
+
+impl <'a,'b,'c> Iterator for Split<'a,'b,'c> {
+    type Item = &'b str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if let Some(n) = self.limit {
+            match n {
+                0 => { self.done = true; return None; },
+                1 => return self.finish(),
+                _ => self.limit = Some(n - 1)
+            }
+        }
+        let text = self.text;
+        let s = text.as_bytes();
+        let step = |pos: usize| pos + text[pos..].chars().next().map_or(1, |c| c.len_utf8());
+        loop {
+            // an anchored pattern can only match at the start
+            let anchored = self.init > 0 && self.m.patt.first() == Some(&b'^');
+            if anchored || self.init > s.len() || ! self.m.matches_bytes_at(s, self.init, false) {
+                return self.finish();
+            }
+            let all = self.m.range();
+            if all.start == all.end && (all.end == self.start || all.end == s.len()) {
+                // empty match at start of piece or end of text: look further on
+                self.init = step(all.end);
+                continue;
+            }
+            let piece = &text[self.start..all.start];
+            self.start = all.end;
+            self.init = if all.start == all.end {step(all.end)} else {all.end};
+            return Some(piece);
+        }
+    }
+
+}
+
+/// Split a string into whitespace-separated fields
+///
+/// Leading and trailing whitespace is ignored, so there are
+/// never any empty fields.
+///
+/// ```
+/// let words: Vec<_> = lua_patterns::fields("  dog cat\tleopard\n").collect();
+/// assert_eq!(words, &["dog","cat","leopard"]);
+/// ```
+pub fn fields<'b>(text: &'b str) -> Fields<'b> {
+    Fields{m: LuaPattern::new("%S+"), text}
+}
+
+/// Iterator for whitespace-separated fields from `fields`
+pub struct Fields<'b> {
+    m: LuaPattern<'static>,
+    text: &'b str
+}
+
+impl <'b> Iterator for Fields<'b> {
+    type Item = &'b str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if ! self.m.matches(self.text) {
+            None
+        } else {
+            let slice = &self.text[self.m.range()];
+            self.text = &self.text[self.m.range().end..];
+            Some(slice)
+        }
+    }
+
+}
+
+// a failed match, treating running out of steps or stack as no match
+fn or_no_match(res: Result<bool,PatternError>) -> bool {
+    match res {
+        Ok(res) => res,
+        Err(ref e) if e.is_step_limit() || e.is_too_complex() => false,
+        Err(e) => panic!("Should not fail - report as bug: {}", e)
+    }
+}
+
+/// A validated Lua pattern which owns its bytes
+///
+/// `LuaPattern` borrows its pattern, which is awkward for patterns loaded
+/// at run time, for instance from configuration files. Use `pattern` to get
+/// a `LuaPattern` for matching.
+///
+/// ```
+/// let owned: lua_patterns::LuaPatternOwned = "(%a+)=(%d+)".parse().unwrap();
+/// let mut m = owned.pattern();
+/// assert_eq!(m.captures("k=10"), &["k=10","k","10"]);
+/// assert!(lua_patterns::LuaPatternOwned::new("(%a+").is_err());
+/// ```
+#[derive(Clone,PartialEq,Eq,Hash)]
+pub struct LuaPatternOwned {
+    patt: Vec<u8>,
+}
+
+impl LuaPatternOwned {
+    /// Maybe create a new owned pattern from a string
+    pub fn new(patt: &str) -> Result<LuaPatternOwned,PatternError> {
+        LuaPatternOwned::from_bytes(patt.as_bytes().to_vec())
+    }
+
+    /// Maybe create a new owned pattern from bytes
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<LuaPatternOwned,PatternError> {
+        str_check(&bytes, LUA_MAXCAPTURES)?;
+        Ok(LuaPatternOwned{patt: bytes})
+    }
+
+    /// Maybe create a new owned pattern written in verbose form
+    ///
+    /// Whitespace is ignored and `#` starts a comment running to the end
+    /// of the line, except when escaped with `%` or inside a set.
+    /// Use `% ` for a literal space and `%#` for a literal hash.
+    ///
+    /// ```
+    /// let owned = lua_patterns::LuaPatternOwned::verbose("
+    ///     ^ (%a+)      # key
+    ///     %s* = %s*
+    ///     ([^# ]+)     # value, up to a space or comment
+    /// ").unwrap();
+    /// assert_eq!(owned.as_str(), Some("^(%a+)%s*=%s*([^# ]+)"));
+    /// assert_eq!(owned.pattern().captures("k = v # c"), &["k = v","k","v"]);
+    /// ```
+    pub fn verbose(patt: &str) -> Result<LuaPatternOwned,PatternError> {
+        LuaPatternOwned::from_bytes(strip_verbose(patt.as_bytes()))
+    }
+
+    /// A `LuaPattern` for matching with this pattern
+    ///
+    /// The pattern was checked when this was created, so it is not checked again.
+    pub fn pattern(&self) -> LuaPattern<'_> {
+        LuaPattern::checked(&self.patt)
+    }
+
+    /// The pattern as bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.patt
+    }
+
+    /// The pattern as a string, if it is valid UTF-8
+    pub fn as_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.patt).ok()
+    }
+}
+
+impl fmt::Debug for LuaPatternOwned {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("LuaPatternOwned").field(&String::from_utf8_lossy(&self.patt)).finish()
+    }
+}
+
+impl <'a> fmt::Debug for LuaPattern<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("LuaPattern").field(&String::from_utf8_lossy(self.patt)).finish()
+    }
+}
+
+impl <'a> PartialEq for LuaPattern<'a> {
+    fn eq(&self, other: &LuaPattern<'a>) -> bool {
+        self.patt == other.patt
+    }
+}
+
+impl <'a> Eq for LuaPattern<'a> {}
+
+impl <'a> std::hash::Hash for LuaPattern<'a> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.patt.hash(state);
+    }
+}
+
+impl std::str::FromStr for LuaPatternOwned {
+    type Err = PatternError;
+
+    fn from_str(patt: &str) -> Result<LuaPatternOwned,PatternError> {
+        LuaPatternOwned::new(patt)
+    }
+}
+
+/// A pattern checked once per process, for use in statics
+///
+/// Creating a `LuaPattern` checks the pattern each time; a `StaticPattern`
+/// checks it on first use and then hands out matchers without checking again.
+/// It can be shared between threads, since each matcher has its own state.
+///
+/// ```
+/// use lua_patterns::StaticPattern;
+/// static PAIR: StaticPattern = StaticPattern::new("(%a+)=(%d+)");
+///
+/// fn pair(s: &str) -> Option<(&str,&str)> {
+///     PAIR.pattern().match_maybe_2(s)
+/// }
+/// assert_eq!(pair("k=10"), Some(("k","10")));
+/// assert_eq!(pair("k=v"), None);
+/// ```
+#[derive(Debug)]
+pub struct StaticPattern {
+    patt: &'static str,
+    checked: std::sync::OnceLock<Result<(),PatternError>>,
+}
+
+impl StaticPattern {
+    /// A pattern which will be checked when first used
+    pub const fn new(patt: &'static str) -> StaticPattern {
+        StaticPattern{patt, checked: std::sync::OnceLock::new()}
+    }
+
+    /// Maybe get a matcher for this pattern
+    pub fn try_pattern(&self) -> Result<LuaPattern<'static>,PatternError> {
+        let patt = self.patt.as_bytes();
+        match self.checked.get_or_init(|| str_check(patt, LUA_MAXCAPTURES)) {
+            Ok(()) => Ok(LuaPattern::checked(patt)),
+            Err(e) => Err(e.clone())
+        }
+    }
+
+    /// Get a matcher for this pattern, panicking if bad
+    pub fn pattern(&self) -> LuaPattern<'static> {
+        self.try_pattern().expect("bad pattern")
+    }
+
+    /// The pattern as a string
+    pub fn as_str(&self) -> &'static str {
+        self.patt
+    }
 }
 
 /// Build a byte Lua pattern, optionally escaping 'magic' characters
+#[derive(Default)]
 pub struct LuaPatternBuilder {
-    bytes: Vec<u8>
+    bytes: Vec<u8>,
+    captures: usize,
+    open: usize,
+    error: Option<PatternError>,
 }
 
 impl LuaPatternBuilder {
     /// Create a new Lua pattern builder
     pub fn new() -> LuaPatternBuilder {
-        LuaPatternBuilder{bytes: Vec::new()}
+        LuaPatternBuilder::default()
+    }
+
+    /// Add unescaped characters from a string
+    ///
+    /// ```
+    /// let patt = lua_patterns::LuaPatternBuilder::new()
+    ///     .text("(boo)")
+    ///     .build();
+    /// assert_eq!(std::str::from_utf8(&patt).unwrap(), "(boo)");
+    /// ```
+    pub fn text(&mut self, s: &str) -> &mut Self {
+        self.bytes.extend_from_slice(s.as_bytes());
+        self
+    }
+
+    /// Add unescaped characters from lines
+    ///
+    /// This looks for first non-whitespace run in each line,
+    /// useful for spreading patterns out and commmenting them.
+    /// Works with patterns that use '%s' religiously!
+    ///
+    /// ```
+    /// let patt = lua_patterns::LuaPatternBuilder::new()
+    ///     .text_lines("
+    ///       hello-dolly
+    ///       you-are-fine  # comment
+    ///       cool
+    ///      ")
+    ///     .build();
+    /// assert_eq!(std::str::from_utf8(&patt).unwrap(),
+    ///   "hello-dollyyou-are-finecool");
+    /// ```
+    pub fn text_lines(&mut self, lines: &str) -> &mut Self {
+        let mut text = String::new();
+        for line in lines.lines() {
+            if let Some(first) = line.split_whitespace().next() {
+                text.push_str(first);
+            }
+        }
+        self.text(&text)
+    }
+
+    /// Add unescaped characters from a verbose pattern
+    ///
+    /// Like `text`, but whitespace and `#` comments are dropped,
+    /// as with `LuaPatternOwned::verbose`.
+    ///
+    /// ```
+    /// let patt = lua_patterns::LuaPatternBuilder::new()
+    ///     .verbose("
+    ///       (%d+)  # year
+    ///       -
+    ///       (%d+)  # month
+    ///     ")
+    ///     .build();
+    /// assert_eq!(std::str::from_utf8(&patt).unwrap(), "(%d+)-(%d+)");
+    /// ```
+    pub fn verbose(&mut self, patt: &str) -> &mut Self {
+        self.bytes.extend(strip_verbose(patt.as_bytes()));
+        self
+    }
+
+    /// Add escaped bytes from a slice
+    ///
+    /// ```
+    /// let patt = lua_patterns::LuaPatternBuilder::new()
+    ///     .text("^")
+    ///     .bytes(b"^") // magic character!
+    ///     .build();
+    /// assert_eq!(std::str::from_utf8(&patt).unwrap(), "^%^");
+    /// ```
+    pub fn bytes(&mut self, b: &[u8]) -> &mut Self {
+        let mut m = LuaPattern::new("[%-%.%+%[%]%(%)%$%^%%%?%*]");
+        let bb = m.gsub_bytes_with(b,|cc| {
+            vec![b'%', cc.get(0)[0]]
+        });
+        self.bytes.extend(bb);
+        self
+    }
+
+    /// Add escaped bytes from hex string
+    ///
+    /// This consists of adjacent pairs of hex digits.
+    ///
+    /// ```
+    /// let patt = lua_patterns::LuaPatternBuilder::new()
+    ///     .text("^")
+    ///     .bytes_as_hex("5E") // which is ASCII '^'
+    ///     .build();
+    /// assert_eq!(std::str::from_utf8(&patt).unwrap(), "^%^");
+    /// ```
+    pub fn bytes_as_hex(&mut self, bs: &str) -> &mut Self {
+        let bb = LuaPatternBuilder::hex_to_bytes(bs);
+        self.bytes(&bb)
+    }
+
+    /// Add a character class like `%d`, without needing to remember its letter
+    ///
+    /// ```
+    /// use lua_patterns::parse::ClassKind;
+    /// let patt = lua_patterns::LuaPatternBuilder::new()
+    ///     .class(ClassKind::Letter)
+    ///     .text("+")
+    ///     .class(ClassKind::Space)
+    ///     .build();
+    /// assert_eq!(std::str::from_utf8(&patt).unwrap(), "%a+%s");
+    /// ```
+    pub fn class(&mut self, kind: parse::ClassKind) -> &mut Self {
+        self.bytes.extend_from_slice(&[b'%', kind.letter()]);
+        self
+    }
+
+    /// Add the complement of a character class, like `%D` for anything but a digit
+    ///
+    /// ```
+    /// use lua_patterns::parse::ClassKind;
+    /// let patt = lua_patterns::LuaPatternBuilder::new()
+    ///     .not_class(ClassKind::Digit)
+    ///     .class(ClassKind::Digit)
+    ///     .build();
+    /// assert_eq!(std::str::from_utf8(&patt).unwrap(), "%D%d");
+    /// ```
+    pub fn not_class(&mut self, kind: parse::ClassKind) -> &mut Self {
+        let class = parse::Class{kind, negated: true};
+        self.bytes.extend_from_slice(&[b'%', class.letter()]);
+        self
+    }
+
+    /// Add a set matching any one of the characters in `chars`
+    ///
+    /// Characters which are special inside a set are escaped.
+    ///
+    /// ```
+    /// let patt = lua_patterns::LuaPatternBuilder::new()
+    ///     .set("a-z]")
+    ///     .build();
+    /// assert_eq!(std::str::from_utf8(&patt).unwrap(), "[a%-z%]]");
+    /// ```
+    pub fn set(&mut self, chars: &str) -> &mut Self {
+        self.set_of(chars.as_bytes(), false)
+    }
+
+    /// Add a set matching any character not in `chars`
+    ///
+    /// ```
+    /// let patt = lua_patterns::LuaPatternBuilder::new()
+    ///     .negated_set("^%")
+    ///     .build();
+    /// assert_eq!(std::str::from_utf8(&patt).unwrap(), "[^%^%%]");
+    /// ```
+    pub fn negated_set(&mut self, chars: &str) -> &mut Self {
+        self.set_of(chars.as_bytes(), true)
+    }
+
+    /// Add a set matching any byte from `lo` to `hi` inclusive
+    ///
+    /// ```
+    /// let patt = lua_patterns::LuaPatternBuilder::new()
+    ///     .range(b'a', b'f')
+    ///     .range(b'#', b'%')
+    ///     .build();
+    /// assert_eq!(std::str::from_utf8(&patt).unwrap(), "[a-f][#$%%]");
+    /// ```
+    pub fn range(&mut self, lo: u8, hi: u8) -> &mut Self {
+        if lo > hi {
+            self.fail(EmptySet, &format!("empty range '{}'-'{}'", lo as char, hi as char));
+        }
+        // Lua cannot use an escaped character as a range end, so ranges
+        // touching the special characters are written out in full
+        if b"%^".contains(&lo) || b"%]".contains(&hi) {
+            let chars: Vec<u8> = (lo..=hi).collect();
+            return self.set_of(&chars, false);
+        }
+        self.bytes.extend_from_slice(&[b'[', lo, b'-', hi, b']']);
+        self
+    }
+
+    fn set_of(&mut self, chars: &[u8], negated: bool) -> &mut Self {
+        if chars.is_empty() {
+            self.fail(EmptySet, "empty set");
+        }
+        self.bytes.push(b'[');
+        if negated {
+            self.bytes.push(b'^');
+        }
+        for &c in chars {
+            if b"]^-%".contains(&c) {
+                self.bytes.push(b'%');
+            }
+            self.bytes.push(c);
+        }
+        self.bytes.push(b']');
+        self
     }
 
-    /// Add unescaped characters from a string
+    /// Open a capture group
+    ///
+    /// Groups are numbered in the order they are opened, starting at 1;
+    /// `captures` gives the number of the group just opened.
     ///
     /// ```
-    /// let patt = lua_patterns::LuaPatternBuilder::new()
-    ///     .text("(boo)")
-    ///     .build();
-    /// assert_eq!(std::str::from_utf8(&patt).unwrap(), "(boo)");
+    /// let mut b = lua_patterns::LuaPatternBuilder::new();
+    /// b.begin_capture().text("%a+");
+    /// assert_eq!(b.captures(), 1);
+    /// let patt = b.end_capture().build();
+    /// assert_eq!(std::str::from_utf8(&patt).unwrap(), "(%a+)");
     /// ```
-    pub fn text(&mut self, s: &str) -> &mut Self {
-        self.bytes.extend_from_slice(s.as_bytes());
+    pub fn begin_capture(&mut self) -> &mut Self {
+        self.bytes.push(b'(');
+        self.captures += 1;
+        self.open += 1;
         self
     }
 
-    /// Add unescaped characters from lines
+    /// Close the most recently opened capture group
     ///
-    /// This looks for first non-whitespace run in each line,
-    /// useful for spreading patterns out and commmenting them.
-    /// Works with patterns that use '%s' religiously!
+    /// Closing a group that was never opened is reported by `try_build`.
+    pub fn end_capture(&mut self) -> &mut Self {
+        self.bytes.push(b')');
+        if self.open == 0 {
+            self.fail(NoOpenCapture, "unbalanced capture groups: closed with none open");
+        } else {
+            self.open -= 1;
+        }
+        self
+    }
+
+    /// Add a capture group whose contents are added by `f`
     ///
     /// ```
+    /// use lua_patterns::parse::ClassKind;
     /// let patt = lua_patterns::LuaPatternBuilder::new()
-    ///     .text_lines("
-    ///       hello-dolly
-    ///       you-are-fine  # comment
-    ///       cool
-    ///      ")
+    ///     .capture(|b| b.class(ClassKind::Letter).text("+"))
+    ///     .text("=")
+    ///     .capture(|b| b.class(ClassKind::Digit).text("+"))
     ///     .build();
-    /// assert_eq!(std::str::from_utf8(&patt).unwrap(),
-    ///   "hello-dollyyou-are-finecool");
+    /// assert_eq!(std::str::from_utf8(&patt).unwrap(), "(%a+)=(%d+)");
     /// ```
-    pub fn text_lines(&mut self, lines: &str) -> &mut Self {
-        let mut text = String::new();
-        for line in lines.lines() {
-            if let Some(first) = line.split_whitespace().next() {
-                text.push_str(first);
-            }
-        }
-        self.text(&text)
+    pub fn capture<F>(&mut self, f: F) -> &mut Self
+    where F: FnOnce(&mut LuaPatternBuilder) -> &mut LuaPatternBuilder {
+        self.begin_capture();
+        f(self);
+        self.end_capture()
     }
 
-    /// Add escaped bytes from a slice
+    /// Match the previous item zero or more times, as with `*`
     ///
     /// ```
+    /// use lua_patterns::parse::ClassKind;
     /// let patt = lua_patterns::LuaPatternBuilder::new()
-    ///     .text("^")
-    ///     .bytes(b"^") // magic character!
+    ///     .class(ClassKind::Space).many()
+    ///     .class(ClassKind::Digit).at_least_one()
+    ///     .text("%.").optional()
+    ///     .text(".").lazy_many()
     ///     .build();
-    /// assert_eq!(std::str::from_utf8(&patt).unwrap(), "^%^");
+    /// assert_eq!(std::str::from_utf8(&patt).unwrap(), "%s*%d+%.?.-");
     /// ```
-    pub fn bytes(&mut self, b: &[u8]) -> &mut Self {
-        let mut m = LuaPattern::new("[%-%.%+%[%]%(%)%$%^%%%?%*]");
-        let bb = m.gsub_bytes_with(b,|cc| {
-            let mut res = Vec::new();
-            res.push(b'%');
-            res.push(cc.get(0)[0]);
-            res
-        });
-        self.bytes.extend(bb);
+    pub fn many(&mut self) -> &mut Self {
+        self.quantifier(b'*')
+    }
+
+    /// Match the previous item one or more times, as with `+`
+    pub fn at_least_one(&mut self) -> &mut Self {
+        self.quantifier(b'+')
+    }
+
+    /// Match the previous item zero or one times, as with `?`
+    pub fn optional(&mut self) -> &mut Self {
+        self.quantifier(b'?')
+    }
+
+    /// Match the previous item zero or more times, as few as possible, as with `-`
+    pub fn lazy_many(&mut self) -> &mut Self {
+        self.quantifier(b'-')
+    }
+
+    // Lua only quantifies single character items, so `(%a)*` or `%d+*`
+    // would quietly mean something else; `try_build` reports these.
+    fn quantifier(&mut self, q: u8) -> &mut Self {
+        if ! ends_with_single(&self.bytes) {
+            self.fail(MisplacedQuantifier, &format!("quantifier '{}' does not follow a single character item", q as char));
+        }
+        self.bytes.push(q);
         self
     }
 
-    /// Add escaped bytes from hex string
+    fn fail(&mut self, kind: PatternErrorKind, msg: &str) {
+        if self.error.is_none() {
+            self.error = Some(PatternError::new(kind, msg));
+        }
+    }
+
+    /// Number of capture groups opened so far
+    pub fn captures(&self) -> usize {
+        self.captures
+    }
+
+    /// Create the pattern
+    pub fn build(&mut self) -> Vec<u8> {
+        self.captures = 0;
+        self.open = 0;
+        self.error = None;
+        std::mem::take(&mut self.bytes)
+    }
+
+    /// Create the pattern, checking that it is valid
     ///
-    /// This consists of adjacent pairs of hex digits.
+    /// Fails if `begin_capture` and `end_capture` calls do not pair up,
+    /// if a quantifier does not follow a single character item,
+    /// or if the resulting pattern is malformed.
     ///
     /// ```
-    /// let patt = lua_patterns::LuaPatternBuilder::new()
-    ///     .text("^")
-    ///     .bytes_as_hex("5E") // which is ASCII '^'
-    ///     .build();
-    /// assert_eq!(std::str::from_utf8(&patt).unwrap(), "^%^");
+    /// let mut b = lua_patterns::LuaPatternBuilder::new();
+    /// let err = b.begin_capture().text("%d+").try_build().unwrap_err();
+    /// assert_eq!(err.message(), "unbalanced capture groups: 1 left open");
+    ///
+    /// let err = b.text("%d+").end_capture().try_build().unwrap_err();
+    /// assert_eq!(err.message(), "unbalanced capture groups: closed with none open");
+    ///
+    /// let err = b.capture(|b| b.text("%d")).many().try_build().unwrap_err();
+    /// assert_eq!(err.message(), "quantifier '*' does not follow a single character item");
+    ///
+    /// let patt = b.capture(|b| b.text("%d+")).try_build().unwrap();
+    /// assert_eq!(patt, b"(%d+)");
     /// ```
-    pub fn bytes_as_hex(&mut self, bs: &str) -> &mut Self {
-        let bb = LuaPatternBuilder::hex_to_bytes(bs);
-        self.bytes(&bb)
+    pub fn try_build(&mut self) -> Result<Vec<u8>,PatternError> {
+        let (open, error) = (self.open, self.error.take());
+        let patt = self.build();
+        if let Some(err) = error {
+            return Err(err);
+        }
+        if open > 0 {
+            return Err(PatternError::new(UnfinishedCapture, &format!("unbalanced capture groups: {} left open", open)));
+        }
+        str_check(&patt, LUA_MAXCAPTURES)?;
+        Ok(patt)
     }
 
-    /// Create the pattern
-    pub fn build(&mut self) -> Vec<u8> {
-        let mut v = Vec::new();
-        std::mem::swap(&mut self.bytes, &mut v);
-        v
+    /// Create a validated owned pattern, ready for matching
+    ///
+    /// Fails for the same reasons as `try_build`.
+    ///
+    /// ```
+    /// use lua_patterns::parse::ClassKind;
+    /// let owned = lua_patterns::LuaPatternBuilder::new()
+    ///     .capture(|b| b.class(ClassKind::Digit).at_least_one())
+    ///     .build_pattern().unwrap();
+    /// assert_eq!(owned.pattern().match_maybe("in 2024"), Some("2024"));
+    /// ```
+    pub fn build_pattern(&mut self) -> Result<LuaPatternOwned,PatternError> {
+        self.try_build().map(|patt| LuaPatternOwned{patt})
     }
 
     /// Utility to create a vector of bytes from a hex string
@@ -677,6 +3276,82 @@ impl LuaPatternBuilder {
 
 }
 
+// drop whitespace and comments from a verbose pattern, keeping
+// escapes, the arguments of %b and the contents of sets intact
+fn strip_verbose(patt: &[u8]) -> Vec<u8> {
+    let mut res = Vec::with_capacity(patt.len());
+    let mut i = 0;
+    while i < patt.len() {
+        let start = i;
+        match patt[i] {
+            b'#' => {
+                while i < patt.len() && patt[i] != b'\n' {
+                    i += 1;
+                }
+                continue;
+            },
+            c if c.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            },
+            b'%' => {
+                i = match patt.get(i+1) {
+                    Some(b'b') => i + 4,
+                    Some(b'f') if patt.get(i+2) == Some(&b'[') => skip_set(patt, i+2),
+                    _ => i + 2,
+                };
+            },
+            b'[' => { i = skip_set(patt, i); },
+            _ => { i += 1; }
+        }
+        res.extend_from_slice(&patt[start..i.min(patt.len())]);
+    }
+    res
+}
+
+// does the pattern so far end with an item that a quantifier may follow?
+fn ends_with_single(patt: &[u8]) -> bool {
+    let mut single = false;
+    let mut i = 0;
+    if patt.first() == Some(&b'^') {
+        i = 1;
+    }
+    while i < patt.len() {
+        match patt[i] {
+            b'%' => {
+                match patt.get(i+1) {
+                    Some(b'b') => { i += 4; single = false; },
+                    Some(b'f') => { i = skip_set(patt, i+2); single = false; },
+                    Some(c) if c.is_ascii_digit() => { i += 2; single = false; },
+                    _ => { i += 2; single = true; }
+                }
+            },
+            b'[' => { i = skip_set(patt, i); single = true; },
+            b'(' | b')' => { i += 1; single = false; },
+            // a quantifier character with nothing to quantify is a literal
+            b'*' | b'+' | b'-' | b'?' => { i += 1; single = ! single; },
+            _ => { i += 1; single = true; }
+        }
+    }
+    single
+}
+
+// index just past the set starting at `i`, or the end of the pattern
+fn skip_set(patt: &[u8], mut i: usize) -> usize {
+    i += 1;
+    if patt.get(i) == Some(&b'^') {
+        i += 1;
+    }
+    // a leading ']' is a literal member of the set
+    if patt.get(i) == Some(&b']') {
+        i += 1;
+    }
+    while i < patt.len() && patt[i] != b']' {
+        i += if patt[i] == b'%' { 2 } else { 1 };
+    }
+    i + 1
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -688,15 +3363,15 @@ mod tests {
         let empty: &[&str] = &[];
         assert_eq!(m.captures("four"), empty);
 
-        assert_eq!(m.matches("one dog"), true);
-        assert_eq!(m.matches("dog one "), true);
-        assert_eq!(m.matches("dog one"), false);
+        assert!(m.matches("one dog"));
+        assert!(m.matches("dog one "));
+        assert!(! m.matches("dog one"));
 
         let text = "one dog";
         let mut m = LuaPattern::new("^(%a+)");
-        assert_eq!(m.matches(text), true);
+        assert!(m.matches(text));
         assert_eq!(&text[m.capture(1)], "one");
-        assert_eq!(m.matches(" one dog"), false);
+        assert!(! m.matches(" one dog"));
 
         // captures without allocation
         m.matches(text);
@@ -722,6 +3397,90 @@ mod tests {
         assert_eq!(rest,"hello");
     }
 
+    #[test]
+    fn partition() {
+        let mut m = LuaPattern::new("%.");
+        assert_eq!(m.partition("lib.tar.gz"), Some(("lib",".","tar.gz")));
+        assert_eq!(m.rpartition("lib.tar.gz"), Some(("lib.tar",".","gz")));
+        assert_eq!(m.partition("readme"), None);
+        assert_eq!(m.rpartition("readme"), None);
+
+        let mut m = LuaPattern::new("%d+");
+        assert_eq!(m.rpartition("a12b345"), Some(("a12b34","5","")));
+    }
+
+    #[test]
+    fn rfind() {
+        let mut m = LuaPattern::new("(%a)(%d)");
+        let text = "a1 b2 c3 dd";
+        assert_eq!(m.rfind(text), Some(6..8));
+        assert_eq!(m.captures(text), &["a1","a","1"]);
+        assert_eq!(m.match_last(text), Some("c"));
+        assert_eq!(m.match_last("no digits"), None);
+        assert_eq!(m.rfind(&format!("x{}a1", " ".repeat(100))), Some(101..103));
+        assert_eq!(m.rfind(&format!("a1{}", " ".repeat(100))), Some(0..2));
+
+        // frontier sees the whole text
+        let mut m = LuaPattern::new("%f[%w]%w+");
+        assert_eq!(m.match_last("one two three"), Some("three"));
+        // empty match at the end
+        let mut m = LuaPattern::new("%s*$");
+        assert_eq!(m.rfind("abc"), Some(3..3));
+        // an anchored pattern only matches at the start
+        let mut m = LuaPattern::new("^a");
+        assert_eq!(m.rfind("aXa"), Some(0..1));
+        assert_eq!(m.rpartition("bXa"), None);
+        assert_eq!(m.match_last("a"), Some("a"));
+    }
+
+    #[test]
+    fn strip_and_trim() {
+        let mut m = LuaPattern::new("%s*#+%s*");
+        assert_eq!(m.strip_prefix("## title"), Some("title"));
+        assert_eq!(m.strip_suffix("title ##"), Some("title"));
+        assert_eq!(m.strip_suffix("title ## x"), None);
+        assert_eq!(m.trim_matches("# title #"), "title");
+
+        // already anchored at the end
+        let mut m = LuaPattern::new("%.txt$");
+        assert_eq!(m.strip_suffix("notes.txt"), Some("notes"));
+        let mut m = LuaPattern::new("%%$");
+        assert_eq!(m.strip_suffix("cost 5%"), Some("cost 5"));
+        let mut m = LuaPattern::new("%$");
+        assert_eq!(m.strip_suffix("cost 5$"), Some("cost 5"));
+        let mut m = LuaPattern::new("%b$$");
+        assert_eq!(m.strip_suffix("a $b$ c"), None);
+        assert_eq!(m.strip_suffix("a $b$"), Some("a "));
+
+        // empty matches are fine
+        let mut m = LuaPattern::new("%s*");
+        assert_eq!(m.strip_suffix("hello"), Some("hello"));
+        assert_eq!(m.trim_matches("  hello "), "hello");
+    }
+
+    #[test]
+    fn matches_full() {
+        let mut m = LuaPattern::new("%d+");
+        assert!(m.matches_full("123"));
+        assert!(! m.matches_full("123a"));
+        assert!(! m.matches_full("a123"));
+        assert!(! m.matches_full(""));
+
+        // lazy quantifiers must still reach the end
+        let mut m = LuaPattern::new("(a-)b-");
+        assert!(m.matches_full("aab"));
+        assert_eq!(m.captures("aab"), &["",""]);
+        assert!(m.matches_full("aab"));
+        assert_eq!(m.range(), 0..3);
+
+        let mut m = LuaPattern::new("^%a+$");
+        assert!(m.matches_full("abc"));
+        // the final '$' here closes a balanced match, not an anchor
+        let mut m = LuaPattern::new("%b$$");
+        assert!(! m.matches_full("$x$y"));
+        assert!(m.matches_full("$x$"));
+    }
+
     #[test]
     fn gmatch() {
         let mut m = LuaPattern::new("%a+");
@@ -742,7 +3501,134 @@ mod tests {
         let mut iter = m.gmatch_captures("one two three");
         assert_eq!(iter.next().unwrap().get(1), "one");
         assert_eq!(iter.next().unwrap().get(1), "two");
-        assert_eq!(iter.next().unwrap().get(1), "three");
+        let cc = iter.next().unwrap();
+        assert_eq!(cc.get(1), "three");
+        assert_eq!(cc.range(1), 8..13);
+        assert_eq!(iter.range(), 8..13);
+
+        // offsets are relative to the whole string
+        let mut m = LuaPattern::new("%a+");
+        let text = "one two three";
+        let mut iter = m.gmatch(text);
+        let mut ranges = Vec::new();
+        while let Some(word) = iter.next() {
+            assert_eq!(&text[iter.range()], word);
+            ranges.push(iter.range());
+        }
+        assert_eq!(ranges, &[0..3, 4..7, 8..13]);
+
+        // empty matches don't loop forever
+        let mut m = LuaPattern::new("a*");
+        let res: Vec<_> = m.gmatch("baaéc").collect();
+        assert_eq!(res, &["","aa","",""]);
+
+        // the frontier pattern sees the previous character
+        let mut m = LuaPattern::new("%f[%a]%a");
+        let res: Vec<_> = m.gmatch("ab cd").collect();
+        assert_eq!(res, &["a","c"]);
+    }
+
+    #[test]
+    fn split() {
+        let mut m = LuaPattern::new("%s*;%s*");
+        let split: Vec<_> = m.split("one; two ;three").collect();
+        assert_eq!(split, &["one","two","three"]);
+        let split: Vec<_> = m.split(";one;").collect();
+        assert_eq!(split, &["","one",""]);
+        let split: Vec<_> = m.split("").collect();
+        assert_eq!(split, &[""]);
+        let split: Vec<_> = m.split_terminator("one;two;").collect();
+        assert_eq!(split, &["one","two"]);
+        let split: Vec<_> = m.splitn("one;two;three", 2).collect();
+        assert_eq!(split, &["one","two;three"]);
+        assert_eq!(m.splitn("one;two", 0).count(), 0);
+
+        let mut m = LuaPattern::new("x*");
+        let split: Vec<_> = m.split("axxbc").collect();
+        assert_eq!(split, &["a","b","c"]);
+
+        // anchors and frontiers see the whole text
+        let mut m = LuaPattern::new("^,");
+        let split: Vec<_> = m.split(",,a").collect();
+        assert_eq!(split, &["",",a"]);
+        let mut m = LuaPattern::new("%f[%a]");
+        let split: Vec<_> = m.split("ab cd").collect();
+        assert_eq!(split, &["ab ","cd"]);
+        let mut m = LuaPattern::new("%f[%s]%s+");
+        let split: Vec<_> = m.splitn("é d  e", 2).collect();
+        assert_eq!(split, &["é","d  e"]);
+    }
+
+    #[test]
+    fn fields() {
+        let words: Vec<_> = super::fields(" one  two\tthree\n").collect();
+        assert_eq!(words, &["one","two","three"]);
+        assert_eq!(super::fields(" \t ").count(), 0);
+    }
+
+    #[test]
+    fn count() {
+        let mut m = LuaPattern::new("%d+");
+        assert_eq!(m.count("1 22 333 x"), 3);
+        assert_eq!(m.count("none"), 0);
+        assert_eq!(m.count(""), 0);
+
+        // empty matches, as for Lua 5.4's gmatch
+        let mut m = LuaPattern::new("x*");
+        assert_eq!(m.count("abc"), 4);
+        let mut m = LuaPattern::new("a*");
+        assert_eq!(m.count("baaac"), 3);
+    }
+
+    #[test]
+    fn reuse_after_iteration() {
+        // iterators only borrow the pattern while they are alive
+        let mut m = LuaPattern::from_bytes(b"%d+");
+        let first: Vec<_> = m.gmatch_bytes(b"1 22").collect();
+        let second: Vec<_> = m.gmatch_bytes(b"333").collect();
+        assert_eq!(first, &[b"1" as &[u8], b"22"]);
+        assert_eq!(second, &[b"333"]);
+        assert!(m.matches("4"));
+        let words: Vec<_> = m.gmatch("5 6").collect();
+        assert_eq!(words, &["5","6"]);
+        assert_eq!(m.count("7 8 9"), 3);
+    }
+
+    #[test]
+    fn match_fn() {
+        let mut names = vec!["alice".to_string(), "Bob".to_string(), "carol".to_string()];
+        names.retain(LuaPattern::new("^%l").into_match_fn());
+        assert_eq!(names, &["alice", "carol"]);
+        let has_digit = LuaPattern::new("%d").into_match_fn();
+        assert!(has_digit("a1") && ! has_digit("ab"));
+        // more captures than fit in the usual buffer
+        let patt = "(%a)".repeat(40);
+        let m = LuaPattern::new_with_max_captures(&patt, 40).unwrap();
+        let text = "x".repeat(40);
+        assert!(m.is_match(&text) && ! m.is_match(&text[1..]));
+        assert_eq!(m.find_captures(&text).unwrap().len(), 41);
+    }
+
+    #[test]
+    fn starts_and_ends() {
+        let m = LuaPattern::new("%d+");
+        assert!(m.starts_with_pattern("12 ab"));
+        assert!(! m.starts_with_pattern("ab 12"));
+        assert!(m.ends_with_pattern("ab 12"));
+        assert!(! m.ends_with_pattern("12 ab"));
+        assert!(! m.ends_with_pattern(""));
+        let m = LuaPattern::new("^a.*");
+        assert!(m.ends_with_pattern("abc"));
+        assert!(! m.ends_with_pattern("ba"));
+        let m = LuaPattern::new("é$");
+        assert!(m.ends_with_pattern("café"));
+        assert!(m.starts_with_pattern("é"));
+        assert!(! m.starts_with_pattern("éé"));
+        let m = LuaPattern::new("x*");
+        assert!(m.starts_with_pattern("") && m.ends_with_pattern("") && m.ends_with_pattern("ab"));
+        let m = LuaPattern::new("%b$$");
+        assert!(m.ends_with_pattern("a $b$"));
+        assert!(! m.ends_with_pattern("$b$ c"));
     }
 
     #[test]
@@ -765,6 +3651,101 @@ mod tests {
             |cc| map.get(cc.get(1)).unwrap_or(&"?").to_string()
         );
         assert_eq!(res, "hello baby you're so cool and pretty");
+        // in place, growing and shrinking in turn
+        let mut m = LuaPattern::new("%$%((.-)%)");
+        for text in &["$(dolly)$(good-looking)", "$(a)$(dolly)$(b) $(good-looking)$(c)", "x", "$(fine)"] {
+            for repl in &["", "[%1]", "%1%1", "<<<%0>>>"] {
+                let mut s = text.to_string();
+                let n = m.gsub_in_place(&mut s, repl).unwrap();
+                assert_eq!(s, m.gsub(text, repl));
+                assert_eq!(n, m.count(text));
+            }
+        }
+        // growing then shrinking back needs no more room
+        let mut m = LuaPattern::new("(%a)%d*");
+        let mut s = String::with_capacity(8);
+        s.push_str("a b12345");
+        assert_eq!(m.gsub_in_place(&mut s, "%1%1"), Ok(2));
+        assert_eq!(s, "aa bb");
+        assert_eq!(s.capacity(), 8);
+        let mut s = "x1 y z9".to_string();
+        m.gsub_in_place(&mut s, "<%0>").unwrap();
+        assert_eq!(s, "<x1> <y> <z9>");
+        // splitting a character, or a bad replacement, leaves the text alone
+        let mut s = "añb".to_string();
+        let err = LuaPattern::new("a(.)").gsub_in_place(&mut s, "%1").unwrap_err();
+        assert_eq!(err.kind(), PatternErrorKind::SplitsCharacter);
+        assert_eq!(m.gsub_in_place(&mut s, "%2").unwrap_err().kind(), PatternErrorKind::InvalidReplacement);
+        assert_eq!(s, "añb");
+        let mut m = LuaPattern::new("%$%((.-)%)");
+        let res = m.gsub_map("$(fine) $(dolly) $(shy) $(fine", &map);
+        assert_eq!(res, "cool baby $(shy) $(fine");
+        let mut m = LuaPattern::new("%a+");
+        assert_eq!(m.gsub_map("dolly is fine", &map), "baby is cool");
+
+        // with no captures, %1 is the whole match on every path
+        assert_eq!(m.gsub("hi there", "<%1>"), "<hi> <there>");
+        assert_eq!(m.try_gsub("hi there", "<%1>").unwrap(), "<hi> <there>");
+        assert_eq!(m.replace("hi there", "<%1>"), "<hi> there");
+        let mut out = String::new();
+        m.gsub_to("hi there", "<%1>", &mut out).unwrap();
+        assert_eq!(out, "<hi> <there>");
+        let mut s = "hi there".to_string();
+        m.gsub_in_place(&mut s, "%1%1").unwrap();
+        assert_eq!(s, "hihi therethere");
+        assert_eq!(m.gsub_bytes(b"hi there", b"<%1>"), b"<hi> <there>");
+        assert!(m.matches("hi"));
+        assert_eq!(Substitute::new("%1!").subst(&m, "hi"), "hi!");
+
+        // closures may keep state
+        let mut m = LuaPattern::new("%?");
+        let mut n = 0;
+        let res = m.gsub_with("a=? and b=?", |_| { n += 1; format!("${}", n) });
+        assert_eq!(res, "a=$1 and b=$2");
+        assert_eq!(n, 2);
+
+        // the closure knows where the match is
+        let mut m = LuaPattern::new("%$(%a+)");
+        let text = "$one\n---\n$two $three";
+        let body = text.find("---").unwrap();
+        let res = m.gsub_with(text, |cc| {
+            if cc.range(0).start > body { cc.get(1).to_uppercase() } else { cc.get(0).to_string() }
+        });
+        assert_eq!(res, "$one\n---\nTWO THREE");
+
+        // writing to a sink
+        let mut m = LuaPattern::new("(%a+)=(%d+)");
+        let mut out = String::new();
+        assert_eq!(m.gsub_to("a=1 b=2 c", "%2:%1", &mut out), Ok(2));
+        assert_eq!(out, "1:a 2:b c");
+        let mut out = Vec::new();
+        assert_eq!(m.gsub_bytes_to(b"a=1 b=2 c", b"%2:%1", &mut out).unwrap(), 2);
+        assert_eq!(out, b"1:a 2:b c");
+
+        // bad replacements are found before matching, even with no match
+        let mut out = String::new();
+        let err = m.gsub_to("none", "%3", &mut out).unwrap_err();
+        assert_eq!(err.kind(), PatternErrorKind::InvalidReplacement);
+        assert_eq!(out, "");
+        let mut out = Vec::new();
+        let err = m.gsub_bytes_to(b"none", b"100%", &mut out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(out.is_empty());
+
+        // anchored patterns only match at the start
+        let mut m = LuaPattern::new("^a");
+        assert_eq!(m.gsub("aaa", "b"), "baa");
+
+        // empty matches are as for Lua 5.4
+        let mut m = LuaPattern::new("a*");
+        assert_eq!(m.gsub("baaac", "-"), "-b-c-");
+        assert_eq!(m.gsub_bytes(b"baaac", b"-"), b"-b-c-");
+
+        let mut m = LuaPattern::new("%$%((.-)%)");
+        let res = m.try_gsub_with("$(dolly) and $(nobody)",
+            |cc| map.get(cc.get(1)).map(|s| s.to_string()).ok_or(cc.get(1).to_string())
+        );
+        assert_eq!(res, Err("nobody".to_string()));
 
         let mut m = LuaPattern::new("%s+");
         let res = m.gsub("hello dolly you're so fine","");
@@ -773,6 +3754,274 @@ mod tests {
         let mut m = LuaPattern::new("(%S+)%s*=%s*(%S+);%s*");
         let res = m.gsub("a=2; b=3; c = 4;", "'%2':%1 ");
         assert_eq!(res,"'2':a '3':b '4':c ");
+
+        let mut m = LuaPattern::new("(%a+)");
+        assert_eq!(m.replace("hello dolly", "<%1>"), "<hello> dolly");
+        assert_eq!(m.replacen("hello dolly you're", "<%1>", 2), "<hello> <dolly> you're");
+        assert!(matches!(m.replace("42", "<%1>"), Cow::Borrowed("42")));
+        let mut m = LuaPattern::new("x*");
+        assert_eq!(m.replacen("abc", "-", 0), "-a-b-c-");
+
+        let mut m = LuaPattern::from_bytes(b"(%x%x)=(%d)");
+        let res = m.gsub_bytes(b"\x00ab=1;cd=2\xFF", b"%2:%1%%");
+        assert_eq!(res, b"\x001:ab%;2:cd%\xFF");
+    }
+
+    #[test]
+    #[should_panic(expected = "not a char boundary")]
+    fn str_match_splitting_a_char() {
+        LuaPattern::new(".").match_maybe("é");
+    }
+
+    #[test]
+    #[should_panic(expected = "bad replacement")]
+    fn gsub_bad_replacement() {
+        LuaPattern::new("(%a+)").gsub("no match here!", "%2");
+    }
+
+    // millions of steps without the limit, far too slow under miri
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn step_limit() {
+        let mut m = LuaPattern::new("(.-)%s*$");
+        let text = format!("x{}x", " ".repeat(2000));
+        assert!(m.matches(&text));
+        m.set_step_limit(Some(10_000));
+        assert!(m.try_matches(&text).unwrap_err().is_step_limit());
+        assert!(! m.matches(&text));
+        assert_eq!(m.gsub(&text, "y"), text);
+        assert_eq!(m.try_matches("short  "), Ok(true));
+        m.set_step_limit(None);
+        assert_eq!(m.try_matches(&text), Ok(true));
+    }
+
+    #[test]
+    fn literal_prefix() {
+        let mut m = LuaPattern::new("ERROR: (%d+)");
+        let text = "ok\nERROR:x\nERROR: 42\n";
+        assert_eq!(m.match_maybe(text), Some("42"));
+        assert_eq!(m.range(), 11..20);
+        let mut m = LuaPattern::new("%((%a+)%)");
+        assert_eq!(m.match_maybe("a (b) c"), Some("b"));
+        // optional and repeated literals must not be skipped past
+        let mut m = LuaPattern::new("ab?c");
+        assert_eq!(m.match_maybe("xacx"), Some("ac"));
+        let mut m = LuaPattern::new("xa+b");
+        assert_eq!(m.match_maybe("xaaab"), Some("xaaab"));
+        let mut m = LuaPattern::new("a-b");
+        assert_eq!(m.match_maybe("b"), Some("b"));
+        let mut m = LuaPattern::new("ab");
+        assert!(! m.matches("a"));
+        assert_eq!(m.gmatch("ab ab aab").count(), 3);
+    }
+
+    #[test]
+    fn literal_patterns() {
+        let mut m = LuaPattern::new("%(a%.b%)");
+        assert!(m.matches("x (a.b)"));
+        assert_eq!(m.range(), 2..7);
+        assert!(! m.matches("(axb)"));
+        assert_eq!(m.gsub("(a.b)(a.b)", "-"), "--");
+        let mut m = LuaPattern::new("^ab");
+        assert!(m.matches("abc"));
+        assert!(! m.matches("cab"));
+        assert_eq!(m.gmatch("ababab").count(), 1);
+        let mut m = LuaPattern::new("ab");
+        assert!(m.matches_bytes_at(b"abab", 1, false));
+        assert_eq!(m.range(), 2..4);
+        assert!(! m.matches_bytes_at(b"abab", 1, true));
+        assert!(! m.matches_bytes_at(b"ab", 3, false));
+        assert_eq!(m.captures("xaby"), &["ab"]);
+        assert_eq!(m.rfind("abxab"), Some(3..5));
+    }
+
+    #[test]
+    fn class_prescan() {
+        let mut m = LuaPattern::new("%d+");
+        let text = format!("{}123{}45", "x".repeat(100), "y".repeat(50));
+        let v: Vec<_> = m.gmatch(&text).collect();
+        assert_eq!(v, &["123","45"]);
+        let mut m = LuaPattern::new("[%a_][%w_]*");
+        assert_eq!(m.match_maybe("  123 _foo9 "), Some("_foo9"));
+        let mut m = LuaPattern::new("[^%s]");
+        assert_eq!(m.match_maybe("   x"), Some("x"));
+        let mut m = LuaPattern::from_bytes(b"[\x80-\xff]+");
+        assert!(m.matches_bytes(b"abc\xc3\xa9d"));
+        assert_eq!(m.range(), 3..5);
+        // an optional class can match the empty string anywhere
+        let mut m = LuaPattern::new("%d*");
+        assert!(m.matches("abc"));
+        assert_eq!(m.range(), 0..0);
+        let mut m = LuaPattern::new("%d-x");
+        assert_eq!(m.match_maybe("abx"), Some("x"));
+    }
+
+    #[test]
+    fn max_captures() {
+        let patt = "(.)".repeat(32);
+        let text = "x".repeat(32);
+        let mut m = LuaPattern::new(&patt);
+        assert!(m.matches(&text));
+        assert_eq!(m.captures(&text).len(), 33);
+
+        let patt = "(.)".repeat(33);
+        assert_eq!(LuaPattern::new_try(&patt).err(), Some(PatternError::new(TooManyCaptures, "too many captures").at(96)));
+        let mut m = LuaPattern::new_with_max_captures(&patt, 100).unwrap();
+        assert_eq!(m.captures(&"x".repeat(33)).len(), 34);
+        assert!(LuaPattern::new_with_max_captures("(a)(b)", 1).is_err());
+    }
+
+    #[test]
+    fn back_references() {
+        let mut m = LuaPattern::new("(%a+) %1");
+        let text = String::from("say hello hello");
+        assert_eq!(m.match_maybe(&text), Some("hello"));
+        assert_eq!(text, "say hello hello");
+        assert!(! m.matches("hello world"));
+        assert_eq!(m.match_maybe("ab abc"), Some("ab"));
+        // a position capture never matches as a back reference
+        let mut m = LuaPattern::new("()a%1");
+        assert!(! m.matches("aa"));
+    }
+
+    #[test]
+    fn position_captures() {
+        let mut m = LuaPattern::new("()ll()");
+        assert!(m.matches("hello"));
+        assert_eq!(m.capture(1), 2..2);
+        assert_eq!(m.capture(2), 4..4);
+    }
+
+    #[test]
+    fn lua54_sets_and_escapes() {
+        let mut m = LuaPattern::new("[]]+");
+        assert_eq!(m.match_maybe("a]]b"), Some("]]"));
+        let mut m = LuaPattern::new("[^]]+");
+        assert_eq!(m.match_maybe("]ab]"), Some("ab"));
+        let mut m = LuaPattern::new("%[(%a+)%]");
+        assert_eq!(m.match_maybe("x [tag] y"), Some("tag"));
+        let mut m = LuaPattern::new("(%d+)%%");
+        assert_eq!(m.match_maybe("it is 100% true"), Some("100"));
+        let mut m = LuaPattern::new("%b)(");
+        assert_eq!(m.match_maybe("a)b(c"), Some(")b("));
+        let mut m = LuaPattern::new("%s");
+        assert!(m.matches("\x0B"));
+        let mut m = LuaPattern::new("");
+        assert!(m.matches("abc"));
+        assert_eq!(m.range(), 0..0);
+    }
+
+    #[test]
+    fn recursion_depth() {
+        // tail calls no longer use up the matcher's depth budget
+        let mut m = LuaPattern::new("a.b");
+        let text = format!("{}ayb", "ax".repeat(1000));
+        assert!(m.matches(&text));
+        let patt = "%a".repeat(1000);
+        let mut m = LuaPattern::new(&patt);
+        assert!(m.matches(&"x".repeat(1000)));
+
+        let patt = format!("{}a{}", "(".repeat(250), ")".repeat(250));
+        let mut m = LuaPattern::new_with_max_captures(&patt, 250).unwrap();
+        assert!(m.try_matches("a").unwrap_err().is_too_complex());
+        assert!(! m.matches("a"));
+        let patt = "a?".repeat(300);
+        let mut m = LuaPattern::new(&patt);
+        assert!(m.try_matches(&"a".repeat(300)).unwrap_err().is_too_complex());
+    }
+
+    #[test]
+    fn iterating_captures() {
+        let mut m = LuaPattern::from_bytes(b"(%x%x)(%x%x)");
+        assert!(m.matches_bytes(b"--CAFE--"));
+        let cc = m.match_captures_bytes(b"--CAFE--");
+        assert_eq!(cc.iter().len(), 3);
+        assert_eq!(cc.iter().collect::<Vec<_>>(), &[&b"CAFE"[..], b"CA", b"FE"]);
+        let mut caps = Vec::new();
+        for c in &cc {
+            caps.push(c);
+        }
+        assert_eq!(caps, cc.into_iter().collect::<Vec<_>>());
+        let mut m = LuaPattern::new("(%a+) (%a+)");
+        assert!(m.matches("hello dolly"));
+        let cc = m.match_captures("hello dolly");
+        assert_eq!(cc.into_iter().rev().collect::<Vec<_>>(), &["dolly","hello","hello dolly"]);
+    }
+
+    #[test]
+    fn gmatch_bytes_captures() {
+        // type-length-value records
+        let bytes = b"\x01\x03abc\x02\x01z\x01\x02hi";
+        let mut m = LuaPattern::from_bytes(b"([\x01\x02])([\x01-\x03])");
+        let mut records = Vec::new();
+        let mut iter = m.gmatch_bytes_captures(bytes);
+        while let Some(cc) = iter.next() {
+            assert_eq!(cc.num_matches(), 3);
+            records.push((cc.get(1)[0], cc.get(2)[0], iter.range()));
+        }
+        assert_eq!(records, &[(1,3,0..2), (2,1,5..7), (1,2,8..10)]);
+    }
+
+    #[test]
+    fn c_strings() {
+        use std::ffi::{CStr, CString};
+        let patt = CString::new("(%a+)=(%d+)").unwrap();
+        let mut m = LuaPattern::from_cstr(&patt);
+        let text = CStr::from_bytes_with_nul(b"x=10\0").unwrap();
+        assert!(m.matches_cstr(text));
+        assert_eq!(m.capture(2), 2..4);
+        assert!(LuaPattern::from_cstr_try(&CString::new("(%a").unwrap()).is_err());
+    }
+
+    #[test]
+    fn embedded_nuls() {
+        // no need for the deprecated %z; nul is an ordinary byte
+        let data = b"key\0value\0\0last\0";
+        let mut m = LuaPattern::from_bytes(b"[^\0]+");
+        let fields: Vec<_> = m.gmatch_bytes(data).collect();
+        assert_eq!(fields, &[&b"key"[..], b"value", b"last"]);
+        let mut m = LuaPattern::from_bytes(b"\0([^\0]+)\0");
+        assert!(m.matches_bytes(b"xx\0zz\0"));
+        assert_eq!(m.capture(1), 3..5);
+        // but it still works, and other escaped letters are themselves
+        let mut m = LuaPattern::new("%z(%Z+)%z%Q");
+        assert!(m.matches_bytes(b"a\0bc\0Q"));
+        assert_eq!(m.capture(1), 2..4);
+        let mut m = LuaPattern::from_bytes(b"%c+");
+        assert!(m.matches_bytes(b"ab\0\x01cd"));
+        assert_eq!(m.range(), 2..4);
+        // balanced and frontier items with nul
+        let mut m = LuaPattern::from_bytes(b"%b\0\x01");
+        assert!(m.matches_bytes(b"a\0b\0c\x01d\x01e"));
+        assert_eq!(m.range(), 1..8);
+        let mut m = LuaPattern::from_bytes(b"%f[\0]%a+");
+        assert!(! m.matches_bytes(b"abc"));
+        let mut m = LuaPattern::from_bytes(b"%a+%f[\0]");
+        assert!(m.matches_bytes(b"abc\0def"));
+        assert_eq!(m.range(), 0..3);
+        // and through to substitution
+        let mut m = LuaPattern::from_bytes(b"\0+");
+        assert_eq!(m.gsub_bytes(b"a\0\0b\0c", b"\0|"), b"a\0|b\0|c");
+    }
+
+    #[test]
+    fn os_strings_and_paths() {
+        use std::ffi::OsStr;
+        use std::path::Path;
+        let mut m = LuaPattern::new("^(%a+)%.txt$");
+        assert!(m.matches_os(OsStr::new("notes.txt")));
+        assert!(! m.matches_os(OsStr::new("notes.txt.bak")));
+        assert!(m.matches_file_name(Path::new("docs/notes.txt")));
+        assert!(! m.matches_path(Path::new("docs/notes.txt")));
+        assert!(! m.matches_file_name(Path::new("..")));
+        // not valid Unicode, but no lossy conversion is needed
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let mut m = LuaPattern::from_bytes(b"^\xFF+%.log$");
+            assert!(m.matches_file_name(Path::new(OsStr::from_bytes(b"/var/\xFF\xFF.log"))));
+            assert_eq!(m.range(), 0..6);
+        }
     }
 
     #[test]
@@ -784,14 +4033,191 @@ mod tests {
         ("bonzo (dog (cat)","unfinished capture"),
         ("frodo %f[%A","malformed pattern (missing ']')"),
         ("frodo (1) (2(3)%2)%1","invalid capture index %2"),
+        ("%0","invalid capture index %0"),
+        ("x)","no open capture"),
+        ("%b(","malformed pattern (missing arguments to '%b')"),
+        ("%fx","missing '[' after '%f' in pattern"),
+        ("[]","malformed pattern (missing ']')"),
+        ("[^]","malformed pattern (missing ']')"),
+        ("[a%","malformed pattern (missing ']')"),
         ];
         for p in bad.iter() {
             let res = LuaPattern::new_try(p.0);
             if let Err(e) = res {
-                assert_eq!(e, PatternError(p.1.into()));
+                assert_eq!(e.message(), p.1);
             } else {
                 panic!("false positive");
             }
         }
     }
+
+    #[test]
+    fn quantifiable_items() {
+        let single = ["a", "^a", "%d", "%%", ".", "[%]a]", "[]x]", "x*a", "*", "%f[%w]-", "a$"];
+        for p in single.iter() {
+            assert!(ends_with_single(p.as_bytes()), "{}", p);
+        }
+        let not_single = ["", "^", "(a)", "()", "a*", "%b()", "%f[%w]", "(a)%1", "a+"];
+        for p in not_single.iter() {
+            assert!(! ends_with_single(p.as_bytes()), "{}", p);
+        }
+    }
+
+    #[test]
+    fn builder_sets() {
+        let patt = LuaPatternBuilder::new().set("]^-%").at_least_one().build();
+        let mut m = LuaPattern::from_bytes(&patt);
+        assert_eq!(m.match_maybe("a]^-%b"), Some("]^-%"));
+
+        let patt = LuaPatternBuilder::new().range(b'[', b']').at_least_one().build();
+        let mut m = LuaPattern::from_bytes(&patt);
+        assert_eq!(m.match_maybe("a[\\]b"), Some("[\\]"));
+
+        let err = LuaPatternBuilder::new().range(b'z', b'a').try_build().unwrap_err();
+        assert_eq!(err.message(), "empty range 'z'-'a'");
+        assert_eq!(err.kind(), PatternErrorKind::EmptySet);
+        let err = LuaPatternBuilder::new().negated_set("").try_build().unwrap_err();
+        assert_eq!(err.message(), "empty set");
+        assert_eq!(err.kind(), PatternErrorKind::EmptySet);
+    }
+
+    #[test]
+    fn verbose_patterns() {
+        let cases = [
+            ("a b\tc\n d", "abcd"),
+            ("a% b %#c # comment\nd", "a% b%#cd"),
+            ("%b## x", "%b##x"),
+            ("%f[ #] x", "%f[ #]x"),
+            ("[] #] x", "[] #]x"),
+            ("x %", "x%"),
+        ];
+        for &(verbose, plain) in cases.iter() {
+            assert_eq!(strip_verbose(verbose.as_bytes()), plain.as_bytes(), "{}", verbose);
+        }
+    }
+
+    #[test]
+    fn static_patterns() {
+        static BAD: StaticPattern = StaticPattern::new("(%d+");
+        static GOOD: StaticPattern = StaticPattern::new("%d+");
+        for _ in 0..2 {
+            assert_eq!(BAD.try_pattern().err(), Some(PatternError::new(UnfinishedCapture, "unfinished capture").at(0)));
+        }
+        let handles: Vec<_> = (0..4).map(|i| std::thread::spawn(move || {
+            let text = format!("n={}", i);
+            GOOD.pattern().match_maybe(&text).map(|s| s.to_string())
+        })).collect();
+        for (i, h) in handles.into_iter().enumerate() {
+            assert_eq!(h.join().unwrap(), Some(i.to_string()));
+        }
+    }
+
+    #[test]
+    fn patterns_as_keys() {
+        use std::collections::{HashMap, HashSet};
+        let mut counts = HashMap::new();
+        for p in ["%d+", "%a+", "%d+"].iter() {
+            *counts.entry(LuaPattern::new(p)).or_insert(0) += 1;
+        }
+        assert_eq!(counts[&LuaPattern::new("%d+")], 2);
+
+        let owned: HashSet<LuaPatternOwned> = ["%d+", "%d+"].iter().map(|p| p.parse().unwrap()).collect();
+        assert_eq!(owned.len(), 1);
+        assert_eq!(format!("{:?}", owned), "{LuaPatternOwned(\"%d+\")}");
+    }
+
+    #[test]
+    fn fallible_iteration() {
+        let mut m = LuaPattern::new("%d+");
+        let mut iter = m.gmatch_bytes(b"1 22");
+        assert_eq!(iter.try_next(), Ok(Some(&b"1"[..])));
+        assert_eq!(iter.try_next(), Ok(Some(&b"22"[..])));
+        assert_eq!(iter.try_next(), Ok(None));
+
+        let mut m = LuaPattern::new("(.-)%s*$");
+        m.set_step_limit(Some(1000));
+        let text = format!("x{}x", " ".repeat(1000));
+        let mut iter = m.gmatch_bytes(text.as_bytes());
+        assert!(iter.try_next().unwrap_err().is_step_limit());
+        let mut iter = m.gmatch(&text);
+        assert!(iter.try_next().unwrap_err().is_step_limit());
+    }
+
+    // patterns which pass `new_try` can be matched against anything
+    // without panicking; run under miri, this also checks for UB
+    #[test]
+    fn arbitrary_patterns() {
+        let mut seed: u32 = 12345;
+        let mut random = move |n: usize| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as usize % n
+        };
+        let patt_bytes = b"%()[]^$*+-?.abf1b";
+        let text_bytes = b"ab()[] %\0\xFF";
+        let rounds = if cfg!(miri) {300} else {20000};
+        for _ in 0..rounds {
+            let patt: Vec<u8> = (0..random(9)).map(|_| patt_bytes[random(patt_bytes.len())]).collect();
+            let text: Vec<u8> = (0..random(12)).map(|_| text_bytes[random(text_bytes.len())]).collect();
+            let mut m = match LuaPattern::from_bytes_try(&patt) {
+                Ok(m) => m,
+                Err(_) => continue
+            };
+            m.set_step_limit(Some(10000));
+            let _ = m.try_matches_bytes(&text);
+            m.matches_bytes(&text);
+            let _ = m.gmatch_bytes(&text).count();
+            let _ = m.gsub_bytes(&text, b"<%0>");
+            for cc in m.gmatch_bytes_captures(&text) {
+                for i in 0..cc.num_matches() {
+                    let _ = cc.get(i);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn resumable_matches() {
+        // where a match could start once more text is added
+        let cases: &[(&str, &[u8], usize)] = &[
+            ("<(%a+)>", b"xx<ab", 2), ("<(%a+)>", b"xxxx", 4), ("[<>]x", b"ab", 2),
+            ("abc", b"xxab", 2), ("abc%d", b"xxxab", 3), ("%f[%a]%a+;", b"x ab", 2),
+            ("(a)%1", b"ba", 1), ("%b()", b"x(a", 1), ("a$", b"ab", 2),
+        ];
+        for &(patt, text, open) in cases {
+            let mut m = LuaPattern::new(patt);
+            assert_eq!(m.matches_bytes_resumable(text, 0), (false, open), "{}", patt);
+        }
+
+        // no match of the whole text starts before the position found for a
+        // prefix of it, so searching from there finds the same match
+        let mut seed: u32 = 54321;
+        let mut random = move |n: usize| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as usize % n
+        };
+        let items: &[&str] = &["a", "b", ".", "[ab]", "%s", "%f[a]", "%f[%s]", "%b()", "(", ")", "()",
+            "(a)", "%1", "*", "+", "-", "?", "$"];
+        let text_bytes = b"aab() ";
+        let rounds = if cfg!(miri) {300} else {20000};
+        for _ in 0..rounds {
+            let patt: Vec<u8> = (0..random(7)).flat_map(|_| items[random(items.len())].bytes()).collect();
+            let text: Vec<u8> = (0..random(12)).map(|_| text_bytes[random(text_bytes.len())]).collect();
+            let mut m = match LuaPattern::from_bytes_try(&patt) {
+                Ok(m) => m,
+                Err(_) => continue
+            };
+            m.set_step_limit(Some(10000));
+            let whole = match m.try_matches_bytes(&text) {
+                Ok(true) => Some(m.range()),
+                Ok(false) => None,
+                Err(_) => continue
+            };
+            let prefix = &text[..random(text.len() + 1)];
+            let (_, open) = m.matches_bytes_resumable(prefix, 0);
+            assert!(open <= prefix.len());
+            let resumed = if m.matches_bytes_at(&text, open, false) {Some(m.range())} else {None};
+            assert_eq!(resumed, whole, "{:?} on {:?} from {}",
+                String::from_utf8_lossy(&patt), String::from_utf8_lossy(&text), open);
+        }
+    }
 }
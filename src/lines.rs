@@ -0,0 +1,204 @@
+//! Line and column positions for matches, for reporting diagnostics.
+//!
+//! Matches are byte ranges in the whole text. `LineIndex` finds the start of
+//! each line once, so that turning many matches into line and column
+//! positions does not mean scanning the text from the start each time.
+//!
+//! ```
+//! use lua_patterns::LuaPattern;
+//! use lua_patterns::lines::LineIndex;
+//!
+//! let source = "let x = 1;\nlet y = x +;\n";
+//! let index = LineIndex::new(source);
+//! let m = LuaPattern::new("%+;");
+//! let span = index.span(m.find(source).unwrap().range());
+//! assert_eq!((span.line, span.column), (2, 11));
+//! assert_eq!(index.line(span.line), Some("let y = x +;"));
+//! ```
+//!
+//! Lua patterns only anchor at the start and end of the whole text, so
+//! `LuaPattern::match_lines` runs a pattern on each line in turn, where
+//! '^' and '$' match at the start and end of the line.
+
+use std::ops::Range;
+use found::{Captures, CaptureMatches};
+use LuaPattern;
+
+/// Where a match is, as a line and column as well as a byte range
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Span {
+    /// Line of the start of the match, starting at one
+    pub line: usize,
+    /// Column of the start of the match in chars, starting at one
+    pub column: usize,
+    /// The byte range of the match in the whole text
+    pub byte_range: Range<usize>,
+}
+
+/// The starts of the lines of a text, built once and used for every match
+#[derive(Clone,Debug)]
+pub struct LineIndex<'t> {
+    text: &'t str,
+    starts: Vec<usize>,
+}
+
+impl <'t> LineIndex<'t> {
+    /// Index the lines of `text`, which end with "\n" (or "\r\n")
+    pub fn new(text: &'t str) -> LineIndex<'t> {
+        let mut starts = vec![0];
+        starts.extend(text.bytes().enumerate()
+            .filter(|&(_, b)| b == b'\n')
+            .map(|(i, _)| i + 1));
+        LineIndex{text, starts}
+    }
+
+    /// Number of lines; text after the last "\n" counts as a line,
+    /// even if it is empty.
+    pub fn num_lines(&self) -> usize {
+        self.starts.len()
+    }
+
+    /// The line and column of a byte offset, both starting at one.
+    ///
+    /// The column counts chars, so an offset inside a char is counted as
+    /// being at the next char.
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.text.len());
+        let line = self.starts.partition_point(|&start| start <= offset);
+        let start = self.starts[line - 1];
+        let column = self.text.as_bytes()[start..offset].iter()
+            .filter(|&&b| (b & 0xC0) != 0x80)
+            .count();
+        (line, column + 1)
+    }
+
+    /// The span of a byte range, such as the range of a match
+    pub fn span(&self, byte_range: Range<usize>) -> Span {
+        let (line, column) = self.line_col(byte_range.start);
+        Span{line, column, byte_range}
+    }
+
+    /// Byte range of a line, without its line ending
+    pub fn line_range(&self, line: usize) -> Option<Range<usize>> {
+        let start = *self.starts.get(line.checked_sub(1)?)?;
+        let mut end = self.starts.get(line).map(|&next| next - 1).unwrap_or(self.text.len());
+        if end > start && self.text.as_bytes()[end - 1] == b'\r' {
+            end -= 1;
+        }
+        Some(start..end)
+    }
+
+    /// The text of a line, without its line ending
+    pub fn line(&self, line: usize) -> Option<&'t str> {
+        self.line_range(line).map(|r| &self.text[r])
+    }
+}
+
+// The byte ranges of the lines of a text, without line endings.
+// As with `str::lines`, there is no empty line after a final "\n"
+pub(crate) struct LineRanges<'t> {
+    text: &'t str,
+    // start of the next line, if there is one
+    next_line: Option<usize>,
+}
+
+impl <'t> LineRanges<'t> {
+    pub(crate) fn new(text: &'t str) -> LineRanges<'t> {
+        let next_line = if text.is_empty() {None} else {Some(0)};
+        LineRanges{text, next_line}
+    }
+}
+
+impl <'t> Iterator for LineRanges<'t> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Range<usize>> {
+        let start = self.next_line?;
+        let rest = &self.text[start..];
+        let (mut end, next) = match rest.find('\n') {
+            Some(i) => (start + i, Some(start + i + 1).filter(|&n| n < self.text.len())),
+            None => (self.text.len(), None)
+        };
+        if end > start && self.text.as_bytes()[end - 1] == b'\r' {
+            end -= 1;
+        }
+        self.next_line = next;
+        Some(start..end)
+    }
+}
+
+/// Iterator over the captures of matches in each line, from `match_lines`
+// lifetimes as for CaptureMatches
+pub struct MatchLines<'a,'c,'t> where 'a: 'c {
+    m: &'c LuaPattern<'a>,
+    text: &'t str,
+    lines: LineRanges<'t>,
+    // matches in the current line, and where it starts
+    current: Option<(usize, CaptureMatches<'a,'c,'t>)>,
+}
+
+impl <'a,'c,'t> MatchLines<'a,'c,'t> {
+    pub(crate) fn new(m: &'c LuaPattern<'a>, text: &'t str) -> MatchLines<'a,'c,'t> {
+        MatchLines{m, text, lines: LineRanges::new(text), current: None}
+    }
+}
+
+impl <'a,'c,'t> Iterator for MatchLines<'a,'c,'t> {
+    type Item = Captures<'t>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((start, ref mut matches)) = self.current {
+                if let Some(cc) = matches.next() {
+                    return Some(cc.shifted(self.text, start));
+                }
+            }
+            let line = self.lines.next()?;
+            self.current = Some((line.start, self.m.captures_iter(&self.text[line])));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use LuaPattern;
+
+    #[test]
+    fn positions() {
+        let text = "één\r\ntwo\n\nfour";
+        let index = LineIndex::new(text);
+        assert_eq!(index.num_lines(), 4);
+        assert_eq!(index.line_col(0), (1, 1));
+        assert_eq!(index.line_col(4), (1, 3));
+        assert_eq!(index.line_col(7), (2, 1));
+        assert_eq!(index.line_col(11), (3, 1));
+        assert_eq!(index.line_col(text.len()), (4, 5));
+        assert_eq!(index.line(1), Some("één"));
+        assert_eq!(index.line(3), Some(""));
+        assert_eq!(index.line(4), Some("four"));
+        assert_eq!(index.line(0), None);
+        assert_eq!(index.line(5), None);
+        let m = LuaPattern::new("%a+");
+        let spans: Vec<_> = m.captures_iter(text)
+            .map(|cc| index.span(cc.get_match().range()))
+            .map(|span| (span.line, span.column))
+            .collect();
+        assert_eq!(spans, &[(1, 3), (2, 1), (4, 1)]);
+    }
+
+    #[test]
+    fn per_line() {
+        let text = "key = 1\r\n# key = 2\nkey=3\n";
+        let m = LuaPattern::new("^(%a+)%s*=%s*(%d+)$");
+        let found: Vec<_> = m.match_lines(text)
+            .map(|cc| (cc.get(2).unwrap().range(), cc[2].to_string()))
+            .collect();
+        assert_eq!(found, &[(6..7, "1".to_string()), (23..24, "3".to_string())]);
+        let m = LuaPattern::new("^$");
+        assert_eq!(m.match_lines("a\n\nb\n").count(), 1);
+        assert_eq!(m.match_lines("").count(), 0);
+        let m = LuaPattern::new("%d");
+        assert_eq!(m.match_lines("1 2\n3").map(|cc| cc.get_match().start()).collect::<Vec<_>>(), &[0, 2, 4]);
+    }
+}
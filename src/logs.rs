@@ -0,0 +1,251 @@
+//! Extracting fields from common log formats.
+//!
+//! There are parsers for web server access logs (the Common and Combined
+//! formats used by Apache and Nginx), BSD syslog lines, and logfmt. They
+//! are built from ordinary Lua patterns and return string slices of the line,
+//! apart from a few fields which are converted to numbers. A line which
+//! doesn't fit the format gives `None`.
+//!
+//! ```
+//! use lua_patterns::logs::parse_access_log;
+//!
+//! let line = r#"127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] "GET /index.html HTTP/1.0" 200 2326 "-" "curl/7.68.0""#;
+//! let log = parse_access_log(line).unwrap();
+//! assert_eq!(log.host, "127.0.0.1");
+//! assert_eq!(log.user, Some("frank"));
+//! assert_eq!(log.time, "10/Oct/2000:13:55:36 -0700");
+//! assert_eq!(log.request_parts(), Some(("GET", "/index.html", "HTTP/1.0")));
+//! assert_eq!((log.status, log.bytes), (200, Some(2326)));
+//! assert_eq!((log.referer, log.user_agent), (None, Some("curl/7.68.0")));
+//! ```
+
+use std::borrow::Cow;
+use LuaPattern;
+
+/// The Common Log Format, capturing the host, ident, user, time, request,
+/// status and size
+pub const COMMON: &str = "^(%S+) (%S+) (%S+) %[([^%]]*)%] \"([^\"]*)\" (%d%d%d) (%S+)";
+
+/// The Combined Log Format, which is `COMMON` followed by the referer and
+/// user agent in quotes
+pub const COMBINED: &str = "^(%S+) (%S+) (%S+) %[([^%]]*)%] \"([^\"]*)\" (%d%d%d) (%S+) \"([^\"]*)\" \"([^\"]*)\"";
+
+/// A line from a web server access log, found by `parse_access_log`.
+///
+/// Fields which are "-" in the log are `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessLog<'t> {
+    /// the client address
+    pub host: &'t str,
+    pub ident: Option<&'t str>,
+    pub user: Option<&'t str>,
+    /// the time, like "10/Oct/2000:13:55:36 -0700"
+    pub time: &'t str,
+    /// the request line, like "GET / HTTP/1.1"
+    pub request: &'t str,
+    pub status: u16,
+    /// the size of the response body
+    pub bytes: Option<u64>,
+    /// the referer, which is always `None` in the Common format
+    pub referer: Option<&'t str>,
+    /// the user agent, which is always `None` in the Common format
+    pub user_agent: Option<&'t str>,
+}
+
+impl <'t> AccessLog<'t> {
+    /// The method, path and protocol of the request, if it has these three parts
+    pub fn request_parts(&self) -> Option<(&'t str, &'t str, &'t str)> {
+        LuaPattern::new("^(%S+) (%S+) (%S+)$").match_maybe_3(self.request)
+    }
+}
+
+// None for the "-" which logs use for a missing field
+fn field(s: &str) -> Option<&str> {
+    if s == "-" {None} else {Some(s)}
+}
+
+/// Parse a line in the Combined or Common Log Format.
+///
+/// Anything after the Combined fields is ignored, so custom formats which
+/// add fields at the end are fine.
+pub fn parse_access_log(line: &str) -> Option<AccessLog<'_>> {
+    let mut m = LuaPattern::new(COMBINED);
+    let cc = if m.matches(line) {
+        m.captures(line)
+    } else {
+        LuaPattern::new(COMMON).captures(line)
+    };
+    if cc.is_empty() {
+        return None;
+    }
+    let bytes = match field(cc[7]) {
+        Some(n) => Some(n.parse().ok()?),
+        None => None
+    };
+    Some(AccessLog {
+        host: cc[1],
+        ident: field(cc[2]),
+        user: field(cc[3]),
+        time: cc[4],
+        request: cc[5],
+        status: cc[6].parse().ok()?,
+        bytes,
+        referer: cc.get(8).and_then(|s| field(s)),
+        user_agent: cc.get(9).and_then(|s| field(s)),
+    })
+}
+
+/// A BSD syslog line (RFC 3164), found by `parse_syslog`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Syslog<'t> {
+    /// the priority in angle brackets, which is missing from log files
+    pub priority: Option<u8>,
+    /// the time, like "Oct 11 22:14:15"
+    pub timestamp: &'t str,
+    pub host: &'t str,
+    /// the program name
+    pub tag: &'t str,
+    /// the process id in square brackets after the tag
+    pub pid: Option<u32>,
+    pub message: &'t str,
+}
+
+impl <'t> Syslog<'t> {
+    /// The facility, which is the priority divided by 8
+    pub fn facility(&self) -> Option<u8> {
+        self.priority.map(|p| p / 8)
+    }
+
+    /// The severity, from 0 (emergency) to 7 (debug)
+    pub fn severity(&self) -> Option<u8> {
+        self.priority.map(|p| p % 8)
+    }
+}
+
+/// Parse a syslog line like "<34>Oct 11 22:14:15 mymachine su[230]: 'su root' failed".
+///
+/// The priority and pid are optional, but there must be a tag followed by ':'.
+///
+/// ```
+/// let log = lua_patterns::logs::parse_syslog("Oct  1 09:05:02 web1 sshd[4242]: Accepted publickey").unwrap();
+/// assert_eq!((log.timestamp, log.host, log.tag), ("Oct  1 09:05:02", "web1", "sshd"));
+/// assert_eq!((log.pid, log.message), (Some(4242), "Accepted publickey"));
+/// ```
+pub fn parse_syslog(line: &str) -> Option<Syslog<'_>> {
+    let (priority, rest) = match LuaPattern::new("^<(%d+)>(.*)$").match_maybe_2(line) {
+        Some((p, rest)) => (Some(p.parse().ok()?), rest),
+        None => (None, line)
+    };
+    let (timestamp, host, tag, rest) = LuaPattern::new("^(%a%a%a [ %d]%d %d%d:%d%d:%d%d) (%S+) ([^%s:%[]+)(.*)$")
+        .match_tuple::<(&str,&str,&str,&str)>(rest)?;
+    let (pid, message) = match LuaPattern::new("^%[(%d+)%]:%s?(.*)$").match_maybe_2(rest) {
+        Some((pid, message)) => (Some(pid.parse().ok()?), message),
+        None => (None, LuaPattern::new("^:%s?(.*)$").match_maybe(rest)?)
+    };
+    Some(Syslog{priority, timestamp, host, tag, pid, message})
+}
+
+/// Parse a logfmt line like `level=info msg="user logged in" id=42`.
+///
+/// A key without '=' has an empty value. Quoted values may contain escaped
+/// quotes and backslashes, which are unescaped, as are `\n` and `\t`.
+/// An unterminated quoted value runs to the end of the line.
+///
+/// ```
+/// let pairs = lua_patterns::logs::logfmt(r#"level=warn msg="disk \"/\" full" retry"#);
+/// assert_eq!(pairs, &[("level","warn".into()), ("msg",r#"disk "/" full"#.into()), ("retry","".into())]);
+/// ```
+pub fn logfmt(line: &str) -> Vec<(&str, Cow<'_, str>)> {
+    let bytes = line.as_bytes();
+    let mut key = LuaPattern::new("%s*([^%s=\"]+)(=?)");
+    let mut bare = LuaPattern::new("[^%s]*");
+    let mut quote_or_escape = LuaPattern::new("[\"\\]");
+    let mut res = Vec::new();
+    let mut pos = 0;
+    while key.matches_bytes_at(bytes, pos, true) {
+        let name = &line[key.capture(1)];
+        pos = key.range().end;
+        if key.capture(2).is_empty() {
+            res.push((name, Cow::Borrowed("")));
+            continue;
+        }
+        if bytes.get(pos) != Some(&b'"') {
+            bare.matches_bytes_at(bytes, pos, true);
+            res.push((name, Cow::Borrowed(&line[bare.range()])));
+            pos = bare.range().end;
+            continue;
+        }
+        // skip escaped characters until the closing quote
+        let start = pos + 1;
+        let mut end = line.len();
+        pos = start;
+        while quote_or_escape.matches_bytes_at(bytes, pos, false) {
+            let at = quote_or_escape.range().start;
+            if bytes[at] == b'"' {
+                end = at;
+                break;
+            }
+            pos = (at + 2).min(line.len());
+        }
+        res.push((name, unescape(&line[start..end])));
+        pos = (end + 1).min(line.len());
+    }
+    res
+}
+
+fn unescape(s: &str) -> Cow<'_, str> {
+    if ! s.contains('\\') {
+        return Cow::Borrowed(s);
+    }
+    Cow::Owned(LuaPattern::new("\\(.)").gsub_with(s, |cc| match cc.get(1) {
+        "n" => "\n".to_string(),
+        "t" => "\t".to_string(),
+        c => c.to_string()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn access_logs() {
+        let line = r#"::1 - - [01/Jan/2024:00:00:01 +0000] "POST /api?x=1 HTTP/2.0" 404 - "https://example.com/" "Mozilla/5.0 (X11)" 0.002"#;
+        let log = parse_access_log(line).unwrap();
+        assert_eq!(log, AccessLog {
+            host: "::1", ident: None, user: None, time: "01/Jan/2024:00:00:01 +0000",
+            request: "POST /api?x=1 HTTP/2.0", status: 404, bytes: None,
+            referer: Some("https://example.com/"), user_agent: Some("Mozilla/5.0 (X11)"),
+        });
+        let log = parse_access_log(r#"10.0.0.1 - bob [x] "-" 400 0"#).unwrap();
+        assert_eq!((log.user, log.request, log.bytes, log.user_agent), (Some("bob"), "-", Some(0), None));
+        assert_eq!(log.request_parts(), None);
+        assert_eq!(parse_access_log("10.0.0.1 - - [x] \"GET /\" 200 lots"), None);
+        assert_eq!(parse_access_log("not a log line"), None);
+    }
+
+    #[test]
+    fn syslog() {
+        let log = parse_syslog("<34>Oct 11 22:14:15 mymachine su: 'su root' failed for lonvick").unwrap();
+        assert_eq!(log, Syslog {
+            priority: Some(34), timestamp: "Oct 11 22:14:15", host: "mymachine", tag: "su",
+            pid: None, message: "'su root' failed for lonvick",
+        });
+        assert_eq!((log.facility(), log.severity()), (Some(4), Some(2)));
+        let log = parse_syslog("Jan  2 03:04:05 host cron[7]:").unwrap();
+        assert_eq!((log.pid, log.message, log.facility()), (Some(7), "", None));
+        assert_eq!(parse_syslog("Jan  2 03:04:05 host no colon"), None);
+        assert_eq!(parse_syslog("<999>Jan  2 03:04:05 host cron: x"), None);
+    }
+
+    #[test]
+    fn logfmt_pairs() {
+        let pairs = logfmt(r#"  a=1 b="" c="x\\y\tz" d= e="unterminated \"q"#);
+        assert_eq!(pairs, &[
+            ("a","1".into()), ("b","".into()), ("c","x\\y\tz".into()),
+            ("d","".into()), ("e","unterminated \"q".into()),
+        ]);
+        assert!(logfmt("   ").is_empty());
+        assert_eq!(logfmt(r#"msg="ends in \"#), &[("msg","ends in \\".into())]);
+    }
+}
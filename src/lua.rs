@@ -0,0 +1,125 @@
+//! Working with [mlua](https://docs.rs/mlua) values, for programs embedding
+//! Lua which want to do their matching on the Rust side.
+//!
+//! Lua strings are arbitrary bytes, so patterns made from them and the
+//! results of matching them keep any bytes which are not UTF-8.
+//!
+//! ```
+//! extern crate mlua;
+//! extern crate lua_patterns;
+//! use std::convert::TryFrom;
+//! use lua_patterns::LuaPattern;
+//!
+//! # fn run() -> mlua::Result<()> {
+//! let lua = mlua::Lua::new();
+//! let patt = lua.create_string("(%a+)=()")?;
+//! let mut m = LuaPattern::try_from(&patt)?;
+//! let text = lua.create_string("set key=")?;
+//! // the same results as string.match
+//! let (key, pos): (String, i64) = lua.unpack_multi(m.lua_match(&lua, &text)?)?;
+//! assert_eq!(key, "key");
+//! assert_eq!(pos, 9);
+//! # Ok(())
+//! # }
+//! # run().unwrap();
+//! ```
+
+use std::convert::TryFrom;
+use mlua::{self, Lua, MultiValue, Value};
+use errors::*;
+use parse::{self, Item, ItemKind};
+use LuaPattern;
+
+impl <'a, 'lua> TryFrom<&'a mlua::String<'lua>> for LuaPattern<'a> {
+    type Error = PatternError;
+
+    fn try_from(patt: &'a mlua::String<'lua>) -> Result<LuaPattern<'a>,PatternError> {
+        LuaPattern::from_bytes_try(patt.as_bytes())
+    }
+}
+
+impl From<PatternError> for mlua::Error {
+    fn from(e: PatternError) -> mlua::Error {
+        mlua::Error::external(e)
+    }
+}
+
+pub(crate) fn position_captures(items: &[Item], res: &mut Vec<usize>) {
+    for item in items {
+        match item.kind {
+            ItemKind::Position(index) => res.push(index),
+            ItemKind::Capture(ref cap) => position_captures(&cap.items, res),
+            _ => {}
+        }
+    }
+}
+
+impl <'a> LuaPattern<'a> {
+    /// Match a Lua string, returning the same values as `string.match`.
+    ///
+    /// That is, `nil` if there is no match, otherwise the captures (or the
+    /// whole match if there are none). Position captures are integers
+    /// counting from one, as in Lua.
+    pub fn lua_match<'lua>(&mut self, lua: &'lua Lua, text: &mlua::String<'lua>) -> mlua::Result<MultiValue<'lua>> {
+        let bytes = text.as_bytes();
+        if ! self.try_matches_bytes(bytes)? {
+            return Ok(MultiValue::from_vec(vec![Value::Nil]));
+        }
+        let mut positions = Vec::new();
+        position_captures(&parse::parse(self.patt)?.items, &mut positions);
+        let mut values = Vec::new();
+        let first = if self.n_match > 1 {1} else {0};
+        for i in first..self.n_match {
+            let r = self.capture(i);
+            values.push(if positions.contains(&i) {
+                Value::Integer(r.start as i64 + 1)
+            } else {
+                Value::String(lua.create_string(&bytes[r])?)
+            });
+        }
+        Ok(MultiValue::from_vec(values))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // compare with what Lua's own string.match returns
+    fn check(patt: &[u8], text: &[u8]) {
+        let lua = Lua::new();
+        let patt = lua.create_string(patt).unwrap();
+        let text = lua.create_string(text).unwrap();
+        let string_match: mlua::Function = lua.load("string.match").eval().unwrap();
+        let expected: MultiValue = string_match.call((text.clone(), patt.clone())).unwrap();
+        let mut m = LuaPattern::try_from(&patt).unwrap();
+        let got = m.lua_match(&lua, &text).unwrap();
+        let show = |values: MultiValue| -> Vec<String> {
+            values.into_iter().map(|v| format!("{:?}", v)).collect()
+        };
+        assert_eq!(show(got), show(expected));
+    }
+
+    #[test]
+    fn same_as_string_match() {
+        check(b"(%a+)=(%d+)", b"x a=1 b=2");
+        check(b"%d+", b"abc 123");
+        check(b"%d+", b"abc");
+        check(b"()(%a+)()", b"  word  ");
+        check(b"\xFE+(.)", b"\x00\xFE\xFE\xFF");
+        check(b"", b"abc");
+        check(b"%Z+(%z)", b"zZZz\0");
+        check(b"%Q+", b"qQQ");
+        check(b"[^\0]+", b"\0\0ab\0");
+    }
+
+    #[test]
+    fn errors() {
+        let lua = Lua::new();
+        let patt = lua.create_string("(%a").unwrap();
+        let err = LuaPattern::try_from(&patt).err().unwrap();
+        assert_eq!(err.message(), "unfinished capture");
+        let err: mlua::Error = err.into();
+        assert!(err.to_string().contains("unfinished capture"));
+    }
+}
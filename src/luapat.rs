@@ -1,7 +1,11 @@
-// translation of Lua 5.2 string pattern code
+// translation of Lua 5.4 string pattern code (lstrlib.c)
 
 use errors::*;
+use errors::PatternErrorKind::*;
+use scan::ByteSet;
 use std::ptr::null;
+use std::slice;
+use std::time::Instant;
 
 pub const LUA_MAXCAPTURES: usize = 32;
 /* maximum recursion depth for 'match' */
@@ -9,20 +13,27 @@ const MAXCCALLS: usize = 200;
 
 const L_ESC: u8 = b'%';
 
+// Like C, the matcher forms pointers past the ends of the subject and
+// pattern (and compares them), so the arithmetic must not assume it stays
+// in bounds; only `at` reads through a pointer.
 fn add(p: CPtr, count: usize) -> CPtr {
-    unsafe {p.offset(count as isize)}
+    p.wrapping_add(count)
 }
 
 fn sub(p: CPtr, count: usize) -> CPtr {
-    unsafe {p.offset(-(count as isize))}
+    p.wrapping_sub(count)
 }
 
 fn next(p: CPtr) -> CPtr {
     add(p, 1)
 }
 
-fn at(p: CPtr) -> u8 {
-   unsafe { *p }
+// The byte at `p`.
+//
+// Safety: `p` must point to a byte of the subject or of the pattern,
+// so not at or past its end.
+unsafe fn at(p: CPtr) -> u8 {
+    *p
 }
 
 fn diff(p1: CPtr, p2: CPtr) -> usize {
@@ -45,19 +56,8 @@ enum CapLen {
 
 impl CapLen {
     fn is_unfinished(&self) -> bool {
-        match *self {
-            CapLen::Unfinished => true,
-            _ => false
-        }
-    }
-
-    fn size(&self) -> Result<usize> {
-        match *self {
-            CapLen::Len(size) => Ok(size),
-            _ => error("capture was unfinished or positional")
-        }
+        matches!(*self, CapLen::Unfinished)
     }
-
 }
 
 type CPtr = *const u8;
@@ -78,80 +78,151 @@ use std::result;
 
 type Result<T> = result::Result<T,PatternError>;
 
-fn error<T>(msg: &str) ->  Result<T> {
-    Err(PatternError(msg.into()))
+fn error<T>(kind: PatternErrorKind, msg: &str) ->  Result<T> {
+    Err(PatternError::new(kind, msg))
+}
+
+/// Limits on the work done by a single match
+#[derive(Copy,Clone,Debug)]
+pub struct Limits {
+    pub max_steps: Option<usize>,
+    pub deadline: Option<Instant>,
+    pub max_captures: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits{max_steps: None, deadline: None, max_captures: LUA_MAXCAPTURES}
+    }
 }
 
+/* how many steps between looking at the clock */
+const DEADLINE_CHECK_STEPS: usize = 1024;
+
+// Every pointer into the subject given to a `MatchState` method is between
+// `src_init` and `src_end`, and every pointer into the pattern is between its
+// start and `p_end`, which is the end of a pattern accepted by `str_check`.
 struct MatchState {
     matchdepth: usize, /* control for recursive depth (to avoid stack overflow) */
+    steps: usize, /* matcher steps left before giving up */
+    clock: usize, /* steps left before checking the deadline */
+    deadline: Option<Instant>,
     src_init: CPtr, /* init of source string */
     src_end: CPtr, /* end ('\0') of source string */
     p_end: CPtr, /* end ('\0') of pattern */
     level: usize, /* total number of captures (finished or unfinished) */
-    capture: [Capture; LUA_MAXCAPTURES],
+    max_captures: usize,
+    capture: Vec<Capture>, /* grows as needed, up to max_captures */
+    hit_end: bool, /* did the match look for more source than there is? */
 }
 
 impl MatchState {
-    fn new(s: CPtr, se: CPtr, pe: CPtr) -> MatchState {
+    fn new(s: CPtr, se: CPtr, pe: CPtr, max_captures: usize) -> MatchState {
         MatchState {
             matchdepth: MAXCCALLS,
+            steps: usize::MAX,
+            clock: DEADLINE_CHECK_STEPS,
+            deadline: None,
             src_init: s,
             src_end: se,
             p_end: pe,
             level: 0,
-            capture: [Capture{init: null(), len: CapLen::Len(0) }; LUA_MAXCAPTURES],
+            max_captures,
+            capture: Vec::new(),
+            hit_end: false,
         }
     }
 
-    fn check_capture(&self, l: usize) -> Result<usize> {
-        let l = l as i8 - b'1' as i8;
-        if l < 0 || l as usize >= self.level || self.capture[l as usize].is_unfinished() {
-            return error(&format!("invalid capture index %{}", l + 1));
+    // open a new capture level
+    fn new_level(&mut self, init: CPtr, len: CapLen) -> Result<()> {
+        let level = self.level;
+        if level >= self.max_captures {
+            return error(TooManyCaptures, "too many captures");
+        }
+        let capture = Capture{init, len};
+        if level == self.capture.len() {
+            self.capture.push(capture);
+        } else {
+            self.capture[level] = capture;
         }
-        Ok(l as usize)
+        self.level = level + 1;
+        Ok(())
+    }
+
+    fn check_capture(&self, l: u8) -> Result<usize> {
+        let l = (l as usize).wrapping_sub(b'1' as usize);
+        if l >= self.level || self.capture[l].is_unfinished() {
+            return error(InvalidCaptureIndex, &format!("invalid capture index %{}", l.wrapping_add(1)));
+        }
+        Ok(l)
     }
 
     fn capture_to_close(&self) -> Result<usize> {
-        let mut level = (self.level - 1) as isize;
-        while level >= 0 {
-            if self.capture[level as usize].is_unfinished() {
-                return Ok(level as usize);
-            }
-            level -= 1;
+        match (0..self.level).rev().find(|&l| self.capture[l].is_unfinished()) {
+            Some(level) => Ok(level),
+            None => error(NoOpenCapture, "invalid pattern capture")
         }
-        error("invalid pattern capture")
     }
 
+    // the pattern byte at `p`, or nul at the end (where C has its terminator)
+    fn pat(&self, p: CPtr) -> u8 {
+        // SAFETY: `p` is in the pattern and before its end
+        if p < self.p_end {unsafe {at(p)}} else {b'\0'}
+    }
+
+    // `p` must be before the end of the pattern
     fn classend (&self, p: CPtr) -> Result<CPtr> {
-        let ch = at(p);
-        let mut next_p = next(p);
-        Ok(match ch {
+        // SAFETY: `p` is before the end of the pattern
+        let ch = unsafe {at(p)};
+        let mut p = next(p);
+        match ch {
             L_ESC => {
-                if next_p == self.p_end {
-                    return error("malformed pattern (ends with '%')");
+                if p == self.p_end {
+                    return error(EndsWithEscape, "malformed pattern (ends with '%')");
                 }
-                next(next_p)
+                Ok(next(p))
             },
             b'[' => {
-                if at(next_p) == b'^' {
-                    next_p = next(next_p);
+                if self.pat(p) == b'^' {
+                    p = next(p);
                 }
-                while at(next_p) != b']' {
-                    if next_p == self.p_end {
-                        return error("malformed pattern (missing ']')");
+                loop { /* look for a ']' */
+                    if p == self.p_end {
+                        return error(MalformedClass, "malformed pattern (missing ']')");
                     }
-                    let ch = at(next_p);
-                    next_p = next(next_p);
-                    if ch == L_ESC && p < self.p_end {
-                        next_p = next(next_p);  /* skip escapes (e.g. `%]') */
+                    // SAFETY: `p` is not at the end, and only moves on one at a time
+                    let c = unsafe {at(p)};
+                    p = next(p);
+                    if c == L_ESC && p < self.p_end {
+                        p = next(p); /* skip escapes (e.g. '%]') */
+                    }
+                    if self.pat(p) == b']' {
+                        break;
                     }
                 }
-                next(next_p)
+                Ok(next(p))
             },
-            _ => next_p
-        })
+            _ => Ok(p)
+        }
     }
 
+    // count a step, checking the step limit and the deadline
+    fn tick(&mut self) -> Result<()> {
+        if self.steps == 0 {
+            return Err(PatternError::step_limit());
+        }
+        self.steps -= 1;
+        if let Some(deadline) = self.deadline {
+            self.clock -= 1;
+            if self.clock == 0 {
+                if Instant::now() >= deadline {
+                    return Err(PatternError::timeout());
+                }
+                self.clock = DEADLINE_CHECK_STEPS;
+            }
+        }
+        Ok(())
+    }
 }
 
 fn match_class (ch: u8, class: u8) -> bool {
@@ -162,19 +233,37 @@ fn match_class (ch: u8, class: u8) -> bool {
         b'g' => ch.is_ascii_graphic(),
         b'l' => ch.is_ascii_lowercase(),
         b'p' => ch.is_ascii_punctuation(),
-        b's' => ch.is_ascii_whitespace(),
+        b's' => ch.is_ascii_whitespace() || ch == 0x0B, /* C's isspace includes \v */
         b'u' => ch.is_ascii_uppercase(),
         b'w' => ch.is_ascii_alphanumeric(),
         b'x' => ch.is_ascii_hexdigit(),
-        lc => return lc == ch,
+        b'z' => ch == 0, /* deprecated option */
+        _ => return class == ch, /* e.g. %Z is just 'Z' */
     };
     if class.is_ascii_lowercase() { res } else {! res}
 }
 
 
-fn matchbracketclass (c: u8, p: CPtr, ec: CPtr) -> bool {
+// Safety: `p` must be the start of a single character class in a pattern,
+// and `ep` its end as found by `classend`.
+unsafe fn single_class(c: u8, p: CPtr, ep: CPtr) -> bool {
+    // SAFETY: the class has at least one byte, and an escape is followed by another
+    let pc = at(p);
+    match pc {
+        b'.' => true, /* matches any char */
+        L_ESC => match_class(c, at(next(p))),
+        b'[' => matchbracketclass(c, p, sub(ep,1)),
+        _ => c == pc
+    }
+}
+
+// Safety: `p` must point to the '[' of a set in a pattern, and `ec` to
+// the ']' which closes it, as found by `classend`.
+unsafe fn matchbracketclass (c: u8, p: CPtr, ec: CPtr) -> bool {
     let mut p = p;
-    // [^ inverts match
+    // SAFETY: in this function, nothing past `ec` is read: a set always has
+    // a byte after '[' or "[^", an escape inside it is followed by a byte
+    // before `ec`, and a range is only read when its end is before `ec`
     let sig = if at(next(p)) == b'^' {
         p = next(p);
         false
@@ -196,56 +285,56 @@ fn matchbracketclass (c: u8, p: CPtr, ec: CPtr) -> bool {
             if lastc <= c && c <= at(p) {
                 return sig;
             }
-        } else
-        if at(p) == c {
+        } else if at(p) == c {
             return sig;
         }
         p = next(p);
     }
-    return ! sig;
+    ! sig
 }
 
 impl MatchState {
 
-    fn singlematch (&self, s: CPtr, p: CPtr, ep: CPtr) -> bool {
+    fn singlematch (&mut self, s: CPtr, p: CPtr, ep: CPtr) -> bool {
         if s >= self.src_end {
+            self.hit_end = true;
             return false;
         }
-        let c = at(s);
-        let pc = at(p);
-        match pc {
-            b'.' => true, /* matches any char */
-            L_ESC => match_class(c, at(next(p))),
-            b'[' => matchbracketclass(c, p, sub(ep,1)),
-            _ => c == pc
-        }
+        // SAFETY: `s` is before the end of the subject, and `p` and `ep` are
+        // a class found by `classend`
+        unsafe {single_class(at(s), p, ep)}
     }
 
-    fn matchbalance (&self, s: CPtr, p: CPtr) -> Result<CPtr> {
+    fn matchbalance (&mut self, s: CPtr, p: CPtr) -> Result<CPtr> {
         if p >= sub(self.p_end,1) {
-            return error("malformed pattern (missing arguments to '%b')");
+            return error(MissingBalanceArgs, "malformed pattern (missing arguments to '%b')");
+        }
+        if s >= self.src_end {
+            self.hit_end = true;
+            return Ok(null());
         }
-        if at(s) != at(p) {
+        // SAFETY: `s` is before the end of the subject, and there are two
+        // bytes of pattern from `p`
+        let (b, e) = unsafe {(at(p), at(next(p)))};
+        if unsafe {at(s)} != b {
             return Ok(null());
         }
-        // e.g. %b()
-        let b = at(p);
-        let e = at(next(p));
         let mut cont = 1;
         let mut s = next(s);
         while s < self.src_end {
-            let ch = at(s);
+            // SAFETY: `s` is before the end of the subject
+            let ch = unsafe {at(s)};
             if ch == e {
                 cont -= 1;
                 if cont == 0 {
                     return Ok(next(s));
                 }
-            } else
-            if ch == b {
+            } else if ch == b {
                 cont += 1;
             }
             s = next(s);
         }
+        self.hit_end = true;
         Ok(null()) /* string ends out of balance */
     }
 
@@ -271,8 +360,7 @@ impl MatchState {
             let res = self.patt_match(s,next(ep))?;
             if ! res.is_null() {
                 return Ok(res);
-            } else
-            if self.singlematch(s, p, ep) {
+            } else if self.singlematch(s, p, ep) {
                 s = next(s);
             } else {
                 return Ok(null());
@@ -281,13 +369,7 @@ impl MatchState {
     }
 
     fn start_capture(&mut self, s: CPtr, p: CPtr, what: CapLen) -> Result<CPtr> {
-        let level = self.level;
-        if level >= LUA_MAXCAPTURES {
-            return error("too many captures");
-        }
-        self.capture[level].init = s;
-        self.capture[level].len = what;
-        self.level = level + 1;
+        self.new_level(s, what)?;
         let res = self.patt_match(s, p)?;
         if res.is_null() { /* match failed? */
             self.level -= 1; /* undo capture */
@@ -305,126 +387,145 @@ impl MatchState {
         Ok(res)
     }
 
-    fn match_capture(&mut self, s: CPtr, l: usize) -> Result<CPtr> {
+    fn match_capture(&mut self, s: CPtr, l: u8) -> Result<CPtr> {
         let l = self.check_capture(l)?;
-        let len = self.capture[l].len.size()?;
+        let len = match self.capture[l].len {
+            CapLen::Len(len) => len,
+            _ => return Ok(null()) /* a position capture never matches */
+        };
         if diff(self.src_end, s) >= len {
-            unsafe {s.copy_to_nonoverlapping(self.capture[l].init as *mut u8, len);}
-            return Ok(add(s,len));
+            // SAFETY: the capture is a finished part of the subject, and there are
+            // at least `len` bytes of it from `s`
+            let (captured, here) = unsafe {
+                (slice::from_raw_parts(self.capture[l].init, len), slice::from_raw_parts(s, len))
+            };
+            if captured == here {
+                return Ok(add(s,len));
+            }
+        } else {
+            self.hit_end = true;
         }
         Ok(null())
     }
 
-
     fn patt_match(&mut self, s: CPtr, p: CPtr) -> Result<CPtr> {
-        let mut s = s;
-        let mut p = p;
-        self.matchdepth -= 1;
         if self.matchdepth == 0 {
-            return error("pattern too complex");
-        }
-
-        if p == self.p_end {  /* end of pattern? */
-            self.matchdepth += 1;
-            return Ok(s);
-        }
-        match at(p) {
-            b'(' => { /* start capture */
-                if at(next(p)) == b')' { /* position capture? */
-                    s = self.start_capture(s, add(p,2), CapLen::Position)?;
-                } else {
-                    s = self.start_capture(s, next(p), CapLen::Unfinished)?;
-                }
-            },
-            b')' => { /* end capture */
-                s = self.end_capture(s, next(p))?;
-            },
-            b'$' => {
-                if next(p) != self.p_end { /* is the `$' the last char in pattern? */
-                   /* no; go to default */
-                   return self.patt_default_match(s, p);
-                }
-                s = if s == self.src_end {s} else {null()}; /* check end of string */
-            }
-            L_ESC => {  /* escaped sequences not in the format class[*+?-]? */
-                match at(next(p)) {
-                    b'b' => { /* balanced string? */
-                        s = self.matchbalance(s, add(p,2))?;
-                        if ! s.is_null() {
-                            // e.g, after %b()
-                            return self.patt_match(s, add(p,4));
-                        }
-                    },
-                    b'f' => { /* frontier? */
-                        p = add(p,2);
-                        if at(p) != b'[' {
-                            return error("missing '[' after '%f' in pattern");
-                        }
-                        let ep = self.classend(p)?; /* points to what is next */
-                        let previous = if s == self.src_init {b'\0'} else {at(sub(s,1))};
-                        let epl = sub(ep,1);
-                        if ! matchbracketclass(previous,p,epl)
-                           && matchbracketclass(at(s),p,epl) {
-                            return self.patt_match(s, ep);
-                        }
-                        s = null(); /* match failed */
-                    },
-                    b'0'...b'9' => {  /* capture results (%0-%9)? */
-                        s = self.match_capture(s,at(next(p)) as usize)?;
-                        if ! s.is_null() {
-                            return self.patt_match(s, add(p,2));
-                        }
-                    },
-                    _ => return self.patt_default_match(s, p)
-                }
-
-            },
-            _ => return self.patt_default_match(s, p)
-
+            return Err(PatternError::too_complex());
         }
+        self.matchdepth -= 1;
+        let res = self.do_match(s, p);
         self.matchdepth += 1;
-        Ok(s)
+        res
     }
 
-    fn patt_default_match(&mut self, s: CPtr, p: CPtr) -> Result<CPtr> {
+    // the body of 'match'; C's 'goto init' becomes 'continue'
+    fn do_match(&mut self, s: CPtr, p: CPtr) -> Result<CPtr> {
         let mut s = s;
-        /* pattern class plus optional suffix */
-        let ep = self.classend(p)?; /* points to optional suffix */
-        /* does not match at least once? */
-        if ! self.singlematch(s, p, ep) {
-            let epc = at(ep);
-            if epc == b'*' || epc == b'?' || epc == b'-' { /* accept empty? */
-                return self.patt_match(s, next(ep));
-            } else { /* '+' or no suffix */
-                s = null(); /* fail */
+        let mut p = p;
+        loop {
+            self.tick()?;
+            if p == self.p_end {  /* end of pattern? */
+                return Ok(s);
+            }
+            // SAFETY: `p` is not at the end of the pattern
+            match unsafe {at(p)} {
+                b'(' => { /* start capture */
+                    return if self.pat(next(p)) == b')' { /* position capture? */
+                        self.start_capture(s, add(p,2), CapLen::Position)
+                    } else {
+                        self.start_capture(s, next(p), CapLen::Unfinished)
+                    };
+                },
+                b')' => { /* end capture */
+                    return self.end_capture(s, next(p));
+                },
+                b'$' if next(p) == self.p_end => { /* is the '$' the last char in pattern? */
+                    return Ok(if s == self.src_end {s} else {null()}); /* check end of string */
+                },
+                L_ESC => {  /* escaped sequences not in the format class[*+?-]? */
+                    match self.pat(next(p)) {
+                        b'b' => { /* balanced string? */
+                            s = self.matchbalance(s, add(p,2))?;
+                            if s.is_null() {
+                                return Ok(s);
+                            }
+                            p = add(p,4); /* e.g, after %b() */
+                            continue;
+                        },
+                        b'f' => { /* frontier? */
+                            p = add(p,2);
+                            if self.pat(p) != b'[' {
+                                return error(MissingFrontierSet, "missing '[' after '%f' in pattern");
+                            }
+                            let ep = self.classend(p)?; /* points to what is next */
+                            // SAFETY: `s` is after the start of the subject before reading
+                            // the byte before it, and before the end before reading it
+                            let previous = if s == self.src_init {b'\0'} else {unsafe {at(sub(s,1))}};
+                            let current = if s == self.src_end {
+                                self.hit_end = true;
+                                b'\0'
+                            } else {
+                                unsafe {at(s)}
+                            };
+                            let epl = sub(ep,1);
+                            // SAFETY: `classend` found the end of the set starting at `p`
+                            let frontier = unsafe {
+                                ! matchbracketclass(previous,p,epl) && matchbracketclass(current,p,epl)
+                            };
+                            if frontier {
+                                p = ep;
+                                continue;
+                            }
+                            return Ok(null()); /* match failed */
+                        },
+                        c @ b'0'..=b'9' => {  /* capture results (%0-%9)? */
+                            s = self.match_capture(s,c)?;
+                            if s.is_null() {
+                                return Ok(s);
+                            }
+                            p = add(p,2);
+                            continue;
+                        },
+                        _ => {}
+                    }
+                },
+                _ => {}
+            }
+            /* pattern class plus optional suffix */
+            let ep = self.classend(p)?; /* points to optional suffix */
+            let epc = self.pat(ep);
+            /* does not match at least once? */
+            if ! self.singlematch(s, p, ep) {
+                if epc == b'*' || epc == b'?' || epc == b'-' { /* accept empty? */
+                    p = next(ep);
+                    continue;
+                }
+                return Ok(null()); /* '+' or no suffix */
             }
-        } else { /* matched once */
-            match at(ep) { /* handle optional suffix */
+            /* matched once */
+            match epc { /* handle optional suffix */
                 b'?' => {
                     let res = self.patt_match(next(s),next(ep))?;
                     if ! res.is_null() {
-                        s = res;
-                    } else {
-                        return self.patt_match(s, next(ep));
+                        return Ok(res);
                     }
+                    p = next(ep);
                 },
                 b'+' => { /* 1 or more repetitions */
-                    s = next(s);
-                    s = self.max_expand(s, p, ep)?;
+                    return self.max_expand(next(s), p, ep);
                 },
                 b'*' => { /* 0 or more repetitions */
-                    s = self.max_expand(s, p, ep)?;
+                    return self.max_expand(s, p, ep);
                 },
                 b'-' => { /* 0 or more repetitions (minimum) */
-                    s = self.min_expand(s, p, ep)?  ;
+                    return self.min_expand(s, p, ep);
                 },
                 _ => { /* no suffix */
-                    return self.patt_match(next(s),ep);
+                    s = next(s);
+                    p = ep;
                 }
             }
         }
-        self.matchdepth += 1;
-        Ok(s)
     }
 
     fn push_onecapture(&mut self, i: usize, s: CPtr, e: CPtr, mm: &mut [LuaMatch]) -> Result<()> {
@@ -434,14 +535,14 @@ impl MatchState {
                 mm[0].end = diff(e,s);
                 Ok(())
             } else {
-                return error("invalid capture index");
+                error(InvalidCaptureIndex, "invalid capture index")
             }
         } else {
             let init = self.capture[i].init;
             match self.capture[i].len {
-                CapLen::Unfinished => error("unfinished capture"),
+                CapLen::Unfinished => error(UnfinishedCapture, "unfinished capture"),
                 CapLen::Position => {
-                    mm[i].start = diff(init,next(self.src_init));
+                    mm[i].start = diff(init,self.src_init);
                     mm[i].end = mm[i].start;
                     Ok(())
                 },
@@ -463,124 +564,282 @@ impl MatchState {
         Ok(nlevels)  /* number of strings pushed */
     }
 
-    pub fn str_match_check(&mut self, p: CPtr) -> Result<()> {
-        let mut level_stack = [0; LUA_MAXCAPTURES];
-        let mut stack_idx = 0;
-        let mut p = p;
-        while p < self.p_end {
-            let ch = at(p);
-            p = next(p);
-            match ch {
-                L_ESC => {
-                    //p = next(p);
-                    let c = at(p);
-                    match c {
-                        b'b' => {
-                            p = next(p);
-                            if p >= self.p_end {
-                                return error("malformed pattern (missing arguments to '%b')");
-                            }
-                        },
-                        b'f' => {
-                            p = next(p);
-                            if at(p) != b'[' {
-                                return error("missing '['  after '%f' in pattern");
-                            }
-                            p = sub(p,1); // so we see [...]
-                        },
-                        b'0' ... b'9' => {
-                            let l = (c as i8) - (b'1' as i8);
-                            println!("level {}", self.level);
-                            if l < 0 || l as usize >= self.level || self.capture[l as usize].is_unfinished() {
-                                return error(&format!("invalid capture index %{}", l + 1));
-                            }
-                            p = sub(p,1);
-                        },
-                        _ => {}
-                    }
-                },
-                b'[' => {
-                    while at(p) != b']' {
-                        if p == self.p_end {
-                            return error("malformed pattern (missing ']')");
-                        }
-                        if at(p) == L_ESC && p < self.p_end {
-                            p = next(p);
-                        }
-                        p = next(p);
-                    }
-                },
-                b'(' => {
-                    if at(p) != b')' { // not a position capture
-                        level_stack[stack_idx] = self.level;
-                        stack_idx += 1;
-                        self.capture[self.level].len = CapLen::Unfinished;
-                        self.level += 1;
-                        if self.level >= LUA_MAXCAPTURES {
-                            return error("too many captures");
-                        }
-                    } else {
-                        p = next(p);
-                    }
-                },
-                b')' => {
-                    if stack_idx == 0 {
-                        return error("no open capture");
-                    }
-                    stack_idx -= 1;
-                    self.capture[level_stack[stack_idx]].len = CapLen::Position;
-                },
-                _ => {}
+}
+
+// The literal bytes which any match must start with. A literal followed by
+// '*', '?' or '-' is optional, and one followed by '+' ends the prefix.
+// Also returns true if the whole pattern is literal.
+fn literal_prefix(p: &[u8]) -> (Vec<u8>,bool) {
+    let mut res = Vec::new();
+    let mut i = 0;
+    while i < p.len() {
+        let c = p[i];
+        let (c, len) = if c == L_ESC {
+            match p.get(i + 1) {
+                Some(&e) if ! e.is_ascii_alphanumeric() => (e, 2),
+                _ => break
             }
+        } else if b"^$*+?.([-)".contains(&c) {
+            break;
+        } else {
+            (c, 1)
+        };
+        i += len;
+        match p.get(i) {
+            Some(&b'*') | Some(&b'?') | Some(&b'-') => break,
+            Some(&b'+') => {
+                res.push(c);
+                return (res, false);
+            },
+            _ => res.push(c)
         }
-        if stack_idx > 0 {
-            return error("unfinished capture");
+    }
+    let whole = i == p.len();
+    (res, whole)
+}
+
+#[cfg(feature = "memchr")]
+fn find_literal(s: &[u8], lit: &[u8]) -> Option<usize> {
+    if lit.len() == 1 {
+        ::memchr::memchr(lit[0], s)
+    } else {
+        ::memchr::memmem::find(s, lit)
+    }
+}
+
+#[cfg(not(feature = "memchr"))]
+fn find_literal(s: &[u8], lit: &[u8]) -> Option<usize> {
+    let (first, rest) = (lit[0], &lit[1..]);
+    let mut i = 0;
+    while let Some(k) = s[i..].iter().position(|&c| c == first) {
+        let start = i + k;
+        if s[start + 1..].starts_with(rest) {
+            return Some(start);
         }
-        Ok(())
+        i = start + 1;
+    }
+    None
+}
+
+/// What can be found about a pattern's start without running the matcher,
+/// so that unanchored matches can skip to likely start positions
+#[derive(Clone)]
+pub struct Prescan {
+    starts_anchored: bool,
+    // literal bytes any match must begin with
+    literal: Vec<u8>,
+    // the pattern is nothing but this literal
+    whole: bool,
+    // otherwise, the set of bytes a match must begin with
+    class: Option<ByteSet>,
+}
+
+impl Prescan {
+    /// `p` must be a valid pattern
+    pub fn new(p: &[u8]) -> Prescan {
+        let starts_anchored = p.first() == Some(&b'^');
+        let p = if starts_anchored {&p[1..]} else {p};
+        let (literal, whole) = literal_prefix(p);
+        let mut class = None;
+        if literal.is_empty() && ! p.is_empty() && b"%[".contains(&p[0]) {
+            let ms = MatchState::new(null(),null(),add(p.as_ptr(),p.len()),0);
+            let p = p.as_ptr();
+            if let Ok(ep) = ms.classend(p) {
+                // a class followed by '*', '?' or '-' may match nothing;
+                // SAFETY: `ep` is only read before the end of the pattern, which is
+                // not empty, and `classend` found that an escape is followed by a byte
+                let required = ep == ms.p_end || ! b"*?-".contains(unsafe {&at(ep)});
+                let special = unsafe {at(p) == L_ESC && b"bf0123456789".contains(&at(next(p)))};
+                if required && ! special {
+                    // SAFETY: `classend` found the end of the class starting at `p`
+                    class = Some(ByteSet::new(|c| unsafe {single_class(c, p, ep)}));
+                }
+            }
+        }
+        Prescan{starts_anchored, literal, whole, class}
     }
+
+    // the first position at or after `i` where a match could start
+    fn skip(&self, s: &[u8], i: usize) -> Option<usize> {
+        let rest = s.get(i..)?;
+        let k = if ! self.literal.is_empty() {
+            find_literal(rest, &self.literal)?
+        } else if let Some(ref class) = self.class {
+            class.find(rest)?
+        } else {
+            0
+        };
+        Some(i + k)
+    }
+}
+
+/// match starting at byte offset `init`, optionally anchored there.
+/// Offsets in `mm` are always relative to the start of `s`.
+pub fn str_match_at(s: &[u8], p: &[u8], prescan: &Prescan, init: usize, anchored: bool, limits: Limits, mm: &mut [LuaMatch]) -> Result<usize> {
+    str_match_resumable(s, p, prescan, init, anchored, limits, mm).map(|(n, _)| n)
 }
 
-pub fn str_match(s: &[u8], p: &[u8], mm: &mut [LuaMatch]) -> Result<usize> {
-    let mut lp = p.len();
-    let mut p = p.as_ptr();
+/// As `str_match_at`, also giving the first position where a match could
+/// start if more bytes were added to the end of `s`: every start before it
+/// failed without looking past the end, so will fail whatever is added.
+/// With a match, this is at most the start of the match.
+pub fn str_match_resumable(s: &[u8], p: &[u8], prescan: &Prescan, init: usize, anchored: bool, limits: Limits, mm: &mut [LuaMatch]) -> Result<(usize,usize)> {
+    let anchored = anchored || prescan.starts_anchored;
+    // where a partial literal at the end could start
+    let literal_tail = (s.len() + 1).saturating_sub(prescan.literal.len()).max(init);
+    // patterns without magic characters are just substring searches
+    if prescan.whole && ! prescan.literal.is_empty() {
+        let lit = &prescan.literal;
+        let found = if anchored {
+            match s.get(init..) {
+                Some(rest) if rest.starts_with(lit) => Some(init),
+                _ => None
+            }
+        } else {
+            prescan.skip(s, init)
+        };
+        return Ok(match found {
+            Some(start) => {
+                mm[0].start = start;
+                mm[0].end = start + lit.len();
+                (1, start)
+            },
+            None => (0, literal_tail)
+        });
+    }
+    if init > s.len() {
+        return Ok((0, init));
+    }
+    let subject = s;
+    let p = if p.first() == Some(&b'^') {&p[1..]} else {p}; /* skip anchor character */
+    let lp = p.len();
+    let p = p.as_ptr();
     let ls = s.len();
     let s = s.as_ptr();
-    let mut s1 = s;
-    let anchor = at(p) == b'^';
-    if anchor {
-        p = next(p);
-        lp -= 1;  /* skip anchor character */
-    }
+    let mut s1 = add(s,init);
 
-    let mut ms = MatchState::new(s,add(s,ls),add(p,lp));
+    let mut ms = MatchState::new(s,add(s,ls),add(p,lp),limits.max_captures);
+    if let Some(steps) = limits.max_steps {
+        ms.steps = steps;
+    }
+    ms.deadline = limits.deadline;
+    let mut open = None;
     loop {
+        // unanchored matches can skip straight to where a match may start
+        if ! anchored {
+            match prescan.skip(subject, diff(s1,s)) {
+                Some(i) => s1 = add(s,i),
+                None => {
+                    let tail = if prescan.literal.is_empty() {ls} else {literal_tail.max(diff(s1,s))};
+                    return Ok((0, open.unwrap_or(tail)));
+                }
+            }
+        }
+        ms.level = 0;
+        ms.matchdepth = MAXCCALLS;
+        ms.hit_end = false;
         let res = ms.patt_match(s1, p)?;
         if ! res.is_null() {
             mm[0].start = diff(s1,s); /* start */
             mm[0].end = diff(res,s); /* end */
-            return Ok(ms.push_captures(null(),null(),&mut mm[1..])? + 1);
+            let n = ms.push_captures(null(),null(),&mut mm[1..])? + 1;
+            return Ok((n, open.unwrap_or(mm[0].start)));
+        }
+        if ms.hit_end && open.is_none() {
+            open = Some(diff(s1,s));
         }
         s1 = next(s1);
-        if ! (s1 < ms.src_end && ! anchor) {
+        if s1 > ms.src_end || anchored {
             break;
         }
     }
-    Ok(0)
+    Ok((0, open.unwrap_or(ls)))
 }
 
-pub fn str_check(p: &[u8]) -> Result<()> {
-    let mut lp = p.len();
-    let mut p = p.as_ptr();
-    let anchor = at(p) == b'^';
-    if anchor {
-        p = next(p);
-        lp -= 1;  /* skip anchor character */
+// where a set starting at `i` ends, following the same rules as 'classend'
+fn set_end(p: &[u8], i: usize) -> Result<usize> {
+    let mut j = i + 1;
+    if p.get(j) == Some(&b'^') {
+        j += 1;
+    }
+    loop { /* look for a ']' */
+        if j >= p.len() {
+            return Err(PatternError::new(MalformedClass, "malformed pattern (missing ']')").at(i));
+        }
+        let c = p[j];
+        j += 1;
+        if c == L_ESC && j < p.len() {
+            j += 1; /* skip escapes (e.g. '%]') */
+        }
+        if p.get(j) == Some(&b']') {
+            return Ok(j + 1);
+        }
+    }
+}
+
+/// Check a pattern up front, so that matching cannot fail
+/// (except by running out of steps, time or stack).
+pub fn str_check(p: &[u8], max_captures: usize) -> Result<()> {
+    let fail = |kind, msg: &str, i| Err(PatternError::new(kind, msg).at(i));
+    // whether each capture has been closed
+    let mut closed: Vec<bool> = Vec::new();
+    // the open captures, and where they start
+    let mut open = Vec::new();
+    let mut i = if p.first() == Some(&b'^') {1} else {0};
+    while i < p.len() {
+        match p[i] {
+            L_ESC => {
+                let c = match p.get(i + 1) {
+                    Some(&c) => c,
+                    None => return fail(EndsWithEscape, "malformed pattern (ends with '%')", i)
+                };
+                match c {
+                    b'b' => {
+                        if i + 4 > p.len() {
+                            return fail(MissingBalanceArgs, "malformed pattern (missing arguments to '%b')", i);
+                        }
+                        i += 4;
+                    },
+                    b'f' if p.get(i + 2) != Some(&b'[') => {
+                        return fail(MissingFrontierSet, "missing '[' after '%f' in pattern", i);
+                    },
+                    b'0' ..= b'9' => {
+                        let l = (c - b'0') as usize;
+                        if l == 0 || l > closed.len() || ! closed[l - 1] {
+                            return fail(InvalidCaptureIndex, &format!("invalid capture index %{}", l), i);
+                        }
+                        i += 2;
+                    },
+                    _ => i += 2
+                }
+            },
+            b'[' => i = set_end(p, i)?,
+            b'(' => {
+                if closed.len() >= max_captures {
+                    return fail(TooManyCaptures, "too many captures", i);
+                }
+                if p.get(i + 1) == Some(&b')') { // position capture
+                    closed.push(true);
+                    i += 2;
+                } else {
+                    open.push((closed.len(), i));
+                    closed.push(false);
+                    i += 1;
+                }
+            },
+            b')' => {
+                match open.pop() {
+                    Some((level, _)) => closed[level] = true,
+                    None => return fail(NoOpenCapture, "no open capture", i)
+                }
+                i += 1;
+            },
+            _ => i += 1
+        }
     }
-    let mut ms = MatchState::new(null(),null(),add(p,lp));
-    if at(sub(ms.p_end,1)) == b'%' {
-        return error("malformed pattern (ends with '%')");
+    if let Some(&(_, start)) = open.last() {
+        return fail(UnfinishedCapture, "unfinished capture", start);
     }
-    ms.str_match_check(p)?;
     Ok(())
 }
 
@@ -0,0 +1,144 @@
+//! Using Lua patterns as [nom](https://docs.rs/nom) parsers.
+//!
+//! The pattern must match at the start of the input, and parsing continues
+//! after the match. This is handy for the messy lexical bits of a grammar.
+//! As with `LuaPattern::new`, these functions panic if the pattern is bad.
+//!
+//! ```
+//! extern crate nom;
+//! extern crate lua_patterns;
+//! use nom::Parser;
+//! use nom::bytes::complete::tag;
+//! use lua_patterns::nom_adapter::{pattern, captures};
+//!
+//! # fn main() {
+//! let mut assign = (pattern("[%a_][%w_]*"), tag("="), captures("(%d+)%.?(%d*)"));
+//! let res: nom::IResult<&str,_> = assign.parse("x=3.14;");
+//! let (rest, (name, _, num)) = res.unwrap();
+//! assert_eq!(name, "x");
+//! assert_eq!(num, &["3","14"]);
+//! assert_eq!(rest, ";");
+//! # }
+//! ```
+
+use nom::{self, IResult};
+use nom::error::{ErrorKind, ParseError};
+use LuaPattern;
+
+fn no_match<I, E: ParseError<I>>(input: I) -> nom::Err<E> {
+    nom::Err::Error(E::from_error_kind(input, ErrorKind::Verify))
+}
+
+// the captures as ranges; the whole match if there are none
+fn capture_ranges(m: &LuaPattern) -> Vec<::std::ops::Range<usize>> {
+    if m.n_match > 1 {
+        (1..m.n_match).map(|i| m.capture(i)).collect()
+    } else {
+        vec![m.range()]
+    }
+}
+
+/// A parser which recognizes text matching a pattern, returning the match.
+pub fn pattern<'a, 'b, E>(patt: &'a str) -> impl FnMut(&'b str) -> IResult<&'b str,&'b str,E> + 'a
+where 'b: 'a, E: 'a + ParseError<&'b str> {
+    let mut m = LuaPattern::new(patt);
+    move |input: &'b str| {
+        if m.matches_bytes_at(input.as_bytes(), 0, true) {
+            let end = m.range().end;
+            Ok((&input[end..], &input[..end]))
+        } else {
+            Err(no_match(input))
+        }
+    }
+}
+
+/// A parser which matches a pattern, returning the captures
+/// (or the whole match if there are none).
+pub fn captures<'a, 'b, E>(patt: &'a str) -> impl FnMut(&'b str) -> IResult<&'b str,Vec<&'b str>,E> + 'a
+where 'b: 'a, E: 'a + ParseError<&'b str> {
+    let mut m = LuaPattern::new(patt);
+    move |input: &'b str| {
+        if m.matches_bytes_at(input.as_bytes(), 0, true) {
+            let caps = capture_ranges(&m).into_iter().map(|r| &input[r]).collect();
+            Ok((&input[m.range().end..], caps))
+        } else {
+            Err(no_match(input))
+        }
+    }
+}
+
+/// A parser which recognizes bytes matching a byte pattern, returning the match.
+pub fn pattern_bytes<'a, 'b, E>(patt: &'a [u8]) -> impl FnMut(&'b [u8]) -> IResult<&'b [u8],&'b [u8],E> + 'a
+where 'b: 'a, E: 'a + ParseError<&'b [u8]> {
+    let mut m = LuaPattern::from_bytes(patt);
+    move |input: &'b [u8]| {
+        if m.matches_bytes_at(input, 0, true) {
+            let end = m.range().end;
+            Ok((&input[end..], &input[..end]))
+        } else {
+            Err(no_match(input))
+        }
+    }
+}
+
+/// A parser which matches a byte pattern, returning the captures
+/// (or the whole match if there are none).
+pub fn captures_bytes<'a, 'b, E>(patt: &'a [u8]) -> impl FnMut(&'b [u8]) -> IResult<&'b [u8],Vec<&'b [u8]>,E> + 'a
+where 'b: 'a, E: 'a + ParseError<&'b [u8]> {
+    let mut m = LuaPattern::from_bytes(patt);
+    move |input: &'b [u8]| {
+        if m.matches_bytes_at(input, 0, true) {
+            let caps = capture_ranges(&m).into_iter().map(|r| &input[r]).collect();
+            Ok((&input[m.range().end..], caps))
+        } else {
+            Err(no_match(input))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nom::Parser;
+    use nom::branch::alt;
+    use nom::multi::many0;
+    use nom::sequence::preceded;
+    use nom::error::Error;
+
+    #[test]
+    fn must_match_at_start() {
+        let mut word = pattern::<Error<&str>>("%a+");
+        assert_eq!(word("hello world"), Ok((" world", "hello")));
+        let err = word(" hello").unwrap_err();
+        assert_eq!(err, nom::Err::Error(Error::new(" hello", ErrorKind::Verify)));
+        // anchoring twice is harmless
+        let mut word = pattern::<Error<&str>>("^%a+");
+        assert_eq!(word("hi"), Ok(("", "hi")));
+    }
+
+    #[test]
+    fn combining() {
+        let number = pattern("%-?%d+");
+        let ident = pattern("[%a_][%w_]*");
+        let mut tokens = many0(preceded(pattern("%s*"), alt((number, ident))));
+        let res: IResult<&str,Vec<&str>> = tokens.parse("x1 -20 y ");
+        assert_eq!(res, Ok((" ", vec!["x1","-20","y"])));
+    }
+
+    #[test]
+    fn capture_lists() {
+        let mut kv = captures::<Error<&str>>("(%a+)=(%d+);?");
+        assert_eq!(kv("a=1;b=2"), Ok(("b=2", vec!["a","1"])));
+        let mut digits = captures::<Error<&str>>("%d+");
+        assert_eq!(digits("42!"), Ok(("!", vec!["42"])));
+    }
+
+    #[test]
+    fn bytes() {
+        let mut magic = pattern_bytes::<Error<&[u8]>>(b"\x89PNG");
+        assert_eq!(magic(b"\x89PNG\r\n"), Ok((&b"\r\n"[..], &b"\x89PNG"[..])));
+        let mut pair = captures_bytes::<Error<&[u8]>>(b"(.)(.)");
+        assert_eq!(pair(b"\x00\xFFrest"), Ok((&b"rest"[..], vec![&b"\x00"[..], &b"\xFF"[..]])));
+        assert!(pair(b"x").is_err());
+    }
+}
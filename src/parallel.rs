@@ -0,0 +1,132 @@
+//! Processing large texts line by line on a [rayon](https://docs.rs/rayon)
+//! thread pool.
+//!
+//! The text is split into chunks of whole lines, and each thread matches with
+//! its own copy of the pattern. Lines are separated by `'\n'` and matches never
+//! span lines, so the results are the same as working through the lines in
+//! order, whatever the chunking.
+//!
+//! ```
+//! let text = "a=1\nb=2\nc=3\n".repeat(10000);
+//! let m = lua_patterns::LuaPattern::new("%a=(%d)");
+//! let values = m.par_gmatch_lines(&text);
+//! assert_eq!(values.len(), 30000);
+//! assert_eq!(&values[..4], &["1","2","3","1"]);
+//! let digits = m.par_gsub_lines(&text, "%1");
+//! assert!(digits.starts_with("1\n2\n3\n1\n"));
+//! ```
+
+use std::fmt::Write;
+use rayon::prelude::*;
+use luapat::{LuaMatch, Limits, Prescan};
+use LuaPattern;
+
+// chunks are about this size, so each task does a worthwhile amount of work
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// split text after '\n' into chunks of at least CHUNK_SIZE bytes
+fn line_chunks(text: &str) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = text;
+    while rest.len() > CHUNK_SIZE {
+        match rest.as_bytes()[CHUNK_SIZE..].iter().position(|&b| b == b'\n') {
+            Some(i) => {
+                let (chunk, tail) = rest.split_at(CHUNK_SIZE + i + 1);
+                chunks.push(chunk);
+                rest = tail;
+            },
+            None => break
+        }
+    }
+    if ! rest.is_empty() {
+        chunks.push(rest);
+    }
+    chunks
+}
+
+// the lines of a chunk, with and without their '\n'
+fn lines(chunk: &str) -> impl Iterator<Item=(&str,bool)> {
+    chunk.split_inclusive('\n').map(|line| match line.strip_suffix('\n') {
+        Some(line) => (line, true),
+        None => (line, false)
+    })
+}
+
+// a fresh matcher for a worker thread
+fn worker(patt: &[u8], limits: Limits) -> LuaPattern<'_> {
+    let matches = vec![LuaMatch{start: 0, end: 0}; limits.max_captures + 1];
+    LuaPattern{patt, matches, n_match: 0, limits, prescan: Prescan::new(patt)}
+}
+
+impl <'a> LuaPattern<'a> {
+    /// All matches in all lines of a text, found in parallel.
+    ///
+    /// As with `gmatch`, each match is the first capture, or the whole match
+    /// if there are no captures. The matches are in the order of the text.
+    pub fn par_gmatch_lines<'b>(&self, text: &'b str) -> Vec<&'b str> {
+        let (patt, limits) = (self.patt, self.limits);
+        let found: Vec<Vec<&'b str>> = line_chunks(text).into_par_iter()
+            .map_init(|| worker(patt, limits), |m, chunk| {
+                let mut found = Vec::new();
+                for (line, _) in lines(chunk) {
+                    found.extend(m.gmatch(line));
+                }
+                found
+            })
+            .collect();
+        found.into_iter().flatten().collect()
+    }
+
+    /// Globally substitute all matches in each line of a text, in parallel.
+    ///
+    /// The replacement is as for `gsub`, and it panics if that is bad.
+    pub fn par_gsub_lines(&self, text: &str, repl: &str) -> String {
+        self.check_replacement(repl).expect("bad replacement");
+        let (patt, limits) = (self.patt, self.limits);
+        let chunks: Vec<String> = line_chunks(text).into_par_iter()
+            .map_init(|| worker(patt, limits), |m, chunk| {
+                let mut out = String::with_capacity(chunk.len());
+                for (line, newline) in lines(chunk) {
+                    m.gsub_to(line, repl, &mut out).expect("writing to a String");
+                    if newline {
+                        out.write_char('\n').expect("writing to a String");
+                    }
+                }
+                out
+            })
+            .collect();
+        chunks.concat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_are_whole_lines() {
+        let line = "x".repeat(1000) + "\n";
+        let text = line.repeat(200) + "end";
+        let chunks = line_chunks(&text);
+        assert!(chunks.len() > 1);
+        assert_eq!(chunks.concat(), text);
+        for chunk in &chunks[..chunks.len() - 1] {
+            assert!(chunk.ends_with('\n'));
+        }
+        assert_eq!(line_chunks(""), Vec::<&str>::new());
+        assert_eq!(line_chunks("short"), &["short"]);
+    }
+
+    #[test]
+    fn same_as_sequential() {
+        let text: String = (0..50000).map(|i| format!("line {} has {} words\n", i, i % 7)).collect();
+        let mut m = LuaPattern::new("(%d+) words");
+        let expected: Vec<_> = text.lines().flat_map(|line| m.gmatch(line).collect::<Vec<_>>()).collect();
+        assert_eq!(m.par_gmatch_lines(&text), expected);
+        assert_eq!(m.par_gsub_lines(&text, "%1 w"), m.gsub(&text, "%1 w"));
+        // matches do not span lines
+        let m = LuaPattern::new("a%s+b");
+        assert_eq!(m.par_gsub_lines("a\nb\na b", "X"), "a\nb\nX");
+        assert_eq!(m.par_gmatch_lines("a\nb\n"), Vec::<&str>::new());
+    }
+}
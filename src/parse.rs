@@ -0,0 +1,791 @@
+//! Parsing Lua patterns into a syntax tree.
+//!
+//! This follows the grammar of Lua string patterns, so that tools can
+//! inspect patterns without reimplementing it.
+//!
+//! ```
+//! use lua_patterns::parse::*;
+//! let patt = parse("^(%a+)=%d*$").unwrap();
+//! assert!(patt.anchored);
+//! assert!(patt.end_anchored);
+//! if let ItemKind::Capture(ref cap) = patt.items[0].kind {
+//!     assert_eq!(cap.index, 1);
+//!     assert_eq!(cap.items[0].kind, ItemKind::Single(
+//!         Single::Class(Class{kind: ClassKind::Letter, negated: false}),
+//!         Quantifier::OneOrMore
+//!     ));
+//! }
+//! assert_eq!(patt.to_bytes(), b"^(%a+)=%d*$");
+//! ```
+
+use std::ops;
+use errors::*;
+use errors::PatternErrorKind::*;
+
+/// A parsed Lua pattern
+#[derive(Debug,Clone,PartialEq)]
+pub struct Pattern {
+    /// starts with `^`
+    pub anchored: bool,
+    /// the items in order
+    pub items: Vec<Item>,
+    /// ends with `$`
+    pub end_anchored: bool,
+    /// total number of captures, including position captures
+    pub num_captures: usize,
+}
+
+/// A pattern item, with its position in the pattern
+#[derive(Debug,Clone,PartialEq)]
+pub struct Item {
+    pub kind: ItemKind,
+    /// the bytes of the pattern making up this item
+    pub span: ops::Range<usize>,
+}
+
+/// The kinds of pattern item
+#[derive(Debug,Clone,PartialEq)]
+pub enum ItemKind {
+    /// a single character class, with optional quantifier
+    Single(Single,Quantifier),
+    /// a capture `(...)`
+    Capture(Capture),
+    /// a position capture `()`, with its index
+    Position(usize),
+    /// a reference to an earlier capture, like `%1`
+    BackRef(usize),
+    /// a balanced match like `%b()`
+    Balanced(u8,u8),
+    /// a frontier like `%f[%w]`
+    Frontier(Set),
+}
+
+/// A capture with its index (starting at one) and contents
+#[derive(Debug,Clone,PartialEq)]
+pub struct Capture {
+    pub index: usize,
+    pub items: Vec<Item>,
+}
+
+/// Something which matches a single character
+#[derive(Debug,Clone,PartialEq)]
+pub enum Single {
+    /// `.`
+    Any,
+    /// a literal character, which may have been escaped
+    Char(u8),
+    /// a class like `%d`
+    Class(Class),
+    /// a set like `[%a_]`
+    Set(Set),
+}
+
+/// The quantifier following a single character class
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Quantifier {
+    /// no quantifier
+    One,
+    /// `*`
+    ZeroOrMore,
+    /// `+`
+    OneOrMore,
+    /// `-`
+    ZeroOrMoreLazy,
+    /// `?`
+    ZeroOrOne,
+}
+
+/// The character classes like `%a`
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum ClassKind {
+    /// `%a`
+    Letter,
+    /// `%c`
+    Control,
+    /// `%d`
+    Digit,
+    /// `%g`
+    Printable,
+    /// `%l`
+    Lower,
+    /// `%p`
+    Punct,
+    /// `%s`
+    Space,
+    /// `%u`
+    Upper,
+    /// `%w`
+    Alnum,
+    /// `%x`
+    Hex,
+    /// `%z`, the nul byte (deprecated, but still in Lua 5.4)
+    Nul,
+}
+
+/// A character class, which is negated if written in upper case, like `%S`
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct Class {
+    pub kind: ClassKind,
+    pub negated: bool,
+}
+
+/// A set like `[^%s,]`
+#[derive(Debug,Clone,PartialEq)]
+pub struct Set {
+    pub negated: bool,
+    pub items: Vec<SetItem>,
+}
+
+/// Members of a set
+#[derive(Debug,Clone,PartialEq)]
+pub enum SetItem {
+    Char(u8),
+    Range(u8,u8),
+    Class(Class),
+}
+
+const MAGIC: &[u8] = b"^$()%.[]*+-?";
+
+impl ClassKind {
+    /// the class for a letter like `b'a'`, if any
+    pub fn from_letter(letter: u8) -> Option<ClassKind> {
+        use self::ClassKind::*;
+        Some(match letter.to_ascii_lowercase() {
+            b'a' => Letter,
+            b'c' => Control,
+            b'd' => Digit,
+            b'g' => Printable,
+            b'l' => Lower,
+            b'p' => Punct,
+            b's' => Space,
+            b'u' => Upper,
+            b'w' => Alnum,
+            b'x' => Hex,
+            b'z' => Nul,
+            _ => return None
+        })
+    }
+
+    /// the (lower case) letter for this class
+    pub fn letter(self) -> u8 {
+        use self::ClassKind::*;
+        match self {
+            Letter => b'a',
+            Control => b'c',
+            Digit => b'd',
+            Printable => b'g',
+            Lower => b'l',
+            Punct => b'p',
+            Space => b's',
+            Upper => b'u',
+            Alnum => b'w',
+            Hex => b'x',
+            Nul => b'z',
+        }
+    }
+}
+
+impl Class {
+    /// the class for an escape like `%S`, given `b'S'`
+    pub fn from_letter(letter: u8) -> Option<Class> {
+        ClassKind::from_letter(letter).map(|kind| Class{kind, negated: letter.is_ascii_uppercase()})
+    }
+
+    /// the letter for the escape, upper case if negated
+    pub fn letter(self) -> u8 {
+        let l = self.kind.letter();
+        if self.negated {l.to_ascii_uppercase()} else {l}
+    }
+
+    /// does this class match a byte? (uses the C locale, like the matcher)
+    pub fn matches(self, c: u8) -> bool {
+        use self::ClassKind::*;
+        let res = match self.kind {
+            Letter => c.is_ascii_alphabetic(),
+            Control => c.is_ascii_control(),
+            Digit => c.is_ascii_digit(),
+            Printable => c.is_ascii_graphic(),
+            Lower => c.is_ascii_lowercase(),
+            Punct => c.is_ascii_punctuation(),
+            Space => c.is_ascii_whitespace() || c == 0x0B,
+            Upper => c.is_ascii_uppercase(),
+            Alnum => c.is_ascii_alphanumeric(),
+            Hex => c.is_ascii_hexdigit(),
+            Nul => c == 0,
+        };
+        res != self.negated
+    }
+}
+
+impl Set {
+    /// does this set match a byte?
+    pub fn matches(&self, c: u8) -> bool {
+        let found = self.items.iter().any(|item| match *item {
+            SetItem::Char(x) => x == c,
+            SetItem::Range(lo, hi) => lo <= c && c <= hi,
+            SetItem::Class(class) => class.matches(c),
+        });
+        found != self.negated
+    }
+}
+
+impl Single {
+    /// does this match a byte?
+    pub fn matches(&self, c: u8) -> bool {
+        match *self {
+            Single::Any => true,
+            Single::Char(x) => x == c,
+            Single::Class(class) => class.matches(c),
+            Single::Set(ref set) => set.matches(c),
+        }
+    }
+}
+
+impl Quantifier {
+    fn from_byte(b: u8) -> Option<Quantifier> {
+        Some(match b {
+            b'*' => Quantifier::ZeroOrMore,
+            b'+' => Quantifier::OneOrMore,
+            b'-' => Quantifier::ZeroOrMoreLazy,
+            b'?' => Quantifier::ZeroOrOne,
+            _ => return None
+        })
+    }
+
+    fn to_byte(self) -> Option<u8> {
+        match self {
+            Quantifier::One => None,
+            Quantifier::ZeroOrMore => Some(b'*'),
+            Quantifier::OneOrMore => Some(b'+'),
+            Quantifier::ZeroOrMoreLazy => Some(b'-'),
+            Quantifier::ZeroOrOne => Some(b'?'),
+        }
+    }
+}
+
+fn error<T>(kind: PatternErrorKind, msg: &str, pos: usize) -> Result<T,PatternError> {
+    Err(PatternError::new(kind, msg).at(pos))
+}
+
+/// Parse a Lua pattern, given as a string or bytes.
+///
+/// The errors are the same as for `LuaPattern::new_try`.
+pub fn parse<P: AsRef<[u8]>>(patt: P) -> Result<Pattern,PatternError> {
+    Parser{p: patt.as_ref(), i: 0, level: 0, closed: Vec::new()}.pattern()
+}
+
+struct Parser<'a> {
+    p: &'a [u8],
+    i: usize,
+    level: usize,
+    closed: Vec<bool>,
+}
+
+impl <'a> Parser<'a> {
+    fn pattern(&mut self) -> Result<Pattern,PatternError> {
+        let anchored = self.p.first() == Some(&b'^');
+        if anchored {
+            self.i = 1;
+        }
+        // stack of open captures, with the items before them
+        let mut stack: Vec<(usize,usize,Vec<Item>)> = Vec::new();
+        let mut items = Vec::new();
+        let mut end_anchored = false;
+        while self.i < self.p.len() {
+            let start = self.i;
+            let kind = match self.p[start] {
+                b'(' => {
+                    let index = self.new_capture();
+                    if self.p.get(start + 1) == Some(&b')') {
+                        self.closed[index - 1] = true;
+                        self.i += 2;
+                        ItemKind::Position(index)
+                    } else {
+                        self.i += 1;
+                        stack.push((index, start, items));
+                        items = Vec::new();
+                        continue;
+                    }
+                },
+                b')' => {
+                    let (index, start, outer) = match stack.pop() {
+                        Some(open) => open,
+                        None => return error(NoOpenCapture, "no open capture", start)
+                    };
+                    self.closed[index - 1] = true;
+                    self.i += 1;
+                    let cap = Capture{index, items};
+                    items = outer;
+                    items.push(Item{kind: ItemKind::Capture(cap), span: start..self.i});
+                    continue;
+                },
+                b'$' if start + 1 == self.p.len() => {
+                    end_anchored = true;
+                    self.i += 1;
+                    continue;
+                },
+                b'%' => self.escape()?,
+                _ => {
+                    let single = self.single()?;
+                    ItemKind::Single(single, self.quantifier())
+                }
+            };
+            items.push(Item{kind, span: start..self.i});
+        }
+        if let Some(&(_, start, _)) = stack.last() {
+            return error(UnfinishedCapture, "unfinished capture", start);
+        }
+        Ok(Pattern{anchored, items, end_anchored, num_captures: self.level})
+    }
+
+    fn new_capture(&mut self) -> usize {
+        self.level += 1;
+        self.closed.push(false);
+        self.level
+    }
+
+    // an item starting with '%'
+    fn escape(&mut self) -> Result<ItemKind,PatternError> {
+        let p = self.p;
+        let i = self.i;
+        let c = match p.get(i + 1) {
+            Some(&c) => c,
+            None => return error(EndsWithEscape, "malformed pattern (ends with '%')", i)
+        };
+        Ok(match c {
+            b'b' => {
+                if i + 4 > p.len() {
+                    return error(MissingBalanceArgs, "malformed pattern (missing arguments to '%b')", i);
+                }
+                self.i += 4;
+                ItemKind::Balanced(p[i + 2], p[i + 3])
+            },
+            b'f' => {
+                self.i += 2;
+                if p.get(self.i) != Some(&b'[') {
+                    return error(MissingFrontierSet, "missing '[' after '%f' in pattern", i);
+                }
+                ItemKind::Frontier(self.set()?)
+            },
+            b'0' ..= b'9' => {
+                let l = (c - b'0') as usize;
+                if l == 0 || l > self.level || ! self.closed[l - 1] {
+                    return error(InvalidCaptureIndex, &format!("invalid capture index %{}", l), i);
+                }
+                self.i += 2;
+                ItemKind::BackRef(l)
+            },
+            _ => {
+                let single = self.single()?;
+                ItemKind::Single(single, self.quantifier())
+            }
+        })
+    }
+
+    // a single character class
+    fn single(&mut self) -> Result<Single,PatternError> {
+        let c = self.p[self.i];
+        Ok(match c {
+            b'.' => {
+                self.i += 1;
+                Single::Any
+            },
+            b'[' => Single::Set(self.set()?),
+            b'%' => {
+                let e = self.p[self.i + 1];
+                self.i += 2;
+                match Class::from_letter(e) {
+                    Some(class) => Single::Class(class),
+                    None => Single::Char(e)
+                }
+            },
+            _ => {
+                self.i += 1;
+                Single::Char(c)
+            }
+        })
+    }
+
+    fn quantifier(&mut self) -> Quantifier {
+        match self.p.get(self.i).and_then(|&b| Quantifier::from_byte(b)) {
+            Some(q) => {
+                self.i += 1;
+                q
+            },
+            None => Quantifier::One
+        }
+    }
+
+    // a set starting at '[', following Lua's rules: the first
+    // character (after any '^') is always part of the set
+    fn set(&mut self) -> Result<Set,PatternError> {
+        let p = self.p;
+        let mut j = self.i + 1;
+        let negated = p.get(j) == Some(&b'^');
+        if negated {
+            j += 1;
+        }
+        let first = j;
+        loop {
+            if j >= p.len() {
+                return error(MalformedClass, "malformed pattern (missing ']')", self.i);
+            }
+            let c = p[j];
+            j += 1;
+            if c == b'%' && j < p.len() {
+                j += 1;
+            }
+            if p.get(j) == Some(&b']') {
+                break;
+            }
+        }
+        let end = j;
+        let mut items = Vec::new();
+        let mut k = first;
+        while k < end {
+            if p[k] == b'%' {
+                k += 1;
+                items.push(match Class::from_letter(p[k]) {
+                    Some(class) => SetItem::Class(class),
+                    None => SetItem::Char(p[k])
+                });
+            } else if k + 2 < end && p[k + 1] == b'-' {
+                items.push(SetItem::Range(p[k], p[k + 2]));
+                k += 2;
+            } else {
+                items.push(SetItem::Char(p[k]));
+            }
+            k += 1;
+        }
+        self.i = end + 1;
+        Ok(Set{negated, items})
+    }
+}
+
+impl Pattern {
+    /// Write the pattern out again as Lua pattern bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut res = Vec::new();
+        if self.anchored {
+            res.push(b'^');
+        }
+        write_items(&self.items, &mut res);
+        if self.end_anchored {
+            res.push(b'$');
+        }
+        res
+    }
+}
+
+impl Pattern {
+    /// A human-readable description of the pattern, one item per line
+    ///
+    /// ```
+    /// let patt = lua_patterns::parse::parse("^(%a+)=%d*").unwrap();
+    /// assert_eq!(patt.explain(), "\
+    /// start of text
+    /// capture 1: one or more letters
+    /// literal '='
+    /// zero or more digits
+    /// ");
+    /// ```
+    pub fn explain(&self) -> String {
+        let mut res = String::new();
+        if self.anchored {
+            res.push_str("start of text\n");
+        }
+        explain_items(&self.items, "", &mut res);
+        if self.end_anchored {
+            res.push_str("end of text\n");
+        }
+        res
+    }
+}
+
+fn explain_items(items: &[Item], indent: &str, res: &mut String) {
+    for item in items {
+        res.push_str(indent);
+        match item.kind {
+            ItemKind::Capture(ref cap) => {
+                if cap.items.len() == 1 {
+                    res.push_str(&format!("capture {}: ", cap.index));
+                    explain_items(&cap.items, "", res);
+                } else {
+                    res.push_str(&format!("capture {}:\n", cap.index));
+                    explain_items(&cap.items, &format!("{}  ", indent), res);
+                }
+                continue;
+            },
+            ItemKind::Single(ref single, q) => {
+                let (one, many) = single_names(single);
+                res.push_str(&match q {
+                    Quantifier::One => one,
+                    Quantifier::ZeroOrMore => format!("zero or more {}", many),
+                    Quantifier::OneOrMore => format!("one or more {}", many),
+                    Quantifier::ZeroOrMoreLazy => format!("as few as possible {}", many),
+                    Quantifier::ZeroOrOne => format!("optionally {}", one),
+                });
+            },
+            ItemKind::Position(index) => res.push_str(&format!("capture {}: the current position", index)),
+            ItemKind::BackRef(index) => res.push_str(&format!("the same text as capture {}", index)),
+            ItemKind::Balanced(b, e) => res.push_str(&format!("a balanced string from {} to {}", char_name(b), char_name(e))),
+            ItemKind::Frontier(ref set) => res.push_str(&format!("a frontier before {}", set_names(set).1)),
+        }
+        res.push('\n');
+    }
+}
+
+fn char_name(c: u8) -> String {
+    if c.is_ascii_graphic() || c == b' ' {
+        format!("'{}'", c as char)
+    } else {
+        format!("0x{:02X}", c)
+    }
+}
+
+// singular and plural descriptions of a class
+fn class_names(class: Class) -> (String,String) {
+    use self::ClassKind::*;
+    let (one, many) = match class.kind {
+        Letter => ("a letter", "letters"),
+        Control => ("a control character", "control characters"),
+        Digit => ("a digit", "digits"),
+        Printable => ("a printable character", "printable characters"),
+        Lower => ("a lower-case letter", "lower-case letters"),
+        Punct => ("a punctuation character", "punctuation characters"),
+        Space => ("a space character", "space characters"),
+        Upper => ("an upper-case letter", "upper-case letters"),
+        Alnum => ("a letter or digit", "letters or digits"),
+        Hex => ("a hex digit", "hex digits"),
+        Nul => ("a nul byte", "nul bytes"),
+    };
+    if class.negated {
+        (format!("a character which is not {}", one), format!("characters which are not {}", many))
+    } else {
+        (one.to_string(), many.to_string())
+    }
+}
+
+fn set_names(set: &Set) -> (String,String) {
+    let members: Vec<_> = set.items.iter().map(|item| match *item {
+        SetItem::Char(c) => char_name(c),
+        SetItem::Range(lo, hi) => format!("{} to {}", char_name(lo), char_name(hi)),
+        SetItem::Class(class) => class_names(class).1,
+    }).collect();
+    let members = members.join(", ");
+    if set.negated {
+        (format!("a character not in [{}]", members), format!("characters not in [{}]", members))
+    } else {
+        (format!("a character in [{}]", members), format!("characters in [{}]", members))
+    }
+}
+
+fn single_names(single: &Single) -> (String,String) {
+    match *single {
+        Single::Any => ("any character".to_string(), "characters".to_string()),
+        Single::Char(c) => (format!("literal {}", char_name(c)), char_name(c)),
+        Single::Class(class) => class_names(class),
+        Single::Set(ref set) => set_names(set),
+    }
+}
+
+fn write_items(items: &[Item], res: &mut Vec<u8>) {
+    for item in items {
+        match item.kind {
+            ItemKind::Single(ref single, q) => {
+                write_single(single, res);
+                res.extend(q.to_byte());
+            },
+            ItemKind::Capture(ref cap) => {
+                res.push(b'(');
+                write_items(&cap.items, res);
+                res.push(b')');
+            },
+            ItemKind::Position(_) => res.extend_from_slice(b"()"),
+            ItemKind::BackRef(l) => res.extend_from_slice(&[b'%', b'0' + l as u8]),
+            ItemKind::Balanced(b, e) => res.extend_from_slice(&[b'%', b'b', b, e]),
+            ItemKind::Frontier(ref set) => {
+                res.extend_from_slice(b"%f");
+                write_set(set, res);
+            }
+        }
+    }
+}
+
+fn write_single(single: &Single, res: &mut Vec<u8>) {
+    match *single {
+        Single::Any => res.push(b'.'),
+        Single::Char(c) => {
+            if MAGIC.contains(&c) {
+                res.push(b'%');
+            }
+            res.push(c);
+        },
+        Single::Class(class) => res.extend_from_slice(&[b'%', class.letter()]),
+        Single::Set(ref set) => write_set(set, res),
+    }
+}
+
+fn write_set(set: &Set, res: &mut Vec<u8>) {
+    res.push(b'[');
+    if set.negated {
+        res.push(b'^');
+    }
+    for item in &set.items {
+        match *item {
+            SetItem::Char(c) => {
+                if c == b']' || c == b'%' || c == b'-' || c == b'^' {
+                    res.push(b'%');
+                }
+                res.push(c);
+            },
+            SetItem::Range(lo, hi) => res.extend_from_slice(&[lo, b'-', hi]),
+            SetItem::Class(class) => res.extend_from_slice(&[b'%', class.letter()]),
+        }
+    }
+    res.push(b']');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn single(s: Single, q: Quantifier, span: ops::Range<usize>) -> Item {
+        Item{kind: ItemKind::Single(s, q), span}
+    }
+
+    #[test]
+    fn items() {
+        let patt = parse("a.%d-[^%s,a-z]?").unwrap();
+        assert!(! patt.anchored);
+        assert!(! patt.end_anchored);
+        assert_eq!(patt.items, vec![
+            single(Single::Char(b'a'), Quantifier::One, 0..1),
+            single(Single::Any, Quantifier::One, 1..2),
+            single(Single::Class(Class{kind: ClassKind::Digit, negated: false}), Quantifier::ZeroOrMoreLazy, 2..5),
+            single(Single::Set(Set{negated: true, items: vec![
+                SetItem::Class(Class{kind: ClassKind::Space, negated: false}),
+                SetItem::Char(b','),
+                SetItem::Range(b'a', b'z'),
+            ]}), Quantifier::ZeroOrOne, 5..15),
+        ]);
+    }
+
+    #[test]
+    fn captures() {
+        let patt = parse("((%a)())%2%1").unwrap();
+        assert_eq!(patt.num_captures, 3);
+        assert_eq!(patt.items.len(), 3);
+        match patt.items[0].kind {
+            ItemKind::Capture(ref cap) => {
+                assert_eq!(cap.index, 1);
+                assert_eq!(cap.items[1].kind, ItemKind::Position(3));
+            },
+            ref k => panic!("not a capture {:?}", k)
+        }
+        assert_eq!(patt.items[1].kind, ItemKind::BackRef(2));
+        assert_eq!(patt.items[2].span, 10..12);
+    }
+
+    #[test]
+    fn special_items() {
+        let patt = parse("^%b()%f[%w]%S%q$").unwrap();
+        assert!(patt.anchored && patt.end_anchored);
+        assert_eq!(patt.items[0].kind, ItemKind::Balanced(b'(', b')'));
+        assert_eq!(patt.items[1].kind, ItemKind::Frontier(Set{negated: false,
+            items: vec![SetItem::Class(Class{kind: ClassKind::Alnum, negated: false})]}));
+        assert_eq!(patt.items[2].kind, ItemKind::Single(
+            Single::Class(Class{kind: ClassKind::Space, negated: true}), Quantifier::One));
+        // unknown escapes are literals
+        assert_eq!(patt.items[3].kind, ItemKind::Single(Single::Char(b'q'), Quantifier::One));
+
+        // '^' and '$' are only anchors at the ends; quantifiers need something to follow
+        let patt = parse("a^$b*?").unwrap();
+        assert!(! patt.anchored && ! patt.end_anchored);
+        assert_eq!(patt.items[1].kind, ItemKind::Single(Single::Char(b'^'), Quantifier::One));
+        assert_eq!(patt.items[3].kind, ItemKind::Single(Single::Char(b'b'), Quantifier::ZeroOrMore));
+        assert_eq!(patt.items[4].kind, ItemKind::Single(Single::Char(b'?'), Quantifier::One));
+
+        // ']' first in a set is a member
+        let patt = parse("[]a-]").unwrap();
+        assert_eq!(patt.items[0].kind, ItemKind::Single(Single::Set(Set{negated: false,
+            items: vec![SetItem::Char(b']'), SetItem::Char(b'a'), SetItem::Char(b'-')]}), Quantifier::One));
+    }
+
+    #[test]
+    fn round_trip() {
+        for p in ["^(%a+)%s*=%s*(.-)$", "[^%]%-]+", "%b{}%f[%a]()%%", "a%.b%1", "%$%^"].iter() {
+            let patt = match parse(p) {
+                Ok(patt) => patt,
+                Err(e) => {
+                    // back reference without a capture
+                    assert_eq!(e, PatternError::new(InvalidCaptureIndex, "invalid capture index %1").at(4));
+                    continue;
+                }
+            };
+            assert_eq!(String::from_utf8(patt.to_bytes()).unwrap(), *p);
+        }
+    }
+
+    #[test]
+    fn matching_bytes() {
+        let digit = Class{kind: ClassKind::Digit, negated: false};
+        assert!(digit.matches(b'5'));
+        assert!(! Class{negated: true, ..digit}.matches(b'5'));
+        let patt = parse("[^%s,a-c]").unwrap();
+        if let ItemKind::Single(ref set, _) = patt.items[0].kind {
+            assert!(set.matches(b'd'));
+            assert!(! set.matches(b'b'));
+            assert!(! set.matches(b','));
+            assert!(! set.matches(0x0B));
+        }
+        assert!(Single::Any.matches(0));
+    }
+
+    #[test]
+    fn explain() {
+        let patt = parse("(%S+)%s*=%s*([^;]-)%b()%f[%u]()%1.?$").unwrap();
+        assert_eq!(patt.explain(), "\
+capture 1: one or more characters which are not space characters
+zero or more space characters
+literal '='
+zero or more space characters
+capture 2: as few as possible characters not in [';']
+a balanced string from '(' to ')'
+a frontier before characters in [upper-case letters]
+capture 3: the current position
+the same text as capture 1
+optionally any character
+end of text
+");
+        let patt = parse("(a[_%d])").unwrap();
+        assert_eq!(patt.explain(), "\
+capture 1:
+  literal 'a'
+  a character in ['_', digits]
+");
+    }
+
+    #[test]
+    fn errors() {
+        let bad = [
+            ("bonzo %", EndsWithEscape, "malformed pattern (ends with '%')", 6),
+            ("bonzo (dog%(", UnfinishedCapture, "unfinished capture", 6),
+            ("alles [%a%[", MalformedClass, "malformed pattern (missing ']')", 6),
+            ("bonzo (dog (cat)", UnfinishedCapture, "unfinished capture", 6),
+            ("frodo %f[%A", MalformedClass, "malformed pattern (missing ']')", 8),
+            ("frodo (1) (2(3)%2)%1", InvalidCaptureIndex, "invalid capture index %2", 15),
+            ("a)", NoOpenCapture, "no open capture", 1),
+            ("%bx", MissingBalanceArgs, "malformed pattern (missing arguments to '%b')", 0),
+            ("%fx", MissingFrontierSet, "missing '[' after '%f' in pattern", 0),
+            ("%0", InvalidCaptureIndex, "invalid capture index %0", 0),
+            ("^(a", UnfinishedCapture, "unfinished capture", 1),
+        ];
+        for &(p, kind, msg, pos) in bad.iter() {
+            let err = PatternError::new(kind, msg).at(pos);
+            assert_eq!(parse(p), Err(err.clone()), "{}", p);
+            // the same as when checking a pattern for matching
+            assert_eq!(::LuaPattern::new_try(p).err(), Some(err), "{}", p);
+        }
+    }
+}
@@ -0,0 +1,237 @@
+//! Ready-made patterns for everyday needs.
+//!
+//! These are plain strings, so they can be used as they are or combined
+//! into larger patterns. None of them are anchored, except `TRIMMED_LINE`.
+//! Lua patterns cannot express everything a full grammar could (there are
+//! no optional groups), so each constant documents what it accepts.
+//!
+//! ```
+//! use lua_patterns::LuaPattern;
+//! use lua_patterns::patterns;
+//!
+//! let mut m = LuaPattern::new(patterns::ISO_DATE);
+//! assert_eq!(m.match_maybe_3("due 2024-02-29!"), Some(("2024","02","29")));
+//! let assign = format!("({})%s*=%s*({})", patterns::IDENT, patterns::INTEGER);
+//! let mut m = LuaPattern::new(&assign);
+//! assert_eq!(m.match_maybe_2("let max_len = -10;"), Some(("max_len","-10")));
+//! ```
+
+use std::ops::Range;
+use {LuaAlternation, LuaPattern};
+
+/// An integer with an optional sign, like "42" or "-7"
+pub const INTEGER: &str = "[%+%-]?%d+";
+
+/// A decimal number with an optional sign and fraction, like "3.14", ".5"
+/// or "-2". There is no exponent, and a trailing point ("3.") is not included.
+pub const FLOAT: &str = "[%+%-]?%d*%.?%d+";
+
+/// A hexadecimal number with a `0x` or `0X` prefix, like "0xFF"
+pub const HEX: &str = "0[xX]%x+";
+
+/// An identifier as in C or Rust, like "_tmp1"
+pub const IDENT: &str = "[%a_][%w_]*";
+
+/// A string in double quotes, without escapes; the capture is the contents
+pub const DOUBLE_QUOTED: &str = "\"([^\"]*)\"";
+
+/// A string in single quotes, without escapes; the capture is the contents
+pub const SINGLE_QUOTED: &str = "'([^']*)'";
+
+/// A date like "2024-02-29", not part of a longer number, capturing the
+/// year, month and day. The ranges of the month and day are not checked.
+pub const ISO_DATE: &str = "%f[%d](%d%d%d%d)%-(%d%d)%-(%d%d)%f[%D]";
+
+/// A date and time like "2024-02-29T13:45:00" (or with a space instead
+/// of 'T'), capturing the year, month, day, hour, minute and second.
+/// Any fraction of a second or time zone which follows is not included.
+pub const ISO_DATETIME: &str = "%f[%d](%d%d%d%d)%-(%d%d)%-(%d%d)[T ](%d%d):(%d%d):(%d%d)";
+
+/// A whole line, capturing it without leading and trailing whitespace
+pub const TRIMMED_LINE: &str = "^%s*(.-)%s*$";
+
+/// A pattern for text between `open` and `close` delimiters, capturing the
+/// text in between, which may not contain `close`.
+/// The delimiters are escaped as needed.
+///
+/// ```
+/// let patt = lua_patterns::patterns::delimited('[', ']');
+/// assert_eq!(patt, "%[([^%]]*)%]");
+/// let mut m = lua_patterns::LuaPattern::new(&patt);
+/// assert_eq!(m.match_maybe("see [note 1]."), Some("note 1"));
+/// ```
+pub fn delimited(open: char, close: char) -> String {
+    format!("{}([^{}]*){}", escape(open), escape(close), escape(close))
+}
+
+// a character escaped for use in a pattern
+fn escape(c: char) -> String {
+    if c.is_ascii_punctuation() {format!("%{}", c)} else {c.to_string()}
+}
+
+/// Parse `key=value` pairs, where `sep` is the separator between key and value.
+///
+/// There may be whitespace around the separator. Keys are made of letters,
+/// digits, '_', '.' and '-'. Values may be in double or single quotes, and
+/// otherwise run until whitespace, ',' or ';'. Quotes are not included in
+/// the values. Anything which isn't a pair is skipped.
+///
+/// ```
+/// let pairs = lua_patterns::patterns::key_values(r#"x=1, name = "Joe Soap"; empty=; mode='fast'"#, '=');
+/// assert_eq!(pairs, &[("x","1"), ("name","Joe Soap"), ("empty",""), ("mode","fast")]);
+/// let pairs = lua_patterns::patterns::key_values("host: example.com port:80", ':');
+/// assert_eq!(pairs, &[("host","example.com"), ("port","80")]);
+/// ```
+pub fn key_values(text: &str, sep: char) -> Vec<(&str,&str)> {
+    let pair = format!("([%w_%.%-]+)%s*{}%s*", escape(sep));
+    let patt = format!("{0}\"([^\"]*)\"|{0}'([^']*)'|{0}([^%s,;]*)", pair);
+    let mut m = LuaAlternation::new(&patt);
+    let mut res = Vec::new();
+    let mut pos = 0;
+    while m.matches(&text[pos..]) {
+        let first = 2 * m.which() + 1;
+        let (key, value) = (m.capture(first), m.capture(first + 1));
+        res.push((&text[pos + key.start .. pos + key.end], &text[pos + value.start .. pos + value.end]));
+        pos += m.range().end;
+    }
+    res
+}
+
+/// A balanced group found by `match_balanced` or `balanced_groups`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Balanced<'t> {
+    /// the range of the group in the text, including the delimiters
+    pub range: Range<usize>,
+    /// the group, including the delimiters
+    pub outer: &'t str,
+    /// the contents of the group, without the delimiters
+    pub inner: &'t str,
+}
+
+fn balanced_pattern(open: char, close: char) -> [u8; 4] {
+    assert!(open.is_ascii() && close.is_ascii(), "delimiters must be ASCII");
+    assert!(open != close, "delimiters must be different");
+    [b'%', b'b', open as u8, close as u8]
+}
+
+fn find_balanced<'t>(patt: &[u8], text: &'t str, init: usize) -> Option<Balanced<'t>> {
+    let mut m = LuaPattern::from_bytes(patt);
+    if ! m.matches_bytes_at(text.as_bytes(), init, false) {
+        return None;
+    }
+    let range = m.range();
+    Some(Balanced {
+        outer: &text[range.clone()],
+        inner: &text[range.start + 1 .. range.end - 1],
+        range,
+    })
+}
+
+/// Find the first group between `open` and `close`, allowing nested groups,
+/// like the `%b` pattern item.
+///
+/// Panics if the delimiters are not ASCII, or are the same.
+///
+/// ```
+/// use lua_patterns::patterns::match_balanced;
+/// let b = match_balanced("f(a, g(b), c) + 1", '(', ')').unwrap();
+/// assert_eq!(b.range, 1..13);
+/// assert_eq!(b.inner, "a, g(b), c");
+/// assert_eq!(match_balanced("f(a, g(b)", '(', ')').unwrap().outer, "(b)");
+/// ```
+pub fn match_balanced(text: &str, open: char, close: char) -> Option<Balanced<'_>> {
+    find_balanced(&balanced_pattern(open, close), text, 0)
+}
+
+/// Iterator over the outermost balanced groups, from `balanced_groups`
+pub struct BalancedGroups<'t> {
+    patt: [u8; 4],
+    text: &'t str,
+    pos: usize,
+}
+
+impl <'t> Iterator for BalancedGroups<'t> {
+    type Item = Balanced<'t>;
+
+    fn next(&mut self) -> Option<Balanced<'t>> {
+        let res = find_balanced(&self.patt, self.text, self.pos)?;
+        self.pos = res.range.end;
+        Some(res)
+    }
+}
+
+/// Iterate over all the outermost groups between `open` and `close`,
+/// as for `match_balanced`.
+///
+/// ```
+/// use lua_patterns::patterns::balanced_groups;
+/// let inner: Vec<_> = balanced_groups("{a} x {b {c}} {", '{', '}').map(|b| b.inner).collect();
+/// assert_eq!(inner, &["a", "b {c}"]);
+/// ```
+pub fn balanced_groups(text: &str, open: char, close: char) -> BalancedGroups<'_> {
+    BalancedGroups{patt: balanced_pattern(open, close), text, pos: 0}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use LuaPattern;
+
+    fn all(patt: &str, text: &str) -> Vec<String> {
+        LuaPattern::new(patt).gmatch(text).map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(all(INTEGER, "a1 -22 +3, 4.5"), &["1","-22","+3","4","5"]);
+        assert_eq!(all(FLOAT, "3.14 .5 -2 x 3."), &["3.14",".5","-2","3"]);
+        assert_eq!(all(HEX, "0xFF 0X1a 0x 12"), &["0xFF","0X1a"]);
+    }
+
+    #[test]
+    fn words_and_strings() {
+        assert_eq!(all(IDENT, "x _tmp1 9lives"), &["x","_tmp1","lives"]);
+        assert_eq!(all(DOUBLE_QUOTED, r#"say "hi" and "" or 'no'"#), &["hi",""]);
+        assert_eq!(all(SINGLE_QUOTED, r#"say 'hi' and "no""#), &["hi"]);
+        assert_eq!(all(&delimited('<', '>'), "<a> <b c>"), &["a","b c"]);
+        assert_eq!(delimited('x', 'y'), "x([^y]*)y");
+    }
+
+    #[test]
+    fn pairs() {
+        let text = "a=1 b = 'two words' c=\"x=y\",d=4;e.f-g=-5 junk h=";
+        assert_eq!(key_values(text, '='), &[("a","1"),("b","two words"),("c","x=y"),("d","4"),("e.f-g","-5"),("h","")]);
+        assert_eq!(key_values("no pairs here", '='), Vec::<(&str,&str)>::new());
+        assert_eq!(key_values("k%v", '%'), &[("k","v")]);
+    }
+
+    #[test]
+    fn balanced() {
+        let b = match_balanced("x [[a] [b]] [c]", '[', ']').unwrap();
+        assert_eq!((b.range, b.outer, b.inner), (2..11, "[[a] [b]]", "[a] [b]"));
+        assert_eq!(match_balanced("x <>", '<', '>').unwrap().inner, "");
+        assert_eq!(match_balanced("no groups", '(', ')'), None);
+        let groups: Vec<_> = balanced_groups("(1)(2 (3)) 4 (", '(', ')').map(|b| b.range).collect();
+        assert_eq!(groups, &[0..3, 3..10]);
+    }
+
+    #[test]
+    #[should_panic(expected = "delimiters must be different")]
+    fn balanced_same_delimiters() {
+        match_balanced("'a'", '\'', '\'');
+    }
+
+    #[test]
+    fn dates_and_lines() {
+        let mut m = LuaPattern::new(ISO_DATE);
+        assert_eq!(m.captures("on 2017-11-10."), &["2017-11-10","2017","11","10"]);
+        assert!(! m.matches("12017-11-10"));
+        assert!(! m.matches("2017-11-100"));
+        let mut m = LuaPattern::new(ISO_DATETIME);
+        assert_eq!(m.captures("at 2024-02-29T13:45:00Z")[1..], ["2024","02","29","13","45","00"]);
+        assert!(m.matches("2024-02-29 13:45:00"));
+        let mut m = LuaPattern::new(TRIMMED_LINE);
+        assert_eq!(m.match_maybe("  hello  world \t"), Some("hello  world"));
+        assert_eq!(m.match_maybe("   "), Some(""));
+    }
+}
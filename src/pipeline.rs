@@ -0,0 +1,232 @@
+//! Applying a list of substitutions in turn.
+//!
+//! Cleaning up text often means a chain of `gsub` calls, each working on
+//! the result of the last. `GsubPipeline` keeps the patterns and replacements
+//! together, checks them all up front, and counts the substitutions made.
+//!
+//! ```
+//! use lua_patterns::GsubPipeline;
+//! let tidy = GsubPipeline::new()
+//!     .pass("%s+", " ")
+//!     .pass("^ ", "")
+//!     .pass(" $", "")
+//!     .pass("(%a+)=(%d+)", "%1: %2");
+//! let (res, n) = tidy.apply("  a=1 \t b=2\n");
+//! assert_eq!(res, "a: 1 b: 2");
+//! assert_eq!(n, 7);
+//! ```
+//!
+//! A `ReplaceTable` instead makes a single pass over the text, like a lexer,
+//! so replacements are never matched again by later rules.
+
+use errors::*;
+use found::Captures;
+use {LuaPatternOwned, Subst, expand_subst, generate_gsub_patterns};
+
+/// An ordered list of (pattern, replacement) passes
+#[derive(Clone,Debug,Default)]
+pub struct GsubPipeline {
+    passes: Vec<(LuaPatternOwned,String)>,
+}
+
+impl GsubPipeline {
+    /// An empty pipeline, which leaves text as it is
+    pub fn new() -> GsubPipeline {
+        GsubPipeline::default()
+    }
+
+    /// Add a pass, replacing all matches of `patt` with `repl` as for `gsub`,
+    /// or report a bad pattern or replacement
+    pub fn try_pass(mut self, patt: &str, repl: &str) -> Result<GsubPipeline,PatternError> {
+        let owned = LuaPatternOwned::new(patt)?;
+        owned.pattern().check_replacement(repl)?;
+        self.passes.push((owned, repl.to_string()));
+        Ok(self)
+    }
+
+    /// Add a pass, panicking if the pattern or replacement is bad
+    pub fn pass(self, patt: &str, repl: &str) -> GsubPipeline {
+        self.try_pass(patt, repl).expect("bad pattern")
+    }
+
+    /// Number of passes
+    pub fn len(&self) -> usize {
+        self.passes.len()
+    }
+
+    /// Are there no passes?
+    pub fn is_empty(&self) -> bool {
+        self.passes.is_empty()
+    }
+
+    /// Apply each pass in turn to the result of the one before, giving
+    /// the final text and the total number of substitutions.
+    pub fn apply(&self, text: &str) -> (String,usize) {
+        let mut text = text.to_string();
+        let mut count = 0;
+        for (owned, repl) in &self.passes {
+            let mut res = String::with_capacity(text.len());
+            // writing to a String cannot fail
+            count += owned.pattern().gsub_to(&text, repl, &mut res).unwrap();
+            text = res;
+        }
+        (text, count)
+    }
+}
+
+/// Rules which are all applied in one pass over the text
+///
+/// At each point the rule whose match starts first wins; if several start
+/// at the same place, the longest match wins, and then the rule added first.
+/// The text after a match is searched next, so text produced by a replacement
+/// is never replaced again.
+///
+/// ```
+/// use lua_patterns::pipeline::ReplaceTable;
+/// let swap = ReplaceTable::new()
+///     .rule("cat", "dog")
+///     .rule("dog", "cat")
+///     .rule("%d+", "<%0>")
+///     .rule("%d+%.%d+", "[%0]");
+/// let (res, n) = swap.apply("1 cat, 2.5 dogs");
+/// assert_eq!(res, "<1> dog, [2.5] cats");
+/// assert_eq!(n, 4);
+/// ```
+#[derive(Clone,Debug,Default)]
+pub struct ReplaceTable {
+    rules: Vec<(LuaPatternOwned,Vec<Subst>)>,
+}
+
+impl ReplaceTable {
+    /// An empty table, which leaves text as it is
+    pub fn new() -> ReplaceTable {
+        ReplaceTable::default()
+    }
+
+    /// Add a rule replacing matches of `patt` with `repl` as for `gsub`,
+    /// or report a bad pattern or replacement
+    pub fn try_rule(mut self, patt: &str, repl: &str) -> Result<ReplaceTable,PatternError> {
+        let owned = LuaPatternOwned::new(patt)?;
+        owned.pattern().check_replacement(repl)?;
+        self.rules.push((owned, generate_gsub_patterns(repl)));
+        Ok(self)
+    }
+
+    /// Add a rule, panicking if the pattern or replacement is bad
+    pub fn rule(self, patt: &str, repl: &str) -> ReplaceTable {
+        self.try_rule(patt, repl).expect("bad pattern")
+    }
+
+    /// Number of rules
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Are there no rules?
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Replace matches of all the rules in one pass, giving the new text
+    /// and the number of substitutions.
+    pub fn apply(&self, text: &str) -> (String,usize) {
+        let patterns: Vec<_> = self.rules.iter().map(|r| r.0.pattern()).collect();
+        // the next match of each rule, if it has been looked for
+        let mut next: Vec<Option<Option<Captures>>> = vec![None; patterns.len()];
+        let mut res = String::with_capacity(text.len());
+        let mut count = 0;
+        let mut pos = 0;
+        let mut last_end = None;
+        while pos <= text.len() {
+            let mut best: Option<(usize,usize,usize)> = None;
+            for (i, m) in patterns.iter().enumerate() {
+                let stale = match next[i] {
+                    Some(Some(ref cc)) => cc.get_match().start() < pos,
+                    Some(None) => false,
+                    None => true
+                };
+                if stale {
+                    // an anchored rule can only match at the start
+                    let anchored = m.patt.first() == Some(&b'^');
+                    next[i] = Some(if anchored && pos > 0 {None} else {m.find_captures_at(text, pos)});
+                }
+                if let Some(Some(ref cc)) = next[i] {
+                    let mat = cc.get_match();
+                    let better = match best {
+                        Some((start, end, _)) => mat.start() < start || (mat.start() == start && mat.end() > end),
+                        None => true
+                    };
+                    if better {
+                        best = Some((mat.start(), mat.end(), i));
+                    }
+                }
+            }
+            let (start, end, i) = match best {
+                Some(best) => best,
+                None => break
+            };
+            res.push_str(&text[pos..start]);
+            // as in Lua 5.4, no empty match straight after the previous match
+            if start == end && last_end == Some(end) {
+                let step = text[start..].chars().next().map_or(1, |c| c.len_utf8());
+                res.push_str(&text[start..(start + step).min(text.len())]);
+                pos = start + step;
+                continue;
+            }
+            if let Some(Some(ref cc)) = next[i] {
+                let capture = |c| cc.get(c).map(|m| m.range());
+                expand_subst(&self.rules[i].1, text, capture, &mut res).expect("writing to a String");
+            }
+            count += 1;
+            last_end = Some(end);
+            if start == end {
+                let step = text[end..].chars().next().map_or(1, |c| c.len_utf8());
+                res.push_str(&text[end..(end + step).min(text.len())]);
+                pos = end + step;
+            } else {
+                pos = end;
+            }
+        }
+        if pos < text.len() {
+            res.push_str(&text[pos..]);
+        }
+        (res, count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passes() {
+        let empty = GsubPipeline::new();
+        assert!(empty.is_empty());
+        assert_eq!(empty.apply("as is"), ("as is".to_string(), 0));
+        // later passes see the results of earlier ones
+        let p = GsubPipeline::new().pass("a", "b").pass("b", "c");
+        assert_eq!(p.len(), 2);
+        assert_eq!(p.apply("ab"), ("cc".to_string(), 3));
+        assert!(GsubPipeline::new().try_pass("(%a", "").is_err());
+        let err = GsubPipeline::new().try_pass("(%a)", "%2").unwrap_err();
+        assert_eq!(err.kind(), PatternErrorKind::InvalidReplacement);
+    }
+
+    #[test]
+    fn one_pass() {
+        let p = ReplaceTable::new().rule("a", "b").rule("b", "c");
+        assert_eq!(p.apply("ab"), ("bc".to_string(), 2));
+        assert_eq!(ReplaceTable::new().apply("as is"), ("as is".to_string(), 0));
+        // empty matches and anchors behave as for gsub
+        for &(patt, text) in &[("%a*", "ab, cd"), ("^%s*", "  x"), ("x*", "éxé"), ("$", "end")] {
+            let table = ReplaceTable::new().rule(patt, "<%0>");
+            let mut m = ::LuaPattern::new(patt);
+            let mut n = 0;
+            let expected = m.gsub_with(text, |cc| {n += 1; format!("<{}>", cc.get(0))});
+            assert_eq!(table.apply(text), (expected, n), "{}", patt);
+        }
+        let t = ReplaceTable::new().rule("%a+", "w").rule("%a+%d", "wd").rule("%d", "d");
+        assert_eq!(t.len(), 3);
+        assert_eq!(t.apply("ab1 cd 2"), ("wd w d".to_string(), 3));
+    }
+}
@@ -0,0 +1,156 @@
+//! Lua's own `string.match`, `string.gmatch` and `string.gsub`, for checking
+//! this crate's results against real Lua.
+//!
+//! Each thread has its own embedded Lua 5.4, created on first use. Texts,
+//! patterns and replacements are bytes, since Lua strings need not be UTF-8.
+//! Errors, such as bad patterns, are Lua's own.
+//!
+//! ```
+//! use lua_patterns::reference::{self, CaptureValue};
+//! use lua_patterns::LuaPattern;
+//!
+//! let expected = reference::string_match(b"key=10", b"(%a+)=()").unwrap();
+//! assert_eq!(expected, Some(vec![CaptureValue::Text(b"key".to_vec()), CaptureValue::Position(5)]));
+//!
+//! let mut m = LuaPattern::new("(%a+)=(%d+)");
+//! let (res, n) = reference::gsub(b"a=1 b=2", b"(%a+)=(%d+)", b"%2=%1").unwrap();
+//! assert_eq!(m.gsub_bytes(b"a=1 b=2", b"%2=%1"), res);
+//! assert_eq!(n, 2);
+//! ```
+
+use mlua::{self, Lua, Value};
+
+thread_local! {
+    static LUA: Lua = Lua::new();
+}
+
+/// A capture returned by Lua
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum CaptureValue {
+    /// The captured text
+    Text(Vec<u8>),
+    /// A position capture, counting from one as in Lua
+    Position(usize),
+}
+
+fn capture_value(value: Value) -> mlua::Result<CaptureValue> {
+    match value {
+        Value::String(s) => Ok(CaptureValue::Text(s.as_bytes().to_vec())),
+        Value::Integer(i) => Ok(CaptureValue::Position(i as usize)),
+        other => Err(mlua::Error::RuntimeError(format!("unexpected capture {:?}", other)))
+    }
+}
+
+// the captures of one match, packed into a table
+fn captures(t: mlua::Table) -> mlua::Result<Vec<CaptureValue>> {
+    let n: usize = t.get("n")?;
+    (1..=n).map(|i| capture_value(t.get(i)?)).collect()
+}
+
+/// `string.match(text, patt)`: the captures, or the whole match if there are none
+pub fn string_match(text: &[u8], patt: &[u8]) -> mlua::Result<Option<Vec<CaptureValue>>> {
+    LUA.with(|lua| {
+        let f: mlua::Function = lua.load("return table.pack(string.match(...))").into_function()?;
+        let t: mlua::Table = f.call((lua.create_string(text)?, lua.create_string(patt)?))?;
+        if let Value::Nil = t.get(1)? {
+            return Ok(None);
+        }
+        captures(t).map(Some)
+    })
+}
+
+/// `string.gmatch(text, patt)`: the captures of each match in turn
+pub fn gmatch(text: &[u8], patt: &[u8]) -> mlua::Result<Vec<Vec<CaptureValue>>> {
+    LUA.with(|lua| {
+        let f: mlua::Function = lua.load("
+            local res = {}
+            for t in function(iter) return table.pack(iter()) end, string.gmatch(...) do
+                if t[1] == nil then break end
+                res[#res + 1] = t
+            end
+            return res
+        ").into_function()?;
+        let all: mlua::Table = f.call((lua.create_string(text)?, lua.create_string(patt)?))?;
+        all.sequence_values::<mlua::Table>().map(|t| captures(t?)).collect()
+    })
+}
+
+/// `string.gsub(text, patt, repl)`: the new text and the number of substitutions
+pub fn gsub(text: &[u8], patt: &[u8], repl: &[u8]) -> mlua::Result<(Vec<u8>,usize)> {
+    LUA.with(|lua| {
+        let f: mlua::Function = lua.load("return string.gsub(...)").into_function()?;
+        let args = (lua.create_string(text)?, lua.create_string(patt)?, lua.create_string(repl)?);
+        let (res, n): (mlua::String, usize) = f.call(args)?;
+        Ok((res.as_bytes().to_vec(), n))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parse;
+    use LuaPattern;
+
+    const CASES: &[(&[u8],&[u8])] = &[
+        (b"(%a+)=(%d+)", b"x a=1 b=2"),
+        (b"%d+", b"abc 123 45"),
+        (b"()(%a+)()", b"  word  more"),
+        (b"%f[%w]%w+", b"THE (quick) fox"),
+        (b"%b()", b"f(a(b)c) g()"),
+        (b"(h)(.-)%1", b"hah hoh"),
+        (b"x*", b"axxb"),
+        (b"[%]%-]+", b"a]-]b"),
+        (b"\xFE+(.)", b"\x00\xFE\xFE\xFF"),
+        (b"%s*$", b"tail   "),
+    ];
+
+    // our captures in the same form as Lua's
+    fn our_captures(m: &LuaPattern, bytes: &[u8], positions: &[usize]) -> Vec<CaptureValue> {
+        let n = m.num_captures();
+        let range = if n == 0 {0..1} else {1..n + 1};
+        range.map(|i| {
+            let r = m.capture(i);
+            if positions.contains(&i) {
+                CaptureValue::Position(r.start + 1)
+            } else {
+                CaptureValue::Text(bytes[r].to_vec())
+            }
+        }).collect()
+    }
+
+    fn positions(patt: &[u8]) -> Vec<usize> {
+        let mut res = Vec::new();
+        ::lua::position_captures(&parse::parse(patt).unwrap().items, &mut res);
+        res
+    }
+
+    #[test]
+    fn same_as_lua() {
+        for &(patt, text) in CASES {
+            let mut m = LuaPattern::from_bytes(patt);
+            let positions = positions(patt);
+
+            let ours = if m.matches_bytes(text) {Some(our_captures(&m, text, &positions))} else {None};
+            assert_eq!(ours, string_match(text, patt).unwrap(), "match {:?}", patt);
+
+            let mut ours = Vec::new();
+            let mut iter = m.gmatch_bytes(text);
+            while iter.next().is_some() {
+                ours.push(our_captures(iter.m, text, &positions));
+            }
+            assert_eq!(ours, gmatch(text, patt).unwrap(), "gmatch {:?}", patt);
+
+            let mut m = LuaPattern::from_bytes(patt);
+            let mut out = Vec::new();
+            let n = m.gsub_bytes_to(text, b"<%0>", &mut out).unwrap();
+            assert_eq!((out, n), gsub(text, patt, b"<%0>").unwrap(), "gsub {:?}", patt);
+        }
+    }
+
+    #[test]
+    fn lua_errors() {
+        let err = string_match(b"abc", b"(%a").unwrap_err();
+        assert!(err.to_string().contains("unfinished capture"), "{}", err);
+        assert!(gsub(b"abc", b"(%a)", b"%2").is_err());
+    }
+}
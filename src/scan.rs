@@ -0,0 +1,119 @@
+// Fast scanning for the first byte which belongs to a set.
+// With the `simd` feature, x86-64 processors with SSSE3 check sixteen
+// bytes at a time, using the 'truffle' shuffle technique from Hyperscan.
+
+#[derive(Clone)]
+pub struct ByteSet {
+    bits: [u8; 32],
+    // for each low nibble, bit n is set if the byte with that low nibble
+    // and high nibble n (n + 8 for `high_set`) is in the set
+    high_clear: [u8; 16],
+    high_set: [u8; 16],
+}
+
+impl ByteSet {
+    pub fn new<F: Fn(u8) -> bool>(contains: F) -> ByteSet {
+        let mut set = ByteSet{bits: [0; 32], high_clear: [0; 16], high_set: [0; 16]};
+        for c in 0..=255u8 {
+            if contains(c) {
+                set.bits[(c >> 3) as usize] |= 1 << (c & 7);
+                let bit = 1 << ((c >> 4) & 7);
+                if c < 0x80 {
+                    set.high_clear[(c & 0x0F) as usize] |= bit;
+                } else {
+                    set.high_set[(c & 0x0F) as usize] |= bit;
+                }
+            }
+        }
+        set
+    }
+
+    pub fn contains(&self, c: u8) -> bool {
+        self.bits[(c >> 3) as usize] & (1 << (c & 7)) != 0
+    }
+
+    /// position of the first byte of `s` in the set
+    pub fn find(&self, s: &[u8]) -> Option<usize> {
+        #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+        {
+            if is_x86_feature_detected!("ssse3") {
+                return unsafe { self.find_ssse3(s) };
+            }
+        }
+        self.find_scalar(s)
+    }
+
+    fn find_scalar(&self, s: &[u8]) -> Option<usize> {
+        s.iter().position(|&c| self.contains(c))
+    }
+
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    #[target_feature(enable = "ssse3")]
+    unsafe fn find_ssse3(&self, s: &[u8]) -> Option<usize> {
+        use std::arch::x86_64::*;
+        let high_clear = _mm_loadu_si128(self.high_clear.as_ptr() as *const __m128i);
+        let high_set = _mm_loadu_si128(self.high_set.as_ptr() as *const __m128i);
+        let bit_for_nibble = _mm_setr_epi8(1, 2, 4, 8, 16, 32, 64, -128, 1, 2, 4, 8, 16, 32, 64, -128);
+        let low_nibble = _mm_set1_epi8(0x0F);
+        let high_bit = _mm_set1_epi8(-128);
+        let zero = _mm_setzero_si128();
+        let mut i = 0;
+        while i + 16 <= s.len() {
+            let v = _mm_loadu_si128(s.as_ptr().add(i) as *const __m128i);
+            // the shuffle gives zero for lanes with the high bit set,
+            // so each table only answers for its own half of the bytes
+            let rows = _mm_or_si128(
+                _mm_shuffle_epi8(high_clear, v),
+                _mm_shuffle_epi8(high_set, _mm_xor_si128(v, high_bit))
+            );
+            let high = _mm_and_si128(_mm_srli_epi16(v, 4), low_nibble);
+            let hits = _mm_and_si128(rows, _mm_shuffle_epi8(bit_for_nibble, high));
+            let mask = !_mm_movemask_epi8(_mm_cmpeq_epi8(hits, zero)) & 0xFFFF;
+            if mask != 0 {
+                return Some(i + mask.trailing_zeros() as usize);
+            }
+            i += 16;
+        }
+        self.find_scalar(&s[i..]).map(|k| i + k)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_matches_scalar() {
+        let sets: Vec<ByteSet> = vec![
+            ByteSet::new(|c| c.is_ascii_digit()),
+            ByteSet::new(|c| c.is_ascii_alphabetic() || c == b'_'),
+            ByteSet::new(|c| c >= 0x80),
+            ByteSet::new(|c| c == 0 || c == 0xFF || c == 0x7F),
+            ByteSet::new(|_| false),
+        ];
+        let len: u32 = if cfg!(miri) {100} else {1000};
+        let text: Vec<u8> = (0..len).map(|i| (i * 7919 % 251) as u8).collect();
+        for set in &sets {
+            for start in 0..40 {
+                let s = &text[start..];
+                assert_eq!(set.find(s), set.find_scalar(s));
+                for len in 0..40 {
+                    let s = &s[..len];
+                    assert_eq!(set.find(s), set.find_scalar(s));
+                }
+            }
+        }
+        let digits = &sets[0];
+        let high = &sets[2];
+        for pos in 0..64 {
+            let mut buf = vec![b'x'; 64];
+            buf[pos] = b'5';
+            assert_eq!(digits.find(&buf), Some(pos));
+            buf[pos] = 0xC3;
+            assert_eq!(high.find(&buf), Some(pos));
+            assert_eq!(digits.find(&buf), None);
+        }
+        assert_eq!(digits.find(b"abcdefghijklmnopqrstuvwxyz123"), Some(26));
+        assert!(digits.contains(b'0') && ! digits.contains(b'a'));
+    }
+}
@@ -0,0 +1,455 @@
+//! Searching the lines of a file or other reader, searching streams of
+//! byte chunks, and rewriting files.
+//!
+//! ```
+//! use lua_patterns::LuaPattern;
+//! use lua_patterns::search::search_lines;
+//!
+//! let log = "INFO start\nERROR disk full\nINFO stop\nERROR no route\n";
+//! let mut m = LuaPattern::new("^ERROR (.+)");
+//! let errors: Vec<_> = search_lines(log.as_bytes(), &mut m)
+//!     .map(|res| res.unwrap())
+//!     .map(|(line_no, _, captures)| (line_no, captures[1].clone()))
+//!     .collect();
+//! assert_eq!(errors, &[(2,"disk full".to_string()), (4,"no route".to_string())]);
+//! ```
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::ops::Range;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use LuaPattern;
+
+/// Iterator over matching lines, created by `search_lines`
+// lifetimes as for Captures: 'a is pattern, 'c is ref to LuaPattern
+pub struct SearchLines<'a,'c,R> where 'a: 'c {
+    m: &'c mut LuaPattern<'a>,
+    reader: BufReader<R>,
+    buf: String,
+    line_no: usize,
+}
+
+/// Search lines from a reader, yielding `(line_no, line, captures)` for
+/// each line which matches.
+///
+/// Line numbers start at one, and lines do not include the line ending.
+/// The captures are as for `LuaPattern::captures`, so the first is the whole
+/// match. The reader is buffered internally. As with `BufRead::lines`, an
+/// error is returned for text which is not UTF-8.
+pub fn search_lines<'a,'c,R: Read>(reader: R, m: &'c mut LuaPattern<'a>) -> SearchLines<'a,'c,R> {
+    SearchLines {
+        m,
+        reader: BufReader::new(reader),
+        buf: String::new(),
+        line_no: 0,
+    }
+}
+
+impl <'a,'c,R: Read> Iterator for SearchLines<'a,'c,R> {
+    type Item = io::Result<(usize,String,Vec<String>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Err(e) = read_line(&mut self.reader, &mut self.buf)? {
+                return Some(Err(e));
+            }
+            self.line_no += 1;
+            let captures = self.m.captures(&self.buf);
+            if ! captures.is_empty() {
+                let captures = captures.into_iter().map(|s| s.to_string()).collect();
+                return Some(Ok((self.line_no, self.buf.clone(), captures)));
+            }
+        }
+    }
+}
+
+// read the next line into `buf` without its line ending, or None at the end
+fn read_line<R: BufRead>(reader: &mut R, buf: &mut String) -> Option<io::Result<()>> {
+    buf.clear();
+    match reader.read_line(buf) {
+        Ok(0) => return None,
+        Ok(_) => {},
+        Err(e) => return Some(Err(e))
+    }
+    if buf.ends_with('\n') {
+        buf.pop();
+        if buf.ends_with('\r') {
+            buf.pop();
+        }
+    }
+    Some(Ok(()))
+}
+
+/// A line which matched, from `MatchingLines`
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct LineMatch {
+    /// Line number, starting at one
+    pub line_no: usize,
+    /// The line, without its line ending
+    pub line: String,
+    // the whole match followed by the captures
+    ranges: Vec<Range<usize>>,
+}
+
+impl LineMatch {
+    /// The nth capture, where 0 is the whole match
+    pub fn get(&self, i: usize) -> Option<&str> {
+        self.ranges.get(i).map(|r| &self.line[r.clone()])
+    }
+
+    /// Range of the nth capture in the line
+    pub fn range(&self, i: usize) -> Option<Range<usize>> {
+        self.ranges.get(i).cloned()
+    }
+
+    /// Number of captures, including the whole match
+    pub fn num_matches(&self) -> usize {
+        self.ranges.len()
+    }
+}
+
+/// Iterator over the lines of a `BufRead` which match a pattern
+///
+/// Lines are read into a buffer which is used again for the next line,
+/// so only matching lines are copied.
+///
+/// ```
+/// use lua_patterns::LuaPattern;
+/// use lua_patterns::search::MatchingLines;
+///
+/// let log = "INFO start\nERROR disk full\nINFO stop\n";
+/// let m = LuaPattern::new("^(%u+) (.+)");
+/// for res in MatchingLines::new(log.as_bytes(), &m) {
+///     let lm = res.unwrap();
+///     if lm.get(1) == Some("ERROR") {
+///         assert_eq!((lm.line_no, lm.get(2).unwrap()), (2, "disk full"));
+///         assert_eq!(lm.range(2), Some(6..15));
+///     }
+/// }
+/// ```
+// lifetimes as for SearchLines
+pub struct MatchingLines<'a,'c,R> where 'a: 'c {
+    m: &'c LuaPattern<'a>,
+    reader: R,
+    buf: String,
+    line_no: usize,
+}
+
+impl <'a,'c,R: BufRead> MatchingLines<'a,'c,R> {
+    /// Match the lines of `reader`, which is not buffered again
+    pub fn new(reader: R, m: &'c LuaPattern<'a>) -> MatchingLines<'a,'c,R> {
+        MatchingLines{m, reader, buf: String::new(), line_no: 0}
+    }
+}
+
+impl <'a,'c,R: BufRead> Iterator for MatchingLines<'a,'c,R> {
+    type Item = io::Result<LineMatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Err(e) = read_line(&mut self.reader, &mut self.buf)? {
+                return Some(Err(e));
+            }
+            self.line_no += 1;
+            if let Some(cc) = self.m.find_captures(&self.buf) {
+                let ranges = cc.iter().map(|c| c.range()).collect();
+                return Some(Ok(LineMatch{line_no: self.line_no, line: self.buf.clone(), ranges}));
+            }
+        }
+    }
+}
+
+/// A match found by `ChunkedSearcher`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkMatch {
+    /// the range of the match in the whole stream
+    pub range: Range<usize>,
+    /// the whole match followed by any captures
+    pub captures: Vec<Vec<u8>>,
+}
+
+/// Searches a stream of byte chunks, such as network reads or windows of a
+/// memory-mapped file, reporting matches with offsets in the whole stream.
+///
+/// The searcher keeps `overlap` bytes at the end of what it has seen, so that
+/// matches spanning chunk boundaries are found, and a match is only reported
+/// once at least `overlap` bytes follow it. So matches (and the text the
+/// pattern looks at to find them) should be no longer than the overlap.
+/// Matches follow the rules of `gmatch_bytes`, and `^` anchors a pattern to
+/// the start of the stream.
+///
+/// ```
+/// use lua_patterns::LuaPattern;
+/// use lua_patterns::search::ChunkedSearcher;
+///
+/// let mut s = ChunkedSearcher::new(LuaPattern::new("id=(%d+);"), 16);
+/// let mut found = s.push(b"xx id=1");
+/// found.extend(s.push(b"23; id=4"));
+/// found.extend(s.push(b"5; yy"));
+/// found.extend(s.finish());
+/// let ranges: Vec<_> = found.iter().map(|m| m.range.clone()).collect();
+/// assert_eq!(ranges, &[3..10, 11..17]);
+/// assert_eq!(found[0].captures[1], b"123");
+/// ```
+pub struct ChunkedSearcher<'a> {
+    m: LuaPattern<'a>,
+    overlap: usize,
+    buf: Vec<u8>,
+    // stream offset of the start of buf
+    offset: usize,
+    // where to search next in buf
+    pos: usize,
+    // stream offset of the end of the last match
+    last_end: Option<usize>,
+}
+
+impl <'a> ChunkedSearcher<'a> {
+    /// Create a searcher which keeps `overlap` bytes between chunks
+    pub fn new(m: LuaPattern<'a>, overlap: usize) -> ChunkedSearcher<'a> {
+        ChunkedSearcher{m, overlap, buf: Vec::new(), offset: 0, pos: 0, last_end: None}
+    }
+
+    /// Add the next chunk, returning the matches which are now complete
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<ChunkMatch> {
+        self.buf.extend_from_slice(chunk);
+        self.scan(false)
+    }
+
+    /// Finish the stream, returning the remaining matches
+    pub fn finish(mut self) -> Vec<ChunkMatch> {
+        self.scan(true)
+    }
+
+    fn scan(&mut self, last: bool) -> Vec<ChunkMatch> {
+        let mut res = Vec::new();
+        let len = self.buf.len();
+        let limit = if last {len} else {len.saturating_sub(self.overlap)};
+        let anchored = self.m.patt.first() == Some(&b'^');
+        while self.pos <= limit && ! (anchored && self.offset + self.pos > 0) {
+            if ! self.m.matches_bytes_at(&self.buf, self.pos, false) {
+                self.pos = limit;
+                break;
+            }
+            let r = self.m.range();
+            if r.end > limit {
+                // wait until enough follows the match
+                self.pos = r.start.min(limit);
+                break;
+            }
+            let empty = r.start == r.end;
+            self.pos = if empty {r.end + 1} else {r.end};
+            if empty && self.last_end == Some(self.offset + r.end) {
+                continue;
+            }
+            self.last_end = Some(self.offset + r.end);
+            let captures = (0..self.m.n_match).map(|i| self.buf[self.m.capture(i)].to_vec()).collect();
+            res.push(ChunkMatch{range: self.offset + r.start .. self.offset + r.end, captures});
+        }
+        // keep one byte before the search position, for %f
+        let drop = self.pos.saturating_sub(1).min(len);
+        self.buf.drain(..drop);
+        self.offset += drop;
+        self.pos -= drop;
+        res
+    }
+}
+
+/// Options for `LuaPattern::gsub_file`
+#[derive(Debug, Clone, Default)]
+pub struct GsubFileOptions {
+    /// only count the substitutions, leaving the file alone
+    pub dry_run: bool,
+}
+
+impl <'a> LuaPattern<'a> {
+    /// Globally substitute all matches in a file, returning the number
+    /// of substitutions.
+    ///
+    /// The replacement is as for `gsub_bytes`, and the file is treated as
+    /// bytes so it need not be UTF-8. The new contents are written to a
+    /// temporary file in the same directory, which is then renamed over the
+    /// original, so readers never see a partly-written file. The file is not
+    /// touched if nothing matches, or with the `dry_run` option. A bad
+    /// replacement, as found by `check_replacement`, is an `InvalidInput` error.
+    ///
+    /// ```no_run
+    /// use lua_patterns::LuaPattern;
+    /// use lua_patterns::search::GsubFileOptions;
+    ///
+    /// let mut m = LuaPattern::new("old_name%(");
+    /// let opts = GsubFileOptions{dry_run: true};
+    /// let n = m.gsub_file("src/main.rs", "new_name(", &opts).unwrap();
+    /// println!("would make {} changes", n);
+    /// ```
+    pub fn gsub_file<P: AsRef<Path>>(&mut self, path: P, repl: &str, opts: &GsubFileOptions) -> io::Result<usize> {
+        self.check_replacement(repl).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let path = path.as_ref();
+        let bytes = fs::read(path)?;
+        let mut out = Vec::with_capacity(bytes.len());
+        let count = self.gsub_bytes_to(&bytes, repl.as_bytes(), &mut out)?;
+        if opts.dry_run || count == 0 {
+            return Ok(count);
+        }
+        let (tmp, f) = create_temp(path)?;
+        let res = write_file(f, &tmp, &out, path).and_then(|_| fs::rename(&tmp, path));
+        if res.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        res.map(|_| count)
+    }
+}
+
+// a new hidden file next to `path`, so that renaming it is atomic.
+// The name has a counter as well as the process id, and is only used if
+// nobody else has it, so threads rewriting files at once do not collide
+fn create_temp(path: &Path) -> io::Result<(PathBuf, File)> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let name = path.file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?;
+    loop {
+        let mut tmp = OsString::from(".");
+        tmp.push(name);
+        tmp.push(format!(".{}.{}.tmp", process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)));
+        let tmp = path.with_file_name(tmp);
+        match OpenOptions::new().write(true).create_new(true).open(&tmp) {
+            Ok(f) => return Ok((tmp, f)),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e)
+        }
+    }
+}
+
+// write the new contents, keeping the permissions of the original
+fn write_file(mut f: File, tmp: &Path, contents: &[u8], original: &Path) -> io::Result<()> {
+    f.write_all(contents)?;
+    f.sync_all()?;
+    fs::set_permissions(tmp, fs::metadata(original)?.permissions())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_and_captures() {
+        let text = "a=1\r\n\nb = 2\nnothing\nc=3";
+        let mut m = LuaPattern::new("(%a)%s*=%s*(%d)");
+        let found: Vec<_> = search_lines(text.as_bytes(), &mut m).map(|r| r.unwrap()).collect();
+        assert_eq!(found, vec![
+            (1, "a=1".to_string(), vec!["a=1".to_string(), "a".to_string(), "1".to_string()]),
+            (3, "b = 2".to_string(), vec!["b = 2".to_string(), "b".to_string(), "2".to_string()]),
+            (5, "c=3".to_string(), vec!["c=3".to_string(), "c".to_string(), "3".to_string()]),
+        ]);
+    }
+
+    #[test]
+    fn matching_lines() {
+        let text = "a=1\r\n\nb = 2\nnothing\nc=3";
+        let m = LuaPattern::new("(%a)%s*=%s*(%d)");
+        let found: Vec<_> = MatchingLines::new(text.as_bytes(), &m)
+            .map(|r| r.unwrap())
+            .map(|lm| (lm.line_no, lm.get(0).unwrap().to_string(), lm.get(1).unwrap().to_string(), lm.num_matches()))
+            .collect();
+        assert_eq!(found, vec![
+            (1, "a=1".to_string(), "a".to_string(), 3),
+            (3, "b = 2".to_string(), "b".to_string(), 3),
+            (5, "c=3".to_string(), "c".to_string(), 3),
+        ]);
+        let mut iter = MatchingLines::new(&b"ok\n\xFF\n"[..], &m);
+        assert_eq!(iter.next().unwrap().unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn bad_utf8() {
+        let mut m = LuaPattern::new(".");
+        let mut iter = search_lines(&b"ok\n\xFF\n"[..], &mut m);
+        assert_eq!(iter.next().unwrap().unwrap().0, 1);
+        assert_eq!(iter.next().unwrap().unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn gsub_file() {
+        let dir = ::std::env::temp_dir().join(format!("lua-patterns-gsub-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.txt");
+        fs::write(&path, b"one two\n\xFFthree\n").unwrap();
+        let mut m = LuaPattern::new("(%a+)");
+        let dry = GsubFileOptions{dry_run: true};
+        assert_eq!(m.gsub_file(&path, "<%1>", &dry).unwrap(), 3);
+        assert_eq!(fs::read(&path).unwrap(), b"one two\n\xFFthree\n");
+        assert_eq!(m.gsub_file(&path, "<%1>", &GsubFileOptions::default()).unwrap(), 3);
+        assert_eq!(fs::read(&path).unwrap(), b"<one> <two>\n\xFF<three>\n");
+        // no temporary file left behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        assert_eq!(LuaPattern::new("%d").gsub_file(&path, "x", &GsubFileOptions::default()).unwrap(), 0);
+        assert!(LuaPattern::new("%d").gsub_file(dir.join("missing"), "x", &dry).is_err());
+        assert_eq!(m.gsub_file(&path, "%2", &dry).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn gsub_file_threads() {
+        let dir = ::std::env::temp_dir().join(format!("lua-patterns-threads-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("shared.txt");
+        fs::write(&path, "a b c").unwrap();
+        let threads: Vec<_> = (0..8).map(|_| {
+            let path = path.clone();
+            ::std::thread::spawn(move || {
+                let mut m = LuaPattern::new("%a");
+                for _ in 0..20 {
+                    m.gsub_file(&path, "%0", &GsubFileOptions::default()).unwrap();
+                }
+            })
+        }).collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(fs::read(&path).unwrap(), b"a b c");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn chunked() {
+        let text = b"one 12 three 345 six 7890 end";
+        let expected = vec![(4..6, b"12".to_vec()), (13..16, b"345".to_vec()), (21..25, b"7890".to_vec())];
+        // the same matches whatever the chunk size
+        for size in 1..text.len() + 1 {
+            let mut s = ChunkedSearcher::new(LuaPattern::new("%d+"), 8);
+            let mut found = Vec::new();
+            for chunk in text.chunks(size) {
+                found.extend(s.push(chunk));
+            }
+            found.extend(s.finish());
+            let found: Vec<_> = found.into_iter().map(|m| (m.range, m.captures[0].clone())).collect();
+            assert_eq!(found, expected, "chunk size {}", size);
+        }
+    }
+
+    #[test]
+    fn chunked_anchors_and_empty_matches() {
+        let mut s = ChunkedSearcher::new(LuaPattern::new("^ab"), 4);
+        let mut found = s.push(b"a");
+        found.extend(s.push(b"bab ab"));
+        found.extend(s.finish());
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].range, 0..2);
+        let mut s = ChunkedSearcher::new(LuaPattern::new("x*"), 2);
+        let mut found = s.push(b"ax");
+        found.extend(s.push(b"xb"));
+        found.extend(s.finish());
+        let ranges: Vec<_> = found.into_iter().map(|m| m.range).collect();
+        assert_eq!(ranges, &[0..0, 1..3, 4..4]);
+        // frontiers see the byte before a chunk boundary
+        let mut s = ChunkedSearcher::new(LuaPattern::new("%f[%a]%a+"), 8);
+        let mut found = s.push(b"xyz123abc");
+        found.extend(s.push(b"def ghi"));
+        found.extend(s.finish());
+        let ranges: Vec<_> = found.into_iter().map(|m| m.range).collect();
+        assert_eq!(ranges, &[0..3, 6..12, 13..16]);
+    }
+}
@@ -0,0 +1,137 @@
+//! Using `LuaPattern` with the standard string methods.
+//!
+//! This needs a nightly compiler and the `nightly` feature, since
+//! `std::str::pattern` is unstable. A mutable reference to a pattern can
+//! then be passed to `str::find`, `str::split`, `str::matches` and friends.
+//! Matches follow the rules of `gmatch`, except that matches which would
+//! split a UTF-8 character are skipped.
+//!
+//! ```ignore
+//! #![feature(pattern)]
+//! let mut m = lua_patterns::LuaPattern::new("%s*[,;]%s*");
+//! let items: Vec<_> = "one, two ;three".split(&mut m).collect();
+//! assert_eq!(items, &["one","two","three"]);
+//! ```
+
+use std::str::pattern::{Pattern, SearchStep, Searcher};
+use {Cursor, LuaPattern};
+
+/// Searcher for a `LuaPattern` in a string
+pub struct LuaSearcher<'a,'b,'c> where 'a: 'c {
+    m: &'c mut LuaPattern<'a>,
+    text: &'b str,
+    cursor: Cursor,
+    // end of the last step returned
+    pos: usize,
+    // a match found after a stretch of rejected text
+    pending: Option<(usize,usize)>,
+    done: bool,
+}
+
+impl <'a,'b,'c> LuaSearcher<'a,'b,'c> {
+    // the next match which starts and ends on char boundaries
+    fn find_match(&mut self) -> Option<(usize,usize)> {
+        while self.m.find_next(self.text.as_bytes(), &mut self.cursor) {
+            let r = self.m.range();
+            if self.text.is_char_boundary(r.start) && self.text.is_char_boundary(r.end) {
+                return Some((r.start, r.end));
+            }
+        }
+        None
+    }
+}
+
+unsafe impl <'a,'b,'c> Searcher<'b> for LuaSearcher<'a,'b,'c> {
+    fn haystack(&self) -> &'b str {
+        self.text
+    }
+
+    fn next(&mut self) -> SearchStep {
+        if let Some((start,end)) = self.pending.take() {
+            self.pos = end;
+            return SearchStep::Match(start,end);
+        }
+        if self.done {
+            return SearchStep::Done;
+        }
+        match self.find_match() {
+            Some((start,end)) if start > self.pos => {
+                self.pending = Some((start,end));
+                SearchStep::Reject(self.pos,start)
+            },
+            Some((start,end)) => {
+                self.pos = end;
+                SearchStep::Match(start,end)
+            },
+            None => {
+                self.done = true;
+                if self.pos < self.text.len() {
+                    SearchStep::Reject(self.pos,self.text.len())
+                } else {
+                    SearchStep::Done
+                }
+            }
+        }
+    }
+
+    fn next_match(&mut self) -> Option<(usize,usize)> {
+        if let Some(m) = self.pending.take() {
+            self.pos = m.1;
+            return Some(m);
+        }
+        if self.done {
+            return None;
+        }
+        let res = self.find_match();
+        match res {
+            Some((_,end)) => self.pos = end,
+            None => self.done = true
+        }
+        res
+    }
+}
+
+impl <'a,'c> Pattern for &'c mut LuaPattern<'a> {
+    type Searcher<'b> = LuaSearcher<'a,'b,'c>;
+
+    fn into_searcher(self, text: &str) -> LuaSearcher<'a,'_,'c> {
+        LuaSearcher {
+            m: self,
+            text,
+            cursor: Cursor::new(true),
+            pos: 0,
+            pending: None,
+            done: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use LuaPattern;
+
+    #[test]
+    fn str_methods() {
+        let mut m = LuaPattern::new("%d+");
+        assert_eq!("abc 123 x 45".find(&mut m), Some(4));
+        assert_eq!("abc".find(&mut m), None);
+        let nums: Vec<_> = "abc 123 x 45".matches(&mut m).collect();
+        assert_eq!(nums, &["123","45"]);
+        let mut m = LuaPattern::new("%s*,%s*");
+        let items: Vec<_> = "one , two,three".split(&mut m).collect();
+        assert_eq!(items, &["one","two","three"]);
+        assert_eq!("  hello".trim_start_matches(&mut LuaPattern::new("%s")), "hello");
+        assert_eq!("key=value".replace(&mut LuaPattern::new("%a+"), "_"), "_=_");
+    }
+
+    #[test]
+    fn char_boundaries() {
+        // '.' matches a single byte, so only whole ASCII characters are matched
+        let mut m = LuaPattern::new(".");
+        let found: Vec<_> = "aé!".match_indices(&mut m).collect();
+        assert_eq!(found, &[(0,"a"),(3,"!")]);
+        let mut m = LuaPattern::new("x*");
+        let found: Vec<_> = "axxb".match_indices(&mut m).collect();
+        assert_eq!(found, &[(0,""),(1,"xx"),(4,"")]);
+    }
+}
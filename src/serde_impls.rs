@@ -0,0 +1,77 @@
+// Serde support. Patterns are serialized as strings (or bytes, if they
+// are not UTF-8) and are validated when deserialized.
+
+use std::fmt;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use {LuaPattern, LuaPatternOwned};
+
+fn serialize_pattern<S: Serializer>(patt: &[u8], s: S) -> Result<S::Ok,S::Error> {
+    match ::std::str::from_utf8(patt) {
+        Ok(patt) => s.serialize_str(patt),
+        Err(_) => s.serialize_bytes(patt)
+    }
+}
+
+impl Serialize for LuaPatternOwned {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok,S::Error> {
+        serialize_pattern(self.as_bytes(), s)
+    }
+}
+
+impl <'a> Serialize for LuaPattern<'a> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok,S::Error> {
+        serialize_pattern(self.patt, s)
+    }
+}
+
+struct PatternVisitor;
+
+impl <'de> de::Visitor<'de> for PatternVisitor {
+    type Value = LuaPatternOwned;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a Lua pattern")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<LuaPatternOwned,E> {
+        self.visit_byte_buf(v.as_bytes().to_vec())
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<LuaPatternOwned,E> {
+        self.visit_byte_buf(v.to_vec())
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<LuaPatternOwned,E> {
+        let shown = String::from_utf8_lossy(&v).into_owned();
+        LuaPatternOwned::from_bytes(v)
+            .map_err(|e| E::custom(format!("invalid Lua pattern {:?}: {}", shown, e)))
+    }
+}
+
+impl <'de> Deserialize<'de> for LuaPatternOwned {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<LuaPatternOwned,D::Error> {
+        d.deserialize_string(PatternVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+    use LuaPatternOwned;
+
+    #[test]
+    fn round_trip() {
+        let patts: Vec<LuaPatternOwned> = serde_json::from_str(r#"["(%a+)=(%d+)", "^%s*$"]"#).unwrap();
+        assert_eq!(patts[0].as_str(), Some("(%a+)=(%d+)"));
+        assert!(patts[1].pattern().matches("  "));
+        assert_eq!(serde_json::to_string(&patts).unwrap(), r#"["(%a+)=(%d+)","^%s*$"]"#);
+        assert_eq!(serde_json::to_string(&patts[0].pattern()).unwrap(), r#""(%a+)=(%d+)""#);
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        let err = serde_json::from_str::<LuaPatternOwned>(r#""(%a+""#).err().unwrap();
+        assert!(err.to_string().starts_with(r#"invalid Lua pattern "(%a+": unfinished capture"#));
+        assert!(serde_json::from_str::<LuaPatternOwned>("42").is_err());
+    }
+}
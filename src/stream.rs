@@ -0,0 +1,153 @@
+//! Matching streaming input from a [tokio](https://docs.rs/tokio) `AsyncRead`.
+//!
+//! `AsyncGMatch` reads the input in pieces, using a `ChunkedSearcher` so that
+//! matches spanning reads are found, and yields each match as soon as it is
+//! complete. It never blocks; `next` returns a future.
+//!
+//! ```
+//! extern crate tokio;
+//! extern crate lua_patterns;
+//! use lua_patterns::LuaPattern;
+//! use lua_patterns::stream::AsyncGMatch;
+//!
+//! # fn main() {
+//! let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+//! let input: &[u8] = b"GET /index.html\nGET /about.html\n";
+//! let mut iter = AsyncGMatch::new(input, LuaPattern::new("GET (%S+)"), 256);
+//! let mut paths = Vec::new();
+//! while let Some(m) = rt.block_on(iter.next()) {
+//!     paths.push(m.unwrap().captures[1].clone());
+//! }
+//! assert_eq!(paths, &[b"/index.html", b"/about.html"]);
+//! # }
+//! ```
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+use search::{ChunkMatch, ChunkedSearcher};
+use LuaPattern;
+
+const READ_SIZE: usize = 8 * 1024;
+
+/// Finds matches in an `AsyncRead`, yielding them as they complete
+pub struct AsyncGMatch<'a,R> {
+    reader: R,
+    // None once the input is finished
+    searcher: Option<ChunkedSearcher<'a>>,
+    pending: VecDeque<ChunkMatch>,
+    buf: Vec<u8>,
+}
+
+impl <'a,R: AsyncRead + Unpin> AsyncGMatch<'a,R> {
+    /// Search `reader`, keeping `overlap` bytes between reads as for `ChunkedSearcher`
+    pub fn new(reader: R, m: LuaPattern<'a>, overlap: usize) -> AsyncGMatch<'a,R> {
+        AsyncGMatch {
+            reader,
+            searcher: Some(ChunkedSearcher::new(m, overlap)),
+            pending: VecDeque::new(),
+            buf: vec![0; READ_SIZE],
+        }
+    }
+
+    /// Poll for the next match; `None` means the input is finished
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<io::Result<ChunkMatch>>> {
+        loop {
+            if let Some(m) = self.pending.pop_front() {
+                return Poll::Ready(Some(Ok(m)));
+            }
+            if self.searcher.is_none() {
+                return Poll::Ready(None);
+            }
+            let mut buf = ReadBuf::new(&mut self.buf);
+            match Pin::new(&mut self.reader).poll_read(cx, &mut buf) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(Ok(())) => {}
+            }
+            let found = if buf.filled().is_empty() {
+                self.searcher.take().unwrap().finish()
+            } else {
+                self.searcher.as_mut().unwrap().push(buf.filled())
+            };
+            self.pending.extend(found);
+        }
+    }
+
+    /// The next match, or `None` when the input is finished
+    pub fn next<'s>(&'s mut self) -> Next<'s,'a,R> {
+        Next{inner: self}
+    }
+}
+
+/// Future returned by `AsyncGMatch::next`
+pub struct Next<'s,'a: 's,R: 's> {
+    inner: &'s mut AsyncGMatch<'a,R>,
+}
+
+impl <'s,'a,R: AsyncRead + Unpin> Future for Next<'s,'a,R> {
+    type Output = Option<io::Result<ChunkMatch>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.inner.poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::runtime::{Builder, Runtime};
+
+    // a reader which returns a few bytes at a time, and is sometimes not ready
+    struct Trickle {
+        data: &'static [u8],
+        ready: bool,
+    }
+
+    impl AsyncRead for Trickle {
+        fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+            self.ready = ! self.ready;
+            if ! self.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let n = self.data.len().min(3);
+            buf.put_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn runtime() -> Runtime {
+        Builder::new_current_thread().build().unwrap()
+    }
+
+    #[test]
+    fn matches_across_reads() {
+        let rt = runtime();
+        let reader = Trickle{data: b"a=10, bb=200, ccc=3000", ready: false};
+        let mut iter = AsyncGMatch::new(reader, LuaPattern::new("(%a+)=(%d+)"), 16);
+        let mut found = Vec::new();
+        while let Some(m) = rt.block_on(iter.next()) {
+            let m = m.unwrap();
+            found.push((m.range, m.captures[1].clone(), m.captures[2].clone()));
+        }
+        assert_eq!(found, vec![
+            (0..4, b"a".to_vec(), b"10".to_vec()),
+            (6..12, b"bb".to_vec(), b"200".to_vec()),
+            (14..22, b"ccc".to_vec(), b"3000".to_vec()),
+        ]);
+        // finished for good
+        assert!(rt.block_on(iter.next()).is_none());
+    }
+
+    #[test]
+    fn empty_input() {
+        let rt = runtime();
+        let mut iter = AsyncGMatch::new(&b""[..], LuaPattern::new("x"), 4);
+        assert!(rt.block_on(iter.next()).is_none());
+    }
+}
@@ -0,0 +1,122 @@
+//! Expanding `$name` and `${name}` placeholders in templates.
+//!
+//! A very common use of `gsub_with` is filling in a template from a map;
+//! `expand` does this, and `expand_with` takes a function instead of a map.
+//! Names are letters, digits and underscores, not starting with a digit.
+//! Use `${name}` when the name is followed by more letters, and `$$` for
+//! a literal `$`.
+//!
+//! ```
+//! use std::collections::HashMap;
+//! use lua_patterns::template::{expand, Missing};
+//!
+//! let mut vars = HashMap::new();
+//! vars.insert("user", "alice");
+//! vars.insert("n", "3");
+//! let res = expand("$user has ${n}0 messages, costing $$5", &vars, Missing::Error);
+//! assert_eq!(res.unwrap(), "alice has 30 messages, costing $5");
+//! let err = expand("hi $name", &vars, Missing::Error).unwrap_err();
+//! assert_eq!(err.to_string(), "no value for 'name'");
+//! assert_eq!(expand("hi $name", &vars, Missing::Keep).unwrap(), "hi $name");
+//! assert_eq!(expand("hi ${name}!", &vars, Missing::Empty).unwrap(), "hi !");
+//! ```
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use LuaPattern;
+
+/// What to do with a placeholder which has no value
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Missing {
+    /// Fail with `MissingKey`
+    Error,
+    /// Leave the placeholder as it is
+    Keep,
+    /// Replace the placeholder with nothing
+    Empty,
+}
+
+/// The error for a placeholder with no value
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct MissingKey(pub String);
+
+impl fmt::Display for MissingKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,"no value for '{}'",self.0)
+    }
+}
+
+impl error::Error for MissingKey {}
+
+// "$$", "$name" or "${name}"; anything else after '$' is left alone
+const PLACEHOLDER: &str = "%$([%${]?)([%w_]*)(}?)";
+
+/// Expand the placeholders in `template` with values from `map`
+pub fn expand<K,V,H>(template: &str, map: &HashMap<K,V,H>, missing: Missing) -> Result<String,MissingKey>
+where K: Borrow<str> + Hash + Eq, V: AsRef<str>, H: BuildHasher {
+    expand_with(template, missing, |name| map.get(name).map(|v| v.as_ref().to_string()))
+}
+
+/// Expand the placeholders in `template` with values given by `lookup`
+///
+/// ```
+/// use lua_patterns::template::{expand_with, Missing};
+/// let res = expand_with("$NAME: ${N}th", Missing::Keep, |name| match name {
+///     "N" => Some(42.to_string()),
+///     _ => None
+/// });
+/// assert_eq!(res.unwrap(), "$NAME: 42th");
+/// ```
+pub fn expand_with<F>(template: &str, missing: Missing, mut lookup: F) -> Result<String,MissingKey>
+where F: FnMut(&str) -> Option<String> {
+    let mut m = LuaPattern::new(PLACEHOLDER);
+    m.try_gsub_with(template, |cc| {
+        let (open, name, close) = (cc.get(1), cc.get(2), cc.get(3));
+        if open == "$" {
+            return Ok(format!("${}{}", name, close));
+        }
+        let valid = ! name.is_empty() && ! name.starts_with(|c: char| c.is_ascii_digit());
+        if ! valid || (open == "{" && close.is_empty()) {
+            return Ok(cc.get(0).to_string());
+        }
+        // the '}' only belongs to the placeholder if it was opened with '{'
+        let rest = if open == "{" {""} else {close};
+        let value = match lookup(name) {
+            Some(value) => value,
+            None => match missing {
+                Missing::Error => return Err(MissingKey(name.to_string())),
+                Missing::Keep => return Ok(cc.get(0).to_string()),
+                Missing::Empty => String::new()
+            }
+        };
+        Ok(value + rest)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholders() {
+        let mut vars = HashMap::new();
+        vars.insert("a".to_string(), "1".to_string());
+        vars.insert("b_2".to_string(), "2".to_string());
+        let ex = |t: &str| expand(t, &vars, Missing::Keep).unwrap();
+        assert_eq!(ex("$a$b_2 ${a}b $a}"), "12 1b 1}");
+        assert_eq!(ex("$ $1 ${a ${} $"), "$ $1 ${a ${} $");
+        assert_eq!(ex("$$a $$$a"), "$a $1");
+        assert_eq!(ex("${x} $x"), "${x} $x");
+        assert_eq!(expand("$x", &vars, Missing::Error), Err(MissingKey("x".to_string())));
+        let mut seen = Vec::new();
+        let res = expand_with("$one ${two}", Missing::Error, |name| {
+            seen.push(name.to_string());
+            Some(name.len().to_string())
+        });
+        assert_eq!(res.unwrap(), "3 3");
+        assert_eq!(seen, &["one", "two"]);
+    }
+}
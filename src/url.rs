@@ -0,0 +1,144 @@
+//! Splitting URLs and query strings, using Lua patterns throughout.
+//!
+//! This needs the `url` feature. It is meant for the common cases, like
+//! picking a URL out of a log line, and does not try to validate URLs or
+//! handle every corner of RFC 3986.
+//!
+//! ```
+//! use lua_patterns::url::{parse_url, query_params, decode};
+//!
+//! let url = parse_url("https://example.com:8080/search?q=lua+patterns&page=2#top").unwrap();
+//! assert_eq!(url.scheme, "https");
+//! assert_eq!(url.host, "example.com");
+//! assert_eq!(url.port, Some(8080));
+//! assert_eq!(url.path, "/search");
+//! assert_eq!(url.fragment, Some("top"));
+//! let params: Vec<_> = query_params(url.query.unwrap()).collect();
+//! assert_eq!(params, &[("q","lua+patterns"), ("page","2")]);
+//! assert_eq!(decode("lua+patterns%21"), "lua patterns!");
+//! ```
+
+use std::borrow::Cow;
+use LuaPattern;
+
+/// The parts of a URL like `scheme://user@host:port/path?query#fragment`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url<'t> {
+    pub scheme: &'t str,
+    pub user: Option<&'t str>,
+    /// the host name or address; IPv6 addresses keep their brackets
+    pub host: &'t str,
+    pub port: Option<u16>,
+    /// the path, which is empty or starts with '/'
+    pub path: &'t str,
+    /// the query, without the '?'
+    pub query: Option<&'t str>,
+    /// the fragment, without the '#'
+    pub fragment: Option<&'t str>,
+}
+
+// Some(s) if the separator before it was present
+fn present<'t>(sep: &str, s: &'t str) -> Option<&'t str> {
+    if sep.is_empty() {None} else {Some(s)}
+}
+
+/// Split a URL into its parts.
+///
+/// Returns `None` if there is no scheme followed by "://", or if the
+/// port is not a number.
+pub fn parse_url(text: &str) -> Option<Url<'_>> {
+    let (scheme, authority, rest) = LuaPattern::new("^(%a[%w%+%-%.]*)://([^/?#]*)(.*)$")
+        .match_maybe_3(text)?;
+    let (user, host_port) = match LuaPattern::new("^(.*)@(.*)$").match_maybe_2(authority) {
+        Some((user, host_port)) => (Some(user), host_port),
+        None => (None, authority)
+    };
+    // bracketed IPv6 addresses contain ':'
+    let (host, colon, port) = LuaPattern::new("^(%b[])(:?)(.*)$").match_maybe_3(host_port)
+        .or_else(|| LuaPattern::new("^([^:]*)(:?)(.*)$").match_maybe_3(host_port))?;
+    let port = match present(colon, port) {
+        Some(port) => Some(port.parse().ok()?),
+        None => None
+    };
+    let mut m = LuaPattern::new("^([^?#]*)(%??)([^#]*)(#?)(.*)$");
+    let cc = m.captures(rest);
+    Some(Url {
+        scheme, user, host, port,
+        path: cc[1],
+        query: present(cc[2], cc[3]),
+        fragment: present(cc[4], cc[5]),
+    })
+}
+
+/// Iterator over the parameters of a query string, from `query_params`
+pub struct QueryParams<'t> {
+    text: &'t str,
+    pos: usize,
+}
+
+impl <'t> Iterator for QueryParams<'t> {
+    type Item = (&'t str, &'t str);
+
+    fn next(&mut self) -> Option<(&'t str, &'t str)> {
+        let mut m = LuaPattern::new("([^&;=]+)=?([^&;]*)");
+        if ! m.matches_bytes_at(self.text.as_bytes(), self.pos, false) {
+            return None;
+        }
+        self.pos = m.range().end;
+        Some((&self.text[m.capture(1)], &self.text[m.capture(2)]))
+    }
+}
+
+/// Iterate over the `(name, value)` pairs of a query string like "a=1&b=2".
+///
+/// Pairs may be separated by '&' or ';'. A name without '=' has an empty
+/// value. The names and values are not decoded; use `decode` for that.
+pub fn query_params(query: &str) -> QueryParams<'_> {
+    QueryParams{text: query.strip_prefix('?').unwrap_or(query), pos: 0}
+}
+
+/// Decode a query string component, where '+' is a space and "%XX" is a
+/// hex-encoded byte. Invalid UTF-8 is replaced, as with `String::from_utf8_lossy`.
+pub fn decode(s: &str) -> Cow<'_, str> {
+    if ! s.contains(['+', '%']) {
+        return Cow::Borrowed(s);
+    }
+    let bytes = s.replace('+', " ");
+    let bytes = LuaPattern::new("%%(%x%x)").gsub_bytes_with(bytes.as_bytes(), |cc| {
+        let hex = ::std::str::from_utf8(cc.get(1)).unwrap();
+        vec![u8::from_str_radix(hex, 16).unwrap()]
+    });
+    Cow::Owned(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls() {
+        let url = parse_url("ftp://anon:pw@files.example.org/pub/x.tar.gz").unwrap();
+        assert_eq!(url, Url {
+            scheme: "ftp", user: Some("anon:pw"), host: "files.example.org", port: None,
+            path: "/pub/x.tar.gz", query: None, fragment: None,
+        });
+        let url = parse_url("http://[::1]:3000?").unwrap();
+        assert_eq!((url.host, url.port, url.path, url.query), ("[::1]", Some(3000), "", Some("")));
+        let url = parse_url("file:///etc/hosts#").unwrap();
+        assert_eq!((url.host, url.path, url.fragment), ("", "/etc/hosts", Some("")));
+        assert_eq!(parse_url("example.com/path"), None);
+        assert_eq!(parse_url("http://host:port/"), None);
+        assert_eq!(parse_url("http://host:99999/"), None);
+    }
+
+    #[test]
+    fn queries() {
+        let params: Vec<_> = query_params("?a=1&flag;b=&=x&c=%20+").collect();
+        assert_eq!(params, &[("a","1"), ("flag",""), ("b",""), ("x",""), ("c","%20+")]);
+        assert_eq!(query_params("").count(), 0);
+        assert_eq!(decode("%20+"), "  ");
+        assert_eq!(decode("caf%C3%A9%2"), "café%2");
+        assert_eq!(decode("%FF"), "\u{FFFD}");
+        assert!(matches!(decode("plain"), Cow::Borrowed(_)));
+    }
+}
@@ -0,0 +1,45 @@
+// #[derive(FromCaptures)] (feature "derive")
+#[macro_use]
+extern crate lua_patterns;
+use lua_patterns::LuaPattern;
+
+#[derive(FromCaptures, Debug, PartialEq)]
+struct LogLine<'a> {
+    level: &'a str,
+    code: u32,
+    #[capture(4)]
+    message: String,
+    #[capture(3)]
+    module: Option<&'a str>,
+}
+
+#[derive(FromCaptures, Debug, PartialEq)]
+struct Point(i32, i32);
+
+#[derive(FromCaptures, Debug, PartialEq)]
+struct Whole {
+    #[capture(0)]
+    all: String,
+}
+
+#[test]
+fn named_fields() {
+    let mut m = LuaPattern::new("^(%u+) (%d+) %[(%w*)%] (.*)");
+    let line: LogLine = m.extract("WARN 17 [net] connection reset").unwrap();
+    assert_eq!(line, LogLine{level: "WARN", code: 17, message: "connection reset".into(), module: Some("net")});
+    let line: LogLine = m.extract("INFO 2 [] started").unwrap();
+    assert_eq!(line.module, None);
+    // conversion failures and non-matches give None
+    assert_eq!(m.extract::<LogLine>("INFO 99999999999 [] big"), None);
+    assert_eq!(m.extract::<LogLine>("nothing"), None);
+}
+
+#[test]
+fn tuple_and_whole_match() {
+    let mut m = LuaPattern::new("%((%-?%d+),%s*(%-?%d+)%)");
+    assert_eq!(m.extract("at (10, -3)"), Some(Point(10, -3)));
+    // too few captures for the fields
+    let mut m = LuaPattern::new("(%d+)");
+    assert_eq!(m.extract::<Point>("10"), None);
+    assert_eq!(m.extract("x 42 y"), Some(Whole{all: "42".into()}));
+}
@@ -0,0 +1,48 @@
+// Runs the lpgrep binary (feature "cli") over small inputs.
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+const TEXT: &str = "name=alice\nage=42\n# comment\nname=bob\n";
+
+fn lpgrep(args: &[&str], input: &str) -> (i32, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lpgrep"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("cannot run lpgrep");
+    // lpgrep may exit on a bad argument before reading its input
+    let _ = child.stdin.take().unwrap().write_all(input.as_bytes());
+    let out = child.wait_with_output().unwrap();
+    (out.status.code().unwrap(), String::from_utf8(out.stdout).unwrap())
+}
+
+#[test]
+fn matching_lines() {
+    assert_eq!(lpgrep(&["^name="], TEXT), (0, "name=alice\nname=bob\n".into()));
+    assert_eq!(lpgrep(&["-n", "%d+$"], TEXT), (0, "2:age=42\n".into()));
+    assert_eq!(lpgrep(&["-v", "="], TEXT), (0, "# comment\n".into()));
+    assert_eq!(lpgrep(&["xyz"], TEXT), (1, "".into()));
+}
+
+#[test]
+fn only_matching() {
+    assert_eq!(lpgrep(&["-o", "%a+=(%a+)"], TEXT), (0, "alice\nbob\n".into()));
+    assert_eq!(lpgrep(&["-on", "[aeiou]"], "xyz\nbee\n"), (0, "2:e\n2:e\n".into()));
+}
+
+#[test]
+fn replace() {
+    assert_eq!(lpgrep(&["--replace", "%2: %1", "(%a+)=(%w+)"], TEXT),
+        (0, "alice: name\n42: age\nbob: name\n".into()));
+}
+
+#[test]
+fn errors() {
+    assert_eq!(lpgrep(&["(%a"], TEXT).0, 2);
+    assert_eq!(lpgrep(&["-x", "a"], TEXT).0, 2);
+    assert_eq!(lpgrep(&["-o", "--replace", "x", "a"], TEXT).0, 2);
+    assert_eq!(lpgrep(&["--replace", "%3", "(%a+)=(%w+)"], TEXT).0, 2);
+    assert_eq!(lpgrep(&[], TEXT).0, 2);
+}
@@ -0,0 +1,53 @@
+// lua_pat! (feature "macros")
+#[macro_use]
+extern crate lua_patterns;
+use lua_patterns::LuaPatternOwned;
+
+fn range_pattern() -> &'static LuaPatternOwned {
+    lua_pat!("(%d+)-(%d+)")
+}
+
+#[test]
+fn checked_patterns() {
+    let mut m = range_pattern().pattern();
+    assert_eq!(m.captures("pages 10-20"), &["10-20","10","20"]);
+    assert_eq!(lua_pat!("^%a+$").as_str(), Some("^%a+$"));
+}
+
+#[test]
+fn created_once() {
+    let first = range_pattern() as *const LuaPatternOwned;
+    let second = range_pattern() as *const LuaPatternOwned;
+    assert_eq!(first, second);
+}
+
+// lua_pat! checks patterns with its own copy of `str_check`, since the
+// macro crate cannot depend on this one; both must agree on every pattern
+#[path = "../lua-patterns-derive/src/check.rs"]
+mod derive_check;
+
+#[test]
+fn same_checks_as_new_try() {
+    let mut corpus: Vec<Vec<u8>> = [
+        "", "^", "%", "a%", "%a+", "^(%d+)-(%d+)%1", "[]", "[]]", "[^]]", "[%]", "[%]]", "[a-z",
+        "%b", "%b(", "%b()", "^%b()", "%f", "%fx", "%f[%w]", "%f[", "(", ")", "()", "(()", "(a)%1",
+        "(a%1)", "%0", "%2", "(a)(b)%2", "^(a)%2", "a)(", "%[", "[%a%[", "[^", "^[^%]",
+    ].iter().map(|p| p.as_bytes().to_vec()).collect();
+    corpus.push("(%a)".repeat(32).into_bytes());
+    corpus.push("(%a)".repeat(33).into_bytes());
+    corpus.push("()".repeat(33).into_bytes());
+    // and random patterns made from the characters which matter to the checker
+    let mut seed: u32 = 597;
+    let mut random = move |n: usize| {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        (seed >> 16) as usize % n
+    };
+    let patt_bytes = b"%()[]^$bf1209a";
+    for _ in 0..20000 {
+        corpus.push((0..random(10)).map(|_| patt_bytes[random(patt_bytes.len())]).collect());
+    }
+    for patt in &corpus {
+        let expected = lua_patterns::LuaPattern::from_bytes_try(patt).map(|_| ()).map_err(|e| e.to_string());
+        assert_eq!(derive_check::str_check(patt), expected, "{:?}", String::from_utf8_lossy(patt));
+    }
+}